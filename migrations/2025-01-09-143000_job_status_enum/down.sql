@@ -0,0 +1,5 @@
+ALTER TABLE job_opportunities
+    ALTER COLUMN status DROP DEFAULT,
+    ALTER COLUMN status TYPE VARCHAR USING status::text;
+
+DROP TYPE IF EXISTS job_status;
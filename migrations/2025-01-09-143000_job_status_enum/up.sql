@@ -0,0 +1,10 @@
+CREATE TYPE job_status AS ENUM ('OPEN', 'PENDING', 'COMPLETED', 'CANCELLED');
+
+ALTER TABLE job_opportunities
+    ALTER COLUMN status TYPE job_status USING (
+        CASE
+            WHEN upper(status) IN ('OPEN', 'PENDING', 'COMPLETED', 'CANCELLED') THEN upper(status)
+            ELSE 'OPEN'
+        END
+    )::job_status,
+    ALTER COLUMN status SET DEFAULT 'OPEN';
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS job_opportunities_latitude_idx;
+DROP FUNCTION IF EXISTS great_circle_km(DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION);
@@ -0,0 +1,14 @@
+-- Haversine distance in kilometres between two points given in degrees.
+CREATE OR REPLACE FUNCTION great_circle_km(
+    lat1 DOUBLE PRECISION,
+    lng1 DOUBLE PRECISION,
+    lat2 DOUBLE PRECISION,
+    lng2 DOUBLE PRECISION
+) RETURNS DOUBLE PRECISION AS $$
+    SELECT 2 * 6371.0 * asin(least(1.0, sqrt(
+        power(sin(radians(lat2 - lat1) / 2), 2)
+        + cos(radians(lat1)) * cos(radians(lat2)) * power(sin(radians(lng2 - lng1) / 2), 2)
+    )))
+$$ LANGUAGE sql IMMUTABLE STRICT;
+
+CREATE INDEX job_opportunities_latitude_idx ON job_opportunities (latitude);
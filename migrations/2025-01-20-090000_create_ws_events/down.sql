@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS ws_event_recipients;
+DROP TABLE IF EXISTS ws_events;
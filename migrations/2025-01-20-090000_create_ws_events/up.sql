@@ -0,0 +1,15 @@
+CREATE TABLE ws_events (
+    seq BIGSERIAL PRIMARY KEY,
+    body TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX ws_events_created_at_idx ON ws_events (created_at);
+
+CREATE TABLE ws_event_recipients (
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    event_seq BIGINT NOT NULL REFERENCES ws_events(seq) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, event_seq)
+);
+
+CREATE INDEX ws_event_recipients_event_seq_idx ON ws_event_recipients (event_seq);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS company_invitations;
+DROP TABLE IF EXISTS company_members;
@@ -0,0 +1,27 @@
+CREATE TABLE company_members (
+    id BIGSERIAL PRIMARY KEY,
+    company_id BIGINT NOT NULL REFERENCES companies(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL UNIQUE REFERENCES users(id) ON DELETE CASCADE,
+    role VARCHAR NOT NULL DEFAULT 'MEMBER',
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX company_members_company_id_idx ON company_members (company_id);
+
+-- Whoever created a company so far owns it.
+INSERT INTO company_members (company_id, user_id, role)
+SELECT companyid, id, 'ADMIN' FROM users WHERE companyid IS NOT NULL;
+
+CREATE TABLE company_invitations (
+    id BIGSERIAL PRIMARY KEY,
+    company_id BIGINT NOT NULL REFERENCES companies(id) ON DELETE CASCADE,
+    role VARCHAR NOT NULL DEFAULT 'MEMBER',
+    -- When set, only this login may accept the invitation.
+    invited_login VARCHAR,
+    token_hash VARCHAR NOT NULL UNIQUE,
+    invited_by BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMP NOT NULL,
+    accepted_by BIGINT REFERENCES users(id) ON DELETE SET NULL,
+    accepted_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,5 @@
+DROP TABLE account_tokens;
+
+DROP INDEX users_email_key;
+ALTER TABLE users DROP COLUMN email_verified_at;
+ALTER TABLE users DROP COLUMN email;
@@ -0,0 +1,20 @@
+-- Failed login counters, one row per login and per client IP.
+CREATE TABLE login_throttles (
+    key VARCHAR PRIMARY KEY,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failure_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    blocked_until TIMESTAMP
+);
+
+-- Audit trail of rejected logins.
+CREATE TABLE failed_logins (
+    id BIGSERIAL PRIMARY KEY,
+    login VARCHAR NOT NULL,
+    ip VARCHAR NOT NULL,
+    user_id BIGINT REFERENCES users(id) ON DELETE SET NULL,
+    reason VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX failed_logins_login_idx ON failed_logins (login, created_at);
+CREATE INDEX failed_logins_ip_idx ON failed_logins (ip, created_at);
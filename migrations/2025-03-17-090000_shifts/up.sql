@@ -0,0 +1,19 @@
+-- When a worker accepted on a job actually showed up and left.
+CREATE TABLE shifts (
+    id BIGSERIAL PRIMARY KEY,
+    job_id BIGINT NOT NULL REFERENCES job_opportunities(id) ON DELETE CASCADE,
+    employee_id BIGINT NOT NULL REFERENCES employees(id) ON DELETE CASCADE,
+    check_in_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    check_in_latitude DOUBLE PRECISION NOT NULL,
+    check_in_longitude DOUBLE PRECISION NOT NULL,
+    -- How far from the job the worker said they were, in kilometres.
+    check_in_distance_km DOUBLE PRECISION NOT NULL,
+    check_out_at TIMESTAMP,
+    check_out_latitude DOUBLE PRECISION,
+    check_out_longitude DOUBLE PRECISION,
+    check_out_distance_km DOUBLE PRECISION,
+    worked_hours DOUBLE PRECISION,
+    UNIQUE (job_id, employee_id)
+);
+
+CREATE INDEX shifts_employee_id_idx ON shifts (employee_id);
@@ -0,0 +1,2 @@
+DROP TABLE timesheet_events;
+DROP TABLE timesheets;
@@ -0,0 +1,41 @@
+-- Hours a worker claims for a shift, and what the company made of them.
+CREATE TABLE timesheets (
+    id BIGSERIAL PRIMARY KEY,
+    shift_id BIGINT NOT NULL UNIQUE REFERENCES shifts(id) ON DELETE CASCADE,
+    job_id BIGINT NOT NULL REFERENCES job_opportunities(id) ON DELETE CASCADE,
+    employee_id BIGINT NOT NULL REFERENCES employees(id) ON DELETE CASCADE,
+    status VARCHAR NOT NULL,
+    submitted_hours DOUBLE PRECISION NOT NULL,
+    break_minutes INTEGER NOT NULL DEFAULT 0,
+    -- Submitted hours less breaks: what gets paid once approved.
+    net_hours DOUBLE PRECISION NOT NULL,
+    -- The job's rate when submitted, so later edits do not change old pay.
+    pay_rate DOUBLE PRECISION NOT NULL,
+    note TEXT,
+    dispute_reason TEXT,
+    approved_hours DOUBLE PRECISION,
+    payable_amount DOUBLE PRECISION,
+    submitted_at TIMESTAMP NOT NULL,
+    decided_at TIMESTAMP,
+    decided_by BIGINT REFERENCES users(id) ON DELETE SET NULL,
+    auto_approved BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX timesheets_job_id_idx ON timesheets (job_id);
+CREATE INDEX timesheets_employee_id_idx ON timesheets (employee_id);
+CREATE INDEX timesheets_pending_idx ON timesheets (submitted_at) WHERE status = 'SUBMITTED';
+
+-- Everything that happened to a timesheet, for audits and disputes.
+CREATE TABLE timesheet_events (
+    id BIGSERIAL PRIMARY KEY,
+    timesheet_id BIGINT NOT NULL REFERENCES timesheets(id) ON DELETE CASCADE,
+    action VARCHAR NOT NULL,
+    -- NULL when done by the system or through an API key.
+    actor_user_id BIGINT REFERENCES users(id) ON DELETE SET NULL,
+    net_hours DOUBLE PRECISION NOT NULL,
+    note TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX timesheet_events_timesheet_id_idx ON timesheet_events (timesheet_id);
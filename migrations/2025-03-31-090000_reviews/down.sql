@@ -0,0 +1,3 @@
+ALTER TABLE companies DROP COLUMN review_count, DROP COLUMN rating;
+ALTER TABLE employees DROP COLUMN review_count;
+DROP TABLE reviews;
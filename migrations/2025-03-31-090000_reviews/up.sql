@@ -0,0 +1,23 @@
+-- What a company and a worker thought of each other after a job.
+CREATE TABLE reviews (
+    id BIGSERIAL PRIMARY KEY,
+    job_id BIGINT NOT NULL REFERENCES job_opportunities(id) ON DELETE CASCADE,
+    employee_id BIGINT NOT NULL REFERENCES employees(id) ON DELETE CASCADE,
+    company_id BIGINT NOT NULL REFERENCES companies(id) ON DELETE CASCADE,
+    -- COMPANY_TO_EMPLOYEE or EMPLOYEE_TO_COMPANY.
+    direction VARCHAR NOT NULL,
+    author_user_id BIGINT REFERENCES users(id) ON DELETE SET NULL,
+    rating INTEGER NOT NULL CHECK (rating BETWEEN 1 AND 5),
+    comment TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (job_id, employee_id, direction)
+);
+
+CREATE INDEX reviews_employee_id_idx ON reviews (employee_id, direction);
+CREATE INDEX reviews_company_id_idx ON reviews (company_id, direction);
+
+-- Ratings are now worked out from reviews.
+ALTER TABLE employees ADD COLUMN review_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE companies
+    ADD COLUMN rating DOUBLE PRECISION NOT NULL DEFAULT 0,
+    ADD COLUMN review_count INTEGER NOT NULL DEFAULT 0;
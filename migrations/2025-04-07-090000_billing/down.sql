@@ -0,0 +1,6 @@
+DROP TABLE invoice_lines;
+DROP TABLE ledger_entries;
+DROP TABLE ledger_transactions;
+DROP TABLE payouts;
+DROP TABLE payout_batches;
+DROP TABLE invoices;
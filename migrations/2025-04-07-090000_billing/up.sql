@@ -0,0 +1,96 @@
+-- Money is kept in cents so ledger entries always balance exactly.
+
+-- What each company was billed for a period.
+CREATE TABLE invoices (
+    id BIGSERIAL PRIMARY KEY,
+    company_id BIGINT NOT NULL REFERENCES companies(id) ON DELETE CASCADE,
+    period_start TIMESTAMP NOT NULL,
+    period_end TIMESTAMP NOT NULL,
+    subtotal_cents BIGINT NOT NULL,
+    fee_cents BIGINT NOT NULL,
+    total_cents BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX invoices_company_id_idx ON invoices (company_id);
+
+-- One run of the payout cycle.
+CREATE TABLE payout_batches (
+    id BIGSERIAL PRIMARY KEY,
+    status VARCHAR NOT NULL,
+    payout_count INTEGER NOT NULL DEFAULT 0,
+    total_cents BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMP
+);
+
+-- What one employee is paid in a batch.
+CREATE TABLE payouts (
+    id BIGSERIAL PRIMARY KEY,
+    batch_id BIGINT NOT NULL REFERENCES payout_batches(id) ON DELETE CASCADE,
+    employee_id BIGINT NOT NULL REFERENCES employees(id) ON DELETE CASCADE,
+    status VARCHAR NOT NULL,
+    amount_cents BIGINT NOT NULL CHECK (amount_cents > 0),
+    provider VARCHAR NOT NULL,
+    provider_reference VARCHAR,
+    failure_reason TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    paid_at TIMESTAMP
+);
+
+CREATE INDEX payouts_batch_id_idx ON payouts (batch_id);
+CREATE INDEX payouts_employee_id_idx ON payouts (employee_id);
+
+-- A balanced set of ledger entries: the cost of an approved timesheet, or a
+-- payout to an employee.
+CREATE TABLE ledger_transactions (
+    id BIGSERIAL PRIMARY KEY,
+    kind VARCHAR NOT NULL,
+    company_id BIGINT REFERENCES companies(id) ON DELETE SET NULL,
+    employee_id BIGINT REFERENCES employees(id) ON DELETE SET NULL,
+    job_id BIGINT REFERENCES job_opportunities(id) ON DELETE SET NULL,
+    -- A timesheet is only ever charged once.
+    timesheet_id BIGINT UNIQUE REFERENCES timesheets(id) ON DELETE SET NULL,
+    description TEXT NOT NULL,
+    -- Set once the company was invoiced for it.
+    invoice_id BIGINT REFERENCES invoices(id) ON DELETE SET NULL,
+    -- Set while a payout covers its earnings; cleared again if it fails.
+    payout_id BIGINT REFERENCES payouts(id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX ledger_transactions_uninvoiced_idx ON ledger_transactions (company_id, created_at)
+    WHERE kind = 'JOB_COST' AND invoice_id IS NULL;
+CREATE INDEX ledger_transactions_unpaid_idx ON ledger_transactions (employee_id, created_at)
+    WHERE kind = 'JOB_COST' AND payout_id IS NULL;
+
+CREATE TABLE ledger_entries (
+    id BIGSERIAL PRIMARY KEY,
+    transaction_id BIGINT NOT NULL REFERENCES ledger_transactions(id) ON DELETE CASCADE,
+    -- COMPANY_PAYABLE, EMPLOYEE_EARNINGS, PLATFORM_FEES or PLATFORM_CASH.
+    account VARCHAR NOT NULL,
+    company_id BIGINT REFERENCES companies(id) ON DELETE SET NULL,
+    employee_id BIGINT REFERENCES employees(id) ON DELETE SET NULL,
+    -- DEBIT or CREDIT.
+    side VARCHAR NOT NULL,
+    amount_cents BIGINT NOT NULL CHECK (amount_cents > 0),
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX ledger_entries_transaction_id_idx ON ledger_entries (transaction_id);
+CREATE INDEX ledger_entries_account_idx ON ledger_entries (account, company_id, employee_id);
+
+-- Invoices are documents: their lines are copied, not looked up.
+CREATE TABLE invoice_lines (
+    id BIGSERIAL PRIMARY KEY,
+    invoice_id BIGINT NOT NULL REFERENCES invoices(id) ON DELETE CASCADE,
+    ledger_transaction_id BIGINT NOT NULL REFERENCES ledger_transactions(id) ON DELETE CASCADE,
+    job_id BIGINT,
+    employee_id BIGINT,
+    description TEXT NOT NULL,
+    hours DOUBLE PRECISION NOT NULL,
+    amount_cents BIGINT NOT NULL,
+    fee_cents BIGINT NOT NULL
+);
+
+CREATE INDEX invoice_lines_invoice_id_idx ON invoice_lines (invoice_id);
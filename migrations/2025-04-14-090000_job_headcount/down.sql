@@ -0,0 +1,4 @@
+ALTER TABLE job_opportunities
+    DROP COLUMN remaining_slots,
+    DROP COLUMN assigned_slots,
+    DROP COLUMN headcount;
@@ -0,0 +1,28 @@
+-- Jobs may need more than one worker. A job stays OPEN until every slot is
+-- taken, then moves to PENDING; a worker dropping out opens it again.
+ALTER TABLE job_opportunities
+    ADD COLUMN headcount INTEGER NOT NULL DEFAULT 1,
+    ADD COLUMN assigned_slots INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN remaining_slots INTEGER GENERATED ALWAYS AS (headcount - assigned_slots) STORED;
+
+UPDATE job_opportunities j
+SET assigned_slots = accepted.count
+FROM (
+    SELECT job_id, COUNT(*)::INTEGER AS count FROM job_applications
+    WHERE status = 'ACCEPTED'
+    GROUP BY job_id
+) accepted
+WHERE accepted.job_id = j.id;
+
+-- Older jobs could end up with more than one accepted worker; those keep
+-- every worker they already have.
+UPDATE job_opportunities SET headcount = assigned_slots
+WHERE assigned_slots > headcount;
+
+ALTER TABLE job_opportunities
+    ADD CONSTRAINT job_opportunities_headcount_check CHECK (headcount > 0),
+    ADD CONSTRAINT job_opportunities_assigned_slots_check
+        CHECK (assigned_slots BETWEEN 0 AND headcount);
+
+UPDATE job_opportunities SET status = 'PENDING'
+WHERE status = 'OPEN' AND remaining_slots = 0;
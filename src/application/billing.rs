@@ -0,0 +1,456 @@
+use chrono::{Duration, Utc};
+use diesel_async::{pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection};
+use serde::Serialize;
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    application::service::Service,
+    domain::{
+        enums::{LedgerAccount, LedgerSide, LedgerTransactionKind, PayoutStatus},
+        models::{
+            Invoice, InvoiceWithLines, LedgerTransaction, NewInvoice, NewInvoiceLine,
+            NewLedgerEntry, NewLedgerTransaction, Payout, PayoutOutcome, Timesheet,
+        },
+    },
+    infrastructure::{
+        payments::{PaymentError, Payments, PayoutInstruction},
+        repositories::Repository,
+    },
+};
+
+const DEFAULT_FEE_PERCENT: f64 = 10.0;
+const DEFAULT_PERIOD_DAYS: i64 = 7;
+const BILLING_CYCLE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// How companies are charged and employees paid.
+#[derive(Clone, Debug)]
+pub struct BillingConfig {
+    /// Charged to companies on top of what their workers earn.
+    pub fee_percent: f64,
+    /// How much work is gathered in an invoice or payout before issuing it.
+    pub period: Duration,
+}
+
+impl BillingConfig {
+    /// Reads `PLATFORM_FEE_PERCENT` (default 10, from 0 to 100) and
+    /// `BILLING_PERIOD_DAYS` (default 7).
+    pub fn from_env() -> Self {
+        let fee_percent = std::env::var("PLATFORM_FEE_PERCENT")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|percent| (0.0..=100.0).contains(percent))
+            .unwrap_or(DEFAULT_FEE_PERCENT);
+        let period_days = std::env::var("BILLING_PERIOD_DAYS")
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .filter(|days| *days > 0)
+            .unwrap_or(DEFAULT_PERIOD_DAYS);
+
+        Self {
+            fee_percent,
+            period: Duration::days(period_days),
+        }
+    }
+
+    /// The platform fee on `amount_cents` of work, rounded to the cent.
+    pub fn fee_cents(&self, amount_cents: i64) -> i64 {
+        (amount_cents as f64 * self.fee_percent / 100.0).round() as i64
+    }
+
+    /// Periodically invoice companies and pay employees for work older than
+    /// the billing period.
+    pub fn spawn_billing_cycle(
+        &self,
+        pool: bb8::Pool<AsyncDieselConnectionManager<AsyncPgConnection>>,
+        payments: Payments,
+    ) {
+        let period = self.period;
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(BILLING_CYCLE_INTERVAL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let cutoff = chrono::Utc::now().naive_utc() - period;
+                let mut conn = match pool.get().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        tracing::error!("Unable to run the billing cycle: {e}");
+                        continue;
+                    }
+                };
+                match Service::run_billing_cycle(&mut conn, &payments, cutoff).await {
+                    Ok(run) if run.invoices.is_empty() && run.payout_batch.is_none() => {}
+                    Ok(run) => tracing::info!(
+                        "Issued {} invoices and {} payouts for work before {cutoff}",
+                        run.invoices.len(),
+                        run.payout_batch.map_or(0, |batch| batch.payout_count)
+                    ),
+                    Err(e) => tracing::error!("Unable to run the billing cycle: {e}"),
+                }
+            }
+        });
+    }
+}
+
+/// Converts an amount such as a timesheet's `payable_amount` to cents.
+pub fn to_cents(amount: f64) -> i64 {
+    (amount * 100.0).round() as i64
+}
+
+/// Cents as a decimal amount, `1234` as `12.34`.
+fn format_cents(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{sign}{}.{:02}", cents.abs() / 100, cents.abs() % 100)
+}
+
+#[derive(Serialize)]
+struct InvoiceCsvRow<'a> {
+    invoice_id: i64,
+    line_id: i64,
+    job_id: Option<i64>,
+    employee_id: Option<i64>,
+    description: &'a str,
+    hours: f64,
+    amount: String,
+    fee: String,
+    total: String,
+}
+
+#[derive(Serialize)]
+struct PayoutCsvRow<'a> {
+    batch_id: i64,
+    payout_id: i64,
+    employee_id: i64,
+    status: &'a str,
+    amount: String,
+    provider: &'a str,
+    provider_reference: Option<&'a str>,
+    failure_reason: Option<&'a str>,
+    paid_at: Option<String>,
+}
+
+/// One row per line item of the invoice.
+pub fn invoice_csv(invoice: &InvoiceWithLines) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for line in &invoice.lines {
+        writer.serialize(InvoiceCsvRow {
+            invoice_id: invoice.invoice.id,
+            line_id: line.id,
+            job_id: line.job_id,
+            employee_id: line.employee_id,
+            description: &line.description,
+            hours: line.hours,
+            amount: format_cents(line.amount_cents),
+            fee: format_cents(line.fee_cents),
+            total: format_cents(line.amount_cents + line.fee_cents),
+        })?;
+    }
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+/// One row per payout of a batch.
+pub fn payouts_csv(payouts: &[Payout]) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for payout in payouts {
+        writer.serialize(PayoutCsvRow {
+            batch_id: payout.batch_id,
+            payout_id: payout.id,
+            employee_id: payout.employee_id,
+            status: &payout.status,
+            amount: format_cents(payout.amount_cents),
+            provider: &payout.provider,
+            provider_reference: payout.provider_reference.as_deref(),
+            failure_reason: payout.failure_reason.as_deref(),
+            paid_at: payout.paid_at.map(|at| at.to_string()),
+        })?;
+    }
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+/// Charges the company for an approved timesheet: its payable amount is
+/// owed to the employee, and the platform fee on top to the platform. Does
+/// nothing for timesheets already charged or worth nothing.
+pub(crate) async fn post_timesheet_cost(
+    conn: &mut AsyncPgConnection,
+    billing: &BillingConfig,
+    timesheet: &Timesheet,
+    company_id: i64,
+) -> Result<(), diesel::result::Error> {
+    let amount_cents = to_cents(timesheet.payable_amount.unwrap_or(0.0));
+    if amount_cents <= 0 {
+        return Ok(());
+    }
+    let fee_cents = billing.fee_cents(amount_cents);
+
+    let Some(transaction) = Repository::save_ledger_transaction(
+        conn,
+        &NewLedgerTransaction {
+            kind: LedgerTransactionKind::JobCost.as_str().to_string(),
+            company_id: Some(company_id),
+            employee_id: Some(timesheet.employee_id),
+            job_id: Some(timesheet.job_id),
+            timesheet_id: Some(timesheet.id),
+            description: format!(
+                "Timesheet #{} for job #{}: {} h at {:.2}",
+                timesheet.id,
+                timesheet.job_id,
+                timesheet.approved_hours.unwrap_or(0.0),
+                timesheet.pay_rate
+            ),
+        },
+    )
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let mut entries = vec![
+        ledger_entry(
+            &transaction,
+            LedgerAccount::CompanyPayable,
+            LedgerSide::Debit,
+            amount_cents + fee_cents,
+        ),
+        ledger_entry(
+            &transaction,
+            LedgerAccount::EmployeeEarnings,
+            LedgerSide::Credit,
+            amount_cents,
+        ),
+    ];
+    if fee_cents > 0 {
+        entries.push(ledger_entry(
+            &transaction,
+            LedgerAccount::PlatformFees,
+            LedgerSide::Credit,
+            fee_cents,
+        ));
+    }
+    Repository::save_ledger_entries(conn, &entries).await?;
+    Ok(())
+}
+
+/// An entry of `transaction`, tagged with its company or employee when the
+/// account is theirs.
+fn ledger_entry(
+    transaction: &LedgerTransaction,
+    account: LedgerAccount,
+    side: LedgerSide,
+    amount_cents: i64,
+) -> NewLedgerEntry {
+    NewLedgerEntry {
+        transaction_id: transaction.id,
+        account: account.as_str().to_string(),
+        company_id: match account {
+            LedgerAccount::CompanyPayable => transaction.company_id,
+            _ => None,
+        },
+        employee_id: match account {
+            LedgerAccount::EmployeeEarnings => transaction.employee_id,
+            _ => None,
+        },
+        side: side.as_str().to_string(),
+        amount_cents,
+    }
+}
+
+/// Bills the company for its job costs not invoiced yet, `None` when there
+/// are none left.
+pub(crate) async fn issue_invoice(
+    conn: &mut AsyncPgConnection,
+    company_id: i64,
+) -> Result<Option<Invoice>, diesel::result::Error> {
+    let costs = Repository::find_uninvoiced_costs_for_update(conn, &company_id).await?;
+    if costs.is_empty() {
+        return Ok(None);
+    }
+    let cost_ids: Vec<i64> = costs.iter().map(|cost| cost.id).collect();
+    let entries = Repository::find_ledger_entries_for_transactions(conn, &cost_ids).await?;
+    let timesheet_ids: Vec<i64> = costs.iter().filter_map(|cost| cost.timesheet_id).collect();
+    let hours = Repository::find_timesheet_hours(conn, &timesheet_ids).await?;
+
+    let credited = |transaction_id: i64, account: LedgerAccount| -> i64 {
+        entries
+            .iter()
+            .filter(|entry| {
+                entry.transaction_id == transaction_id
+                    && entry.account == account.as_str()
+                    && entry.side == LedgerSide::Credit.as_str()
+            })
+            .map(|entry| entry.amount_cents)
+            .sum()
+    };
+    let period_end = Utc::now().naive_utc();
+    let lines: Vec<NewInvoiceLine> = costs
+        .iter()
+        .map(|cost| NewInvoiceLine {
+            invoice_id: 0,
+            ledger_transaction_id: cost.id,
+            job_id: cost.job_id,
+            employee_id: cost.employee_id,
+            description: cost.description.clone(),
+            hours: hours
+                .iter()
+                .find(|(id, _)| Some(*id) == cost.timesheet_id)
+                .and_then(|(_, hours)| *hours)
+                .unwrap_or(0.0),
+            amount_cents: credited(cost.id, LedgerAccount::EmployeeEarnings),
+            fee_cents: credited(cost.id, LedgerAccount::PlatformFees),
+        })
+        .collect();
+    let subtotal_cents: i64 = lines.iter().map(|line| line.amount_cents).sum();
+    let fee_cents: i64 = lines.iter().map(|line| line.fee_cents).sum();
+
+    let invoice = Repository::save_invoice(
+        conn,
+        &NewInvoice {
+            company_id,
+            period_start: costs[0].created_at,
+            period_end,
+            subtotal_cents,
+            fee_cents,
+            total_cents: subtotal_cents + fee_cents,
+        },
+    )
+    .await?;
+    let lines: Vec<NewInvoiceLine> = lines
+        .into_iter()
+        .map(|line| NewInvoiceLine {
+            invoice_id: invoice.id,
+            ..line
+        })
+        .collect();
+    Repository::save_invoice_lines(conn, &lines).await?;
+    Repository::set_transactions_invoice(conn, &cost_ids, &invoice.id).await?;
+    Ok(Some(invoice))
+}
+
+/// What to ask the provider for to pay `payout`. The reference is the same
+/// on every attempt, so sending a payout again cannot pay it twice.
+pub(crate) fn payout_instruction(payout: &Payout) -> PayoutInstruction {
+    PayoutInstruction {
+        reference: format!("payout-{}", payout.id),
+        employee_id: payout.employee_id,
+        amount_cents: payout.amount_cents,
+    }
+}
+
+/// Marks the payout paid and moves the money out of the employee's earnings,
+/// or marks it failed and frees its earnings for the next batch. Does
+/// nothing for a payout whose outcome was already saved.
+pub(crate) async fn record_payout_outcome(
+    conn: &mut AsyncPgConnection,
+    payout_id: i64,
+    sent: Result<String, PaymentError>,
+) -> Result<(), diesel::result::Error> {
+    let Some(payout) = Repository::find_pending_payout_for_update(conn, &payout_id).await? else {
+        return Ok(());
+    };
+    match sent {
+        Ok(reference) => {
+            Repository::update_payout(
+                conn,
+                &payout.id,
+                &PayoutOutcome {
+                    status: PayoutStatus::PAID.as_str().to_string(),
+                    provider_reference: Some(reference),
+                    failure_reason: None,
+                    paid_at: Some(Utc::now().naive_utc()),
+                },
+            )
+            .await?;
+            let transaction = Repository::save_ledger_transaction(
+                conn,
+                &NewLedgerTransaction {
+                    kind: LedgerTransactionKind::Payout.as_str().to_string(),
+                    company_id: None,
+                    employee_id: Some(payout.employee_id),
+                    job_id: None,
+                    timesheet_id: None,
+                    description: format!("Payout #{} via {}", payout.id, payout.provider),
+                },
+            )
+            .await?
+            .ok_or(diesel::result::Error::NotFound)?;
+            Repository::save_ledger_entries(
+                conn,
+                &[
+                    ledger_entry(
+                        &transaction,
+                        LedgerAccount::EmployeeEarnings,
+                        LedgerSide::Debit,
+                        payout.amount_cents,
+                    ),
+                    ledger_entry(
+                        &transaction,
+                        LedgerAccount::PlatformCash,
+                        LedgerSide::Credit,
+                        payout.amount_cents,
+                    ),
+                ],
+            )
+            .await?;
+        }
+        Err(e) => {
+            tracing::warn!("Payout #{} failed: {e}", payout.id);
+            Repository::update_payout(
+                conn,
+                &payout.id,
+                &PayoutOutcome {
+                    status: PayoutStatus::FAILED.as_str().to_string(),
+                    provider_reference: None,
+                    failure_reason: Some(e.to_string()),
+                    paid_at: None,
+                },
+            )
+            .await?;
+            Repository::release_payout_transactions(conn, &payout.id).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(fee_percent: f64) -> BillingConfig {
+        BillingConfig {
+            fee_percent,
+            period: Duration::days(DEFAULT_PERIOD_DAYS),
+        }
+    }
+
+    #[test]
+    fn amounts_round_to_the_nearest_cent() {
+        assert_eq!(to_cents(12.34), 1234);
+        assert_eq!(to_cents(0.1 + 0.2), 30);
+        assert_eq!(to_cents(19.999), 2000);
+        assert_eq!(to_cents(0.0), 0);
+        assert_eq!(to_cents(-5.5), -550);
+    }
+
+    #[test]
+    fn fees_round_half_away_from_zero() {
+        let billing = config(10.0);
+        assert_eq!(billing.fee_cents(1234), 123);
+        assert_eq!(billing.fee_cents(1235), 124);
+        assert_eq!(billing.fee_cents(5), 1);
+        assert_eq!(billing.fee_cents(4), 0);
+
+        assert_eq!(config(12.5).fee_cents(1000), 125);
+        assert_eq!(config(0.0).fee_cents(1234), 0);
+        assert_eq!(config(100.0).fee_cents(1234), 1234);
+    }
+
+    #[test]
+    fn cents_format_as_decimal_amounts() {
+        assert_eq!(format_cents(1234), "12.34");
+        assert_eq!(format_cents(5), "0.05");
+        assert_eq!(format_cents(0), "0.00");
+        assert_eq!(format_cents(100_000), "1000.00");
+        assert_eq!(format_cents(-1234), "-12.34");
+        assert_eq!(format_cents(-5), "-0.05");
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{
+    domain::models::{JobApplication, JobOpportunity},
+    infrastructure::repositories::Repository,
+    websocket::{protocol::ServerEvent, websocket::WebSocketManager},
+    Pool,
+};
+
+const DEFAULT_DISPATCH_RADIUS_KM: f64 = 10.0;
+
+/// Pushes job and application events to the users they concern.
+#[derive(Clone)]
+pub struct JobDispatcher {
+    pool: Pool,
+    ws_manager: WebSocketManager,
+    radius_km: f64,
+}
+
+impl JobDispatcher {
+    /// Reads the dispatch radius from `JOB_DISPATCH_RADIUS_KM`, falling back to
+    /// 10 km when it is unset or not a positive number.
+    pub fn from_env(pool: Pool, ws_manager: WebSocketManager) -> Self {
+        let radius_km = std::env::var("JOB_DISPATCH_RADIUS_KM")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|radius| *radius > 0.0)
+            .unwrap_or(DEFAULT_DISPATCH_RADIUS_KM);
+
+        Self {
+            pool,
+            ws_manager,
+            radius_km,
+        }
+    }
+
+    /// Notifies available employees near the job. Runs in the background so
+    /// the caller's request does not wait on socket writes.
+    pub fn job_created(&self, job: &JobOpportunity) {
+        let (lat, lng) = (job.latitude, job.longitude);
+        let event = ServerEvent::JobCreated(job.clone());
+        let dispatcher = self.clone();
+
+        tokio::spawn(async move {
+            let mut conn = match dispatcher.pool.get().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::error!("Unable to dispatch job: {e}");
+                    return;
+                }
+            };
+
+            match Repository::find_available_employee_users_near(
+                &mut conn,
+                lat,
+                lng,
+                dispatcher.radius_km,
+            )
+            .await
+            {
+                Ok(user_ids) => {
+                    dispatcher
+                        .ws_manager
+                        .send_event_to_users(&user_ids, &event)
+                        .await
+                }
+                Err(e) => tracing::error!("Unable to find employees to dispatch to: {e}"),
+            }
+        });
+    }
+
+    /// Tells the applicant their application was accepted or rejected.
+    pub fn application_decided(&self, application: &JobApplication) {
+        let employee_id = application.employee_id;
+        let event = ServerEvent::application_status_changed(application);
+        let dispatcher = self.clone();
+
+        tokio::spawn(async move {
+            let mut conn = match dispatcher.pool.get().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::error!("Unable to dispatch application status: {e}");
+                    return;
+                }
+            };
+
+            match Repository::find_employee_user_id(&mut conn, &employee_id).await {
+                Ok(user_id) => dispatcher.ws_manager.send_event(user_id, &event).await,
+                Err(e) => tracing::error!("Unable to find applicant to notify: {e}"),
+            }
+        });
+    }
+}
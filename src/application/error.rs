@@ -0,0 +1,201 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use bcrypt::BcryptError;
+use diesel::result::DatabaseErrorKind;
+use jsonwebtoken::errors::ErrorKind as JwtErrorKind;
+use serde_json::{json, Value};
+use std::fmt;
+
+use crate::domain::validation::ValidationErrors;
+
+/// Every way a request can fail. Responses share one body,
+/// `{"code", "message", "details"}`, so clients can branch on `code`
+/// whatever the route.
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    Conflict {
+        message: String,
+        details: Option<Value>,
+    },
+    /// The request is well formed but its content is not acceptable.
+    Validation {
+        message: String,
+        details: Option<Value>,
+    },
+    Unauthorized(String),
+    Forbidden(String),
+    /// Sent with `Retry-After`, in seconds.
+    TooManyRequests {
+        message: String,
+        retry_after: i64,
+    },
+    /// Logged in full; the client only gets a generic message.
+    Internal(String),
+}
+
+impl AppError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        AppError::NotFound(message.into())
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        AppError::Conflict {
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        AppError::Validation {
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        AppError::Unauthorized(message.into())
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        AppError::Forbidden(message.into())
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        AppError::Internal(message.into())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::Validation { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable identifier of the kind of error, for clients to match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict { .. } => "conflict",
+            AppError::Validation { .. } => "validation_failed",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::TooManyRequests { .. } => "too_many_requests",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::Internal(message)
+            | AppError::Conflict { message, .. }
+            | AppError::Validation { message, .. }
+            | AppError::TooManyRequests { message, .. } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let code = self.code();
+        let (message, details) = match self {
+            AppError::Internal(message) => {
+                tracing::error!("{message}");
+                ("Internal server error".to_string(), None)
+            }
+            AppError::TooManyRequests {
+                message,
+                retry_after,
+            } => {
+                let body = json!({
+                    "code": code,
+                    "message": message,
+                    "details": { "retry_after": retry_after },
+                });
+                return (
+                    status,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(body),
+                )
+                    .into_response();
+            }
+            AppError::Conflict { message, details } | AppError::Validation { message, details } => {
+                (message, details)
+            }
+            AppError::NotFound(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message) => (message, None),
+        };
+
+        let body = json!({ "code": code, "message": message, "details": details });
+        (status, Json(body)).into_response()
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        AppError::Validation {
+            message: "Validation failed".to_string(),
+            details: Some(json!({ "fields": errors })),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for AppError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            diesel::result::Error::NotFound => AppError::not_found("Not found"),
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                AppError::conflict("Already exists")
+            }
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::CheckViolation, _) => {
+                AppError::validation("Invalid value")
+            }
+            err => AppError::internal(format!("Database error: {err}")),
+        }
+    }
+}
+
+impl From<BcryptError> for AppError {
+    fn from(err: BcryptError) -> Self {
+        AppError::internal(format!("Password hashing failed: {err}"))
+    }
+}
+
+/// Anything wrong with a token the client sent is its problem; failing to
+/// sign one is ours.
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        match err.kind() {
+            JwtErrorKind::ExpiredSignature => AppError::unauthorized("Token expired"),
+            JwtErrorKind::InvalidEcdsaKey
+            | JwtErrorKind::InvalidRsaKey(_)
+            | JwtErrorKind::RsaFailedSigning
+            | JwtErrorKind::InvalidKeyFormat
+            | JwtErrorKind::Crypto(_) => AppError::internal(format!("JWT signing failed: {err}")),
+            _ => AppError::unauthorized("Invalid token"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> From<bb8::RunError<E>> for AppError {
+    fn from(err: bb8::RunError<E>) -> Self {
+        AppError::internal(format!("Unable to get a database connection: {err}"))
+    }
+}
@@ -0,0 +1,296 @@
+use axum::http::HeaderMap;
+use chrono::{Duration, NaiveDateTime};
+use std::net::{IpAddr, SocketAddr};
+
+use crate::domain::models::LoginThrottle;
+
+const DEFAULT_BASE_DELAY_SECS: i64 = 1;
+const DEFAULT_MAX_DELAY_SECS: i64 = 60;
+const DEFAULT_LOGIN_LOCKOUT_THRESHOLD: i32 = 10;
+const DEFAULT_IP_LOCKOUT_THRESHOLD: i32 = 50;
+const DEFAULT_LOCKOUT_MINUTES: i64 = 15;
+
+/// How failed logins slow down further attempts. Failures are counted per
+/// login and per client IP: each one makes the next attempt wait twice as
+/// long, and enough of them lock the key out for a while.
+#[derive(Clone, Debug)]
+pub struct LoginThrottleConfig {
+    /// Wait after the first failure.
+    pub base_delay: Duration,
+    /// Longest wait between attempts before the lockout kicks in.
+    pub max_delay: Duration,
+    /// Failures of one login before it is locked out.
+    pub login_lockout_threshold: i32,
+    /// Failures from one IP before it is locked out; higher than for a
+    /// login since many users may share an address.
+    pub ip_lockout_threshold: i32,
+    /// How long a lockout lasts. Failures older than this are forgotten.
+    pub lockout: Duration,
+    /// Take the client IP from `X-Forwarded-For`, for when the app runs
+    /// behind a proxy. Only the last entry, which that proxy added, is used;
+    /// the ones before it come from the client and can be anything.
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for LoginThrottleConfig {
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::seconds(DEFAULT_BASE_DELAY_SECS),
+            max_delay: Duration::seconds(DEFAULT_MAX_DELAY_SECS),
+            login_lockout_threshold: DEFAULT_LOGIN_LOCKOUT_THRESHOLD,
+            ip_lockout_threshold: DEFAULT_IP_LOCKOUT_THRESHOLD,
+            lockout: Duration::minutes(DEFAULT_LOCKOUT_MINUTES),
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+impl LoginThrottleConfig {
+    /// Reads `LOGIN_BACKOFF_BASE_SECS`, `LOGIN_BACKOFF_MAX_SECS`,
+    /// `LOGIN_LOCKOUT_THRESHOLD`, `LOGIN_IP_LOCKOUT_THRESHOLD`,
+    /// `LOGIN_LOCKOUT_MINUTES` and `LOGIN_TRUST_FORWARDED_FOR`, keeping the
+    /// defaults for anything unset or invalid.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+
+        let base_delay = std::env::var("LOGIN_BACKOFF_BASE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::seconds)
+            .unwrap_or(defaults.base_delay);
+
+        let max_delay = std::env::var("LOGIN_BACKOFF_MAX_SECS")
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::seconds)
+            .unwrap_or(defaults.max_delay)
+            .max(base_delay);
+
+        let login_lockout_threshold = std::env::var("LOGIN_LOCKOUT_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse::<i32>().ok())
+            .filter(|failures| *failures > 0)
+            .unwrap_or(defaults.login_lockout_threshold);
+
+        let ip_lockout_threshold = std::env::var("LOGIN_IP_LOCKOUT_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse::<i32>().ok())
+            .filter(|failures| *failures > 0)
+            .unwrap_or(defaults.ip_lockout_threshold);
+
+        let lockout = std::env::var("LOGIN_LOCKOUT_MINUTES")
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .filter(|minutes| *minutes > 0)
+            .map(Duration::minutes)
+            .unwrap_or(defaults.lockout);
+
+        let trust_forwarded_for = std::env::var("LOGIN_TRUST_FORWARDED_FOR")
+            .map(|value| value == "true" || value == "1")
+            .unwrap_or(defaults.trust_forwarded_for);
+
+        Self {
+            base_delay,
+            max_delay,
+            login_lockout_threshold,
+            ip_lockout_threshold,
+            lockout,
+            trust_forwarded_for,
+        }
+    }
+
+    /// The address the request came from.
+    pub fn client_ip(&self, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+        if self.trust_forwarded_for {
+            let forwarded = headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .next_back()
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|last| last.trim().parse().ok());
+            if let Some(ip) = forwarded {
+                return ip;
+            }
+        }
+        peer.ip()
+    }
+
+    pub fn login_key(login: &str) -> String {
+        format!("login:{login}")
+    }
+
+    pub fn ip_key(ip: IpAddr) -> String {
+        format!("ip:{ip}")
+    }
+
+    /// The counter after one more failure at `now`.
+    pub fn after_failure(
+        &self,
+        key: String,
+        previous: Option<&LoginThrottle>,
+        threshold: i32,
+        now: NaiveDateTime,
+    ) -> LoginThrottle {
+        let previous_failures = previous
+            .filter(|throttle| now - throttle.last_failure_at < self.lockout)
+            .map_or(0, |throttle| throttle.failures);
+        let failures = previous_failures.saturating_add(1);
+
+        let wait = if failures >= threshold {
+            self.lockout
+        } else {
+            // Doubling past 2^20 seconds is way beyond any sane maximum.
+            let doublings = (failures - 1).min(20) as u32;
+            (self.base_delay * 2i32.pow(doublings)).min(self.max_delay)
+        };
+
+        LoginThrottle {
+            key,
+            failures,
+            last_failure_at: now,
+            blocked_until: Some(now + wait),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn fail_times(config: &LoginThrottleConfig, times: i32, threshold: i32) -> LoginThrottle {
+        let mut throttle: Option<LoginThrottle> = None;
+        for _ in 0..times {
+            throttle = Some(config.after_failure(
+                "login:alice".to_string(),
+                throttle.as_ref(),
+                threshold,
+                now(),
+            ));
+        }
+        throttle.unwrap()
+    }
+
+    fn forwarded_config() -> LoginThrottleConfig {
+        LoginThrottleConfig {
+            trust_forwarded_for: true,
+            ..LoginThrottleConfig::default()
+        }
+    }
+
+    fn wait(throttle: &LoginThrottle) -> Duration {
+        throttle.blocked_until.unwrap() - throttle.last_failure_at
+    }
+
+    #[test]
+    fn each_failure_doubles_the_wait_up_to_the_maximum() {
+        let config = LoginThrottleConfig::default();
+        let waits: Vec<i64> = (1..=8)
+            .map(|failures| wait(&fail_times(&config, failures, 10)).num_seconds())
+            .collect();
+
+        assert_eq!(waits, [1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+
+    #[test]
+    fn reaching_the_threshold_locks_out() {
+        let config = LoginThrottleConfig::default();
+
+        let below = fail_times(&config, 9, 10);
+        assert_eq!(below.failures, 9);
+        assert_eq!(wait(&below), config.max_delay);
+
+        let locked = fail_times(&config, 10, 10);
+        assert_eq!(locked.failures, 10);
+        assert_eq!(wait(&locked), config.lockout);
+
+        let still_locked = fail_times(&config, 11, 10);
+        assert_eq!(wait(&still_locked), config.lockout);
+    }
+
+    #[test]
+    fn many_failures_do_not_overflow_the_delay() {
+        let config = LoginThrottleConfig {
+            max_delay: Duration::days(365),
+            ..LoginThrottleConfig::default()
+        };
+        let throttle = fail_times(&config, 40, i32::MAX);
+
+        assert_eq!(wait(&throttle), Duration::seconds(1 << 20));
+    }
+
+    #[test]
+    fn failures_older_than_the_lockout_are_forgotten() {
+        let config = LoginThrottleConfig::default();
+        let previous = LoginThrottle {
+            key: "login:alice".to_string(),
+            failures: 9,
+            last_failure_at: now() - config.lockout,
+            blocked_until: Some(now()),
+        };
+
+        let throttle = config.after_failure("login:alice".to_string(), Some(&previous), 10, now());
+        assert_eq!(throttle.failures, 1);
+        assert_eq!(wait(&throttle), config.base_delay);
+
+        let recent = LoginThrottle {
+            last_failure_at: now() - config.lockout + Duration::seconds(1),
+            ..previous
+        };
+        let throttle = config.after_failure("login:alice".to_string(), Some(&recent), 10, now());
+        assert_eq!(throttle.failures, 10);
+        assert_eq!(wait(&throttle), config.lockout);
+    }
+
+    #[test]
+    fn client_ip_is_the_peer_unless_forwarded_for_is_trusted() {
+        let peer: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+
+        let config = LoginThrottleConfig::default();
+        assert_eq!(config.client_ip(peer, &headers), peer.ip());
+
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(forwarded_config().client_ip(peer, &headers), ip);
+        assert_eq!(
+            forwarded_config().client_ip(peer, &HeaderMap::new()),
+            peer.ip()
+        );
+    }
+
+    #[test]
+    fn spoofed_forwarded_for_entries_are_ignored() {
+        let peer: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        // The client sent the first two entries; the proxy added the last.
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "198.51.100.1, 198.51.100.2, 203.0.113.7".parse().unwrap(),
+        );
+        assert_eq!(forwarded_config().client_ip(peer, &headers), ip);
+
+        // Proxies may add their own header line instead of appending.
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", "198.51.100.1".parse().unwrap());
+        headers.append("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        assert_eq!(forwarded_config().client_ip(peer, &headers), ip);
+
+        // An unreadable last entry falls back to the peer.
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.7, junk".parse().unwrap());
+        assert_eq!(forwarded_config().client_ip(peer, &headers), peer.ip());
+    }
+}
@@ -0,0 +1,174 @@
+use diesel::result::DatabaseErrorKind;
+use diesel_async::AsyncPgConnection;
+use serde_json::json;
+
+use crate::{
+    application::error::AppError,
+    domain::{
+        enums::{JobStatus, ReviewDirection},
+        models::{NewReview, Review},
+    },
+    infrastructure::repositories::Repository,
+};
+
+const DEFAULT_PRIOR_MEAN: f64 = 3.0;
+
+/// How ratings are worked out from reviews.
+///
+/// With a prior weight of zero a rating is the plain average. Otherwise it
+/// is a Bayesian average: the reviews are pooled with `prior_weight`
+/// imaginary ones of `prior_mean` stars, so one glowing review does not put
+/// a newcomer above someone with fifty good ones.
+#[derive(Clone, Debug)]
+pub struct ReviewConfig {
+    pub prior_weight: f64,
+    pub prior_mean: f64,
+}
+
+impl ReviewConfig {
+    /// Reads `RATING_PRIOR_WEIGHT` (default 0, a plain average) and
+    /// `RATING_PRIOR_MEAN` (default 3, between 1 and 5).
+    pub fn from_env() -> Self {
+        let prior_weight = std::env::var("RATING_PRIOR_WEIGHT")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|weight| weight.is_finite() && *weight >= 0.0)
+            .unwrap_or(0.0);
+        let prior_mean = std::env::var("RATING_PRIOR_MEAN")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|mean| (1.0..=5.0).contains(mean))
+            .unwrap_or(DEFAULT_PRIOR_MEAN);
+
+        Self {
+            prior_weight,
+            prior_mean,
+        }
+    }
+
+    /// The rating for `count` reviews adding up to `sum` stars, to the
+    /// hundredth. Nobody reviewed yet is rated 0.
+    pub fn rating(&self, sum: i64, count: i64) -> f64 {
+        if count == 0 {
+            return 0.0;
+        }
+        let rating =
+            (self.prior_weight * self.prior_mean + sum as f64) / (self.prior_weight + count as f64);
+        (rating * 100.0).round() / 100.0
+    }
+}
+
+pub enum ReviewError {
+    Database(diesel::result::Error),
+    JobNotCompleted(JobStatus),
+    /// The reviewing employee was not accepted on the job.
+    NotAssigned,
+    /// The employee the company reviews was not accepted on the job.
+    WorkerNotOnJob,
+    AlreadyReviewed,
+}
+
+impl From<diesel::result::Error> for ReviewError {
+    fn from(err: diesel::result::Error) -> Self {
+        ReviewError::Database(err)
+    }
+}
+
+impl From<ReviewError> for AppError {
+    fn from(err: ReviewError) -> Self {
+        match err {
+            ReviewError::Database(e) => e.into(),
+            ReviewError::JobNotCompleted(status) => AppError::Conflict {
+                message: "Only completed jobs can be reviewed".to_string(),
+                details: Some(json!({ "status": status })),
+            },
+            ReviewError::NotAssigned => AppError::forbidden("You did not work on this job"),
+            ReviewError::WorkerNotOnJob => {
+                AppError::not_found("This employee did not work on this job")
+            }
+            ReviewError::AlreadyReviewed => AppError::conflict("This job was already reviewed"),
+        }
+    }
+}
+
+/// Saves a review and recomputes the rating of whoever it is about.
+pub(crate) async fn record(
+    conn: &mut AsyncPgConnection,
+    config: &ReviewConfig,
+    direction: ReviewDirection,
+    new_review: NewReview,
+) -> Result<Review, ReviewError> {
+    let review = Repository::save_review(conn, &new_review)
+        .await
+        .map_err(|err| match err {
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                ReviewError::AlreadyReviewed
+            }
+            err => err.into(),
+        })?;
+
+    match direction {
+        ReviewDirection::CompanyToEmployee => {
+            let (count, stars) =
+                Repository::find_employee_review_totals_for_update(conn, &review.employee_id)
+                    .await?;
+            Repository::update_employee_rating(
+                conn,
+                &review.employee_id,
+                config.rating(stars, count),
+                count as i32,
+            )
+            .await?;
+        }
+        ReviewDirection::EmployeeToCompany => {
+            let (count, stars) =
+                Repository::find_company_review_totals_for_update(conn, &review.company_id).await?;
+            Repository::update_company_rating(
+                conn,
+                &review.company_id,
+                config.rating(stars, count),
+                count as i32,
+            )
+            .await?;
+        }
+    }
+    Ok(review)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(prior_weight: f64, prior_mean: f64) -> ReviewConfig {
+        ReviewConfig {
+            prior_weight,
+            prior_mean,
+        }
+    }
+
+    #[test]
+    fn no_prior_weight_is_a_plain_average() {
+        assert_eq!(config(0.0, 3.0).rating(9, 2), 4.5);
+        assert_eq!(config(0.0, 3.0).rating(5, 1), 5.0);
+    }
+
+    #[test]
+    fn prior_weight_pulls_few_reviews_towards_the_mean() {
+        // Two imaginary 3-star reviews next to one real 5-star review.
+        assert_eq!(config(2.0, 3.0).rating(5, 1), 3.67);
+        // Fifty good reviews outweigh the prior.
+        assert!(config(2.0, 3.0).rating(240, 50) > config(2.0, 3.0).rating(5, 1));
+    }
+
+    #[test]
+    fn nobody_reviewed_yet_is_rated_zero() {
+        assert_eq!(config(0.0, 3.0).rating(0, 0), 0.0);
+        assert_eq!(config(2.0, 3.0).rating(0, 0), 0.0);
+    }
+
+    #[test]
+    fn rating_rounds_to_the_hundredth() {
+        assert_eq!(config(0.0, 3.0).rating(10, 3), 3.33);
+        assert_eq!(config(0.0, 3.0).rating(14, 3), 4.67);
+    }
+}
@@ -1,21 +1,272 @@
 use axum::Json;
-use diesel_async::{pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection};
+use bcrypt::BcryptError;
+use chrono::{Duration, NaiveDateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use diesel::OptionalExtension;
+use diesel_async::{
+    pooled_connection::AsyncDieselConnectionManager, scoped_futures::ScopedFutureExt,
+    AsyncConnection, AsyncPgConnection,
+};
+use rand::RngCore;
+use serde_json::json;
+use std::net::IpAddr;
+use uuid::Uuid;
 
 use crate::{
-    domain::models::{
-        Company, Employee, JobOpportunity, JobOpportunityWithCompany, NewCompany, NewEmployee,
-        NewJobOpportunity, NewUser, User,
+    application::{
+        billing::{self, BillingConfig},
+        error::AppError,
+        login_throttle::LoginThrottleConfig,
+        review::{self, ReviewConfig, ReviewError},
+        timesheet::{self, TimesheetError},
+    },
+    domain::{
+        enums::{
+            AccountTokenPurpose, ApplicationStatus, CompanyRole, FailedLoginReason,
+            InvalidJobTransition, JobStatus, LedgerAccount, PayoutBatchStatus, PayoutStatus,
+            ReviewDirection, Role, TimesheetAction, TimesheetStatus,
+        },
+        models::{
+            AccountToken, BillingRun, Company, CompanyApiKey, CompanyInvitationToken,
+            CompanyMemberWithLogin, CreatedApiKey, Employee, InviteMemberRequest, Invoice,
+            InvoiceWithLines, JobApplication, JobApplicationWithEmployee, JobOpportunity,
+            JobOpportunityWithCompany, JobSearchQuery, MfaEnrollment, MfaRecoveryCodes,
+            NewAccountToken, NewApiKeyRequest, NewCompany, NewCompanyApiKey, NewCompanyInvitation,
+            NewCompanyMember, NewEmployee, NewFailedLogin, NewJobApplication, NewJobOpportunity,
+            NewMfaRecoveryCode, NewPayout, NewRefreshToken, NewReview, NewReviewRequest,
+            NewTimesheet, NewTimesheetEvent, NewUser, NewUserMfa, NewWsEvent, NewWsEventRecipient,
+            Payout, PayoutBatch, PayoutBatchTotals, PayoutBatchWithPayouts, ResetPasswordRequest,
+            Review, Timesheet, TimesheetDecision, TimesheetResubmission, TimesheetSubmission, User,
+            WsEvent,
+        },
+    },
+    infrastructure::{
+        auth::{Auth, Claims, TokenPair},
+        mailer::AccountMailer,
+        payments::Payments,
+        repositories::Repository,
+        totp,
     },
-    infrastructure::{auth::Auth, repositories::Repository},
 };
 
+pub enum JobLifecycleError {
+    Database(diesel::result::Error),
+    IllegalTransition(InvalidJobTransition),
+    NotOpen(JobStatus),
+    /// The job is COMPLETED or CANCELLED.
+    Over(JobStatus),
+    NoWorkers,
+    ShiftStarted,
+}
+
+impl From<diesel::result::Error> for JobLifecycleError {
+    fn from(err: diesel::result::Error) -> Self {
+        JobLifecycleError::Database(err)
+    }
+}
+
+impl From<InvalidJobTransition> for JobLifecycleError {
+    fn from(err: InvalidJobTransition) -> Self {
+        JobLifecycleError::IllegalTransition(err)
+    }
+}
+
+impl From<JobLifecycleError> for AppError {
+    fn from(err: JobLifecycleError) -> Self {
+        match err {
+            JobLifecycleError::Database(e) => e.into(),
+            JobLifecycleError::IllegalTransition(transition) => AppError::Conflict {
+                message: transition.to_string(),
+                details: Some(json!({ "from": transition.from, "to": transition.to })),
+            },
+            JobLifecycleError::NotOpen(status) => AppError::Conflict {
+                message: "Job is not open".to_string(),
+                details: Some(json!({ "status": status })),
+            },
+            JobLifecycleError::Over(status) => AppError::Conflict {
+                message: "Job is already over".to_string(),
+                details: Some(json!({ "status": status })),
+            },
+            JobLifecycleError::NoWorkers => AppError::conflict("No worker was assigned to the job"),
+            JobLifecycleError::ShiftStarted => AppError::conflict("Shift already started"),
+        }
+    }
+}
+
+pub enum SessionError {
+    Database(diesel::result::Error),
+    /// The refresh token is unknown, expired or was revoked.
+    InvalidToken,
+    /// An already rotated refresh token came back; the family was revoked.
+    TokenReused,
+    Token(AppError),
+}
+
+impl From<diesel::result::Error> for SessionError {
+    fn from(err: diesel::result::Error) -> Self {
+        SessionError::Database(err)
+    }
+}
+
+impl From<SessionError> for AppError {
+    fn from(err: SessionError) -> Self {
+        match err {
+            SessionError::InvalidToken => AppError::unauthorized("Invalid refresh token"),
+            SessionError::TokenReused => {
+                AppError::unauthorized("Refresh token reused, session revoked")
+            }
+            SessionError::Database(e) => e.into(),
+            SessionError::Token(e) => e,
+        }
+    }
+}
+
+pub enum MembershipError {
+    Database(diesel::result::Error),
+    /// Unknown, expired, already used or meant for someone else.
+    InvalidInvitation,
+    AlreadyInCompany,
+    /// Removing the member would leave the company without an admin.
+    LastAdmin,
+}
+
+impl From<diesel::result::Error> for MembershipError {
+    fn from(err: diesel::result::Error) -> Self {
+        MembershipError::Database(err)
+    }
+}
+
+impl From<MembershipError> for AppError {
+    fn from(err: MembershipError) -> Self {
+        match err {
+            MembershipError::Database(e) => e.into(),
+            MembershipError::InvalidInvitation => {
+                AppError::validation("Invalid or expired invitation")
+            }
+            MembershipError::AlreadyInCompany => {
+                AppError::conflict("Already a member of a company")
+            }
+            MembershipError::LastAdmin => AppError::conflict("A company needs at least one admin"),
+        }
+    }
+}
+
+/// How long an invitation to join a company can be accepted.
+const INVITATION_TTL_HOURS: i64 = 72;
+
+pub enum AccountTokenError {
+    Database(diesel::result::Error),
+    /// Unknown, expired or already used.
+    InvalidToken,
+    Hashing(BcryptError),
+}
+
+impl From<diesel::result::Error> for AccountTokenError {
+    fn from(err: diesel::result::Error) -> Self {
+        AccountTokenError::Database(err)
+    }
+}
+
+impl From<AccountTokenError> for AppError {
+    fn from(err: AccountTokenError) -> Self {
+        match err {
+            AccountTokenError::InvalidToken => AppError::validation("Invalid or expired token"),
+            AccountTokenError::Database(e) => e.into(),
+            AccountTokenError::Hashing(e) => e.into(),
+        }
+    }
+}
+
+pub enum MfaError {
+    Database(diesel::result::Error),
+    AlreadyEnabled,
+    /// No enrolment to confirm, or nothing to disable.
+    NotEnrolled,
+    InvalidCode,
+}
+
+impl From<diesel::result::Error> for MfaError {
+    fn from(err: diesel::result::Error) -> Self {
+        MfaError::Database(err)
+    }
+}
+
+impl From<MfaError> for AppError {
+    fn from(err: MfaError) -> Self {
+        match err {
+            MfaError::AlreadyEnabled => {
+                AppError::conflict("Two-factor authentication is already enabled")
+            }
+            MfaError::NotEnrolled => AppError::conflict("Two-factor authentication is not set up"),
+            MfaError::InvalidCode => AppError::validation("Invalid code"),
+            MfaError::Database(e) => e.into(),
+        }
+    }
+}
+
+/// Timesheets the auto-approval sweep handles per run.
+const AUTO_APPROVAL_BATCH: i64 = 100;
+
+/// How long a payout may wait for its outcome before the billing cycle
+/// settles it; well past any provider call a run could still be making.
+const STALE_PAYOUT_MINUTES: i64 = 60;
+/// Stale payouts settled per run.
+const STALE_PAYOUT_BATCH: i64 = 100;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// A recovery code like `K3QZP-7WMXA`, easy enough to type from paper.
+fn new_recovery_code() -> String {
+    let mut bytes = [0u8; 7];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = BASE32_NOPAD.encode(&bytes);
+    format!("{}-{}", &code[..5], &code[5..10])
+}
+
+/// Recovery codes are compared regardless of case and dashes.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    Auth::hash_token(&normalized)
+}
+
+/// Marks API keys so they are told apart from JWTs and spotted in leaks.
+pub const API_KEY_PREFIX: &str = "bz_";
+/// Characters after the prefix kept in clear to identify a key.
+const API_KEY_VISIBLE_CHARS: usize = 8;
+
+const PASSWORD_RESET_TTL_HOURS: i64 = 1;
+const EMAIL_VERIFICATION_TTL_HOURS: i64 = 24;
+
+/// Emails are matched case-insensitively, so they are stored lowercased.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
 pub struct Service;
 impl Service {
     pub async fn get_job_opportunities_with_company(
         conn: &mut AsyncPgConnection,
         company_id: i64,
     ) -> Result<Vec<JobOpportunityWithCompany>, diesel::result::Error> {
-        Ok(Repository::find_job_opportunities_with_company(conn, &company_id).await?)
+        Repository::find_job_opportunities_with_company(conn, &company_id).await
+    }
+
+    pub async fn search_job_opportunities(
+        conn: &mut AsyncPgConnection,
+        search: &JobSearchQuery,
+    ) -> Result<Vec<JobOpportunityWithCompany>, diesel::result::Error> {
+        Repository::search_job_opportunities(conn, search).await
+    }
+
+    pub async fn find_user(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user_id: i64,
+    ) -> Result<User, diesel::result::Error> {
+        Repository::find_user(conn, &user_id).await
     }
 
     pub async fn find_by_login(
@@ -25,6 +276,80 @@ impl Service {
         Repository::find_by_login(conn, user_login).await
     }
 
+    /// When a login from `keys` may be tried again, if any of them is
+    /// currently blocked.
+    pub async fn login_blocked_until(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        keys: &[String],
+    ) -> Result<Option<NaiveDateTime>, diesel::result::Error> {
+        let now = Utc::now().naive_utc();
+        Ok(Repository::find_login_throttles(conn, keys)
+            .await?
+            .into_iter()
+            .filter_map(|throttle| throttle.blocked_until)
+            .filter(|until| *until > now)
+            .max())
+    }
+
+    /// Audits a refused login. Wrong passwords and codes also count against
+    /// the login and the client IP, pushing back their next attempt.
+    pub async fn record_failed_login(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        config: &LoginThrottleConfig,
+        login: &str,
+        ip: IpAddr,
+        user_id: Option<i64>,
+        reason: FailedLoginReason,
+    ) -> Result<(), diesel::result::Error> {
+        let config = config.clone();
+        let login = login.to_string();
+
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                if reason != FailedLoginReason::Throttled {
+                    let now = Utc::now().naive_utc();
+                    let keys = [
+                        (
+                            LoginThrottleConfig::login_key(&login),
+                            config.login_lockout_threshold,
+                        ),
+                        (LoginThrottleConfig::ip_key(ip), config.ip_lockout_threshold),
+                    ];
+                    for (key, threshold) in keys {
+                        let previous =
+                            Repository::find_login_throttle_for_update(conn, &key).await?;
+                        let next = config.after_failure(key, previous.as_ref(), threshold, now);
+                        Repository::save_login_throttle(conn, &next).await?;
+                    }
+                }
+
+                Repository::save_failed_login(
+                    conn,
+                    &NewFailedLogin {
+                        login,
+                        ip: ip.to_string(),
+                        user_id,
+                        reason: reason.as_str().to_string(),
+                    },
+                )
+                .await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Forgets the failures of a login once someone gets its password right.
+    /// Those of the IP stay, or one known account would reset them.
+    pub async fn clear_login_failures(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        login: &str,
+    ) -> Result<(), diesel::result::Error> {
+        Repository::delete_login_throttle(conn, &LoginThrottleConfig::login_key(login)).await?;
+        Ok(())
+    }
+
     pub async fn find_employee(
         conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
         employe_id: &i64,
@@ -32,6 +357,19 @@ impl Service {
         Repository::find_employe(conn, employe_id).await
     }
 
+    /// Moves the employee profile of `user_id` to the reported coordinates.
+    /// Users without an employee profile are reported as not found.
+    pub async fn update_employee_location(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user_id: i64,
+        lat: f64,
+        lng: f64,
+    ) -> Result<Employee, diesel::result::Error> {
+        let user = Repository::find_user(conn, &user_id).await?;
+        let employee_id = user.employeeid.ok_or(diesel::result::Error::NotFound)?;
+        Repository::update_employee_location(conn, &employee_id, lat, lng).await
+    }
+
     pub async fn find_company(
         conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
         company_id: &i64,
@@ -54,51 +392,1475 @@ impl Service {
         Repository::update_company_logo(conn, &company_id, &logo_url).await
     }
 
+    /// Creates the user's company, making them its admin, or updates it when
+    /// they already have one.
     pub async fn add_company(
         conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
         company: NewCompany,
         user: User,
     ) -> Result<Json<Company>, diesel::result::Error> {
-        Repository::save_company(conn, &company, &user).await
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            async move { Repository::save_company(conn, &company, &user).await }.scope_boxed()
+        })
+        .await
+    }
+
+    /// Everything the user may currently act as. Company roles come from the
+    /// membership, so removing a member takes effect on their next request.
+    pub async fn user_roles(
+        conn: &mut AsyncPgConnection,
+        user: &User,
+    ) -> Result<Vec<Role>, diesel::result::Error> {
+        let mut roles = Vec::new();
+        if user.employeeid.is_some() {
+            roles.push(Role::Employee);
+        }
+        if let Some(membership) = Repository::find_company_membership(conn, &user.id).await? {
+            if user.companyid == Some(membership.company_id) {
+                roles.push(CompanyRole::from_db(&membership.role).role());
+            }
+        }
+        if user.is_platform_admin {
+            roles.push(Role::PlatformAdmin);
+        }
+        Ok(roles)
+    }
+
+    pub async fn invite_company_member(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        company_id: i64,
+        invited_by: i64,
+        request: InviteMemberRequest,
+    ) -> Result<CompanyInvitationToken, diesel::result::Error> {
+        let (token, token_hash) = Auth::generate_token();
+        let invitation = Repository::save_company_invitation(
+            conn,
+            &NewCompanyInvitation {
+                company_id,
+                role: request.role.as_str().to_string(),
+                invited_login: request.login,
+                token_hash,
+                invited_by,
+                expires_at: (Utc::now() + Duration::hours(INVITATION_TTL_HOURS)).naive_utc(),
+            },
+        )
+        .await?;
+
+        Ok(CompanyInvitationToken {
+            token,
+            role: request.role,
+            expires_at: invitation.expires_at,
+        })
+    }
+
+    /// Adds the user to the company that issued the invitation, using it up.
+    /// Callers run this in a transaction.
+    async fn accept_company_invitation(
+        conn: &mut AsyncPgConnection,
+        user: &User,
+        token: &str,
+    ) -> Result<User, MembershipError> {
+        let invitation =
+            Repository::find_company_invitation_by_hash_for_update(conn, &Auth::hash_token(token))
+                .await?
+                .ok_or(MembershipError::InvalidInvitation)?;
+
+        let usable = invitation.accepted_at.is_none()
+            && invitation.expires_at > Utc::now().naive_utc()
+            && invitation
+                .invited_login
+                .as_ref()
+                .is_none_or(|login| *login == user.login);
+        if !usable {
+            return Err(MembershipError::InvalidInvitation);
+        }
+        if user.companyid.is_some()
+            || Repository::find_company_membership(conn, &user.id)
+                .await?
+                .is_some()
+        {
+            return Err(MembershipError::AlreadyInCompany);
+        }
+
+        Repository::save_company_member(
+            conn,
+            &NewCompanyMember {
+                company_id: invitation.company_id,
+                user_id: user.id,
+                role: invitation.role.clone(),
+            },
+        )
+        .await?;
+        Repository::update_user_company(conn, &user.id, Some(invitation.company_id)).await?;
+        Repository::mark_company_invitation_accepted(conn, &invitation.id, &user.id).await?;
+
+        Ok(User {
+            companyid: Some(invitation.company_id),
+            ..user.clone()
+        })
+    }
+
+    /// Accepts an invitation for a user who just signed in.
+    pub async fn join_company(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user: User,
+        token: String,
+    ) -> Result<User, MembershipError> {
+        conn.transaction::<_, MembershipError, _>(|conn| {
+            async move { Self::accept_company_invitation(conn, &user, &token).await }.scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn list_company_members(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        company_id: i64,
+    ) -> Result<Vec<CompanyMemberWithLogin>, diesel::result::Error> {
+        Repository::find_company_members(conn, &company_id).await
+    }
+
+    /// Takes the user out of the company. The last admin cannot leave.
+    pub async fn remove_company_member(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        company_id: i64,
+        user_id: i64,
+    ) -> Result<(), MembershipError> {
+        conn.transaction::<_, MembershipError, _>(|conn| {
+            async move {
+                let members =
+                    Repository::find_company_members_for_update(conn, &company_id).await?;
+                let member = members
+                    .iter()
+                    .find(|member| member.user_id == user_id)
+                    .ok_or(diesel::result::Error::NotFound)?;
+
+                let admin = CompanyRole::ADMIN.as_str();
+                if member.role == admin
+                    && !members
+                        .iter()
+                        .any(|other| other.user_id != user_id && other.role == admin)
+                {
+                    return Err(MembershipError::LastAdmin);
+                }
+
+                Repository::delete_company_member(conn, &member.id).await?;
+                Repository::update_user_company(conn, &user_id, None).await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Issues an API key for the company. Only its hash is kept, so the key
+    /// is returned this once.
+    pub async fn create_api_key(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        company_id: i64,
+        created_by: i64,
+        request: NewApiKeyRequest,
+    ) -> Result<CreatedApiKey, diesel::result::Error> {
+        let (token, _) = Auth::generate_token();
+        let key = format!("{API_KEY_PREFIX}{token}");
+        let mut scopes: Vec<String> = request
+            .scopes
+            .iter()
+            .map(|scope| scope.as_str().to_string())
+            .collect();
+        scopes.sort();
+        scopes.dedup();
+
+        let api_key = Repository::save_company_api_key(
+            conn,
+            &NewCompanyApiKey {
+                company_id,
+                name: request.name,
+                prefix: key[..API_KEY_PREFIX.len() + API_KEY_VISIBLE_CHARS].to_string(),
+                key_hash: Auth::hash_token(&key),
+                scopes,
+                created_by,
+            },
+        )
+        .await?;
+        Ok(CreatedApiKey { api_key, key })
+    }
+
+    pub async fn list_api_keys(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        company_id: i64,
+    ) -> Result<Vec<CompanyApiKey>, diesel::result::Error> {
+        Repository::find_company_api_keys(conn, &company_id).await
+    }
+
+    /// Revokes one of the company's keys; `NotFound` when it has no such
+    /// active key.
+    pub async fn revoke_api_key(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        company_id: i64,
+        key_id: i64,
+    ) -> Result<(), diesel::result::Error> {
+        match Repository::revoke_company_api_key(conn, &company_id, &key_id).await? {
+            0 => Err(diesel::result::Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// The active key `key` belongs to, marking it as used.
+    pub async fn find_api_key(
+        conn: &mut AsyncPgConnection,
+        key: &str,
+    ) -> Result<Option<CompanyApiKey>, diesel::result::Error> {
+        let api_key =
+            Repository::find_active_company_api_key_by_hash(conn, &Auth::hash_token(key)).await?;
+        if let Some(api_key) = &api_key {
+            Repository::touch_company_api_key(conn, &api_key.id).await?;
+        }
+        Ok(api_key)
     }
 
     pub async fn add_job_opportunity(
         conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
         job: NewJobOpportunity,
-        user: User,
+        company_id: i64,
     ) -> Result<Json<JobOpportunity>, diesel::result::Error> {
         let new_job = NewJobOpportunity {
-            company_id: user.companyid,
+            company_id: Some(company_id),
+            status: JobStatus::OPEN,
             ..job.clone()
         };
 
         Repository::save_job_opportunity(conn, &new_job).await
     }
 
+    /// Creates the user, joining the inviting company when an invitation
+    /// token comes along; a bad invitation leaves no user behind.
     pub async fn register_user(
         conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
         new_user: NewUser,
-    ) -> Result<String, diesel::result::Error> {
-        match Repository::find_by_login(conn, &new_user.login).await {
-            Ok(_) => return Err(diesel::result::Error::BrokenTransactionManager),
-            Err(_) => {}
+        invitation_token: Option<String>,
+    ) -> Result<User, AppError> {
+        if Repository::find_by_login(conn, &new_user.login)
+            .await
+            .optional()?
+            .is_some()
+        {
+            return Err(AppError::conflict("Login already taken"));
         }
 
-        let hashed_password = match Auth::hash_password(&new_user.password.clone()) {
-            Ok(hashed_pass) => hashed_pass,
-            Err(_) => return Err(diesel::result::Error::BrokenTransactionManager),
-        };
+        let email = new_user.email.as_deref().map(normalize_email);
+        if let Some(email) = &email {
+            if Repository::find_user_by_email(conn, email).await?.is_some() {
+                return Err(AppError::conflict("Email already registered"));
+            }
+        }
+
+        let hashed_password = Auth::hash_password(new_user.password.clone()).await?;
 
         let new_user_hashed = NewUser {
             login: new_user.login.clone(),
             password: hashed_password,
+            email,
+        };
+
+        conn.transaction::<_, AppError, _>(|conn| {
+            async move {
+                let Json(user) = Repository::save_user(conn, &new_user_hashed).await?;
+                match invitation_token {
+                    Some(token) => Ok(Self::accept_company_invitation(conn, &user, &token).await?),
+                    None => Ok(user),
+                }
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Mails a link proving the user owns their email, if they gave one that
+    /// is not verified yet.
+    pub async fn send_email_verification(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        mailer: &AccountMailer,
+        user: &User,
+    ) -> Result<(), diesel::result::Error> {
+        let Some(email) = user
+            .email
+            .clone()
+            .filter(|_| user.email_verified_at.is_none())
+        else {
+            return Ok(());
         };
 
-        let user = Repository::save_user(conn, &new_user_hashed).await?;
-        let token = match Auth::encode_jwt(user.login.clone()) {
-            Ok(token) => Ok(token),
-            Err(_) => Err(diesel::result::Error::BrokenTransactionManager),
+        let token = Self::issue_account_token(
+            conn,
+            user.id,
+            AccountTokenPurpose::EmailVerification,
+            Some(email.clone()),
+            Duration::hours(EMAIL_VERIFICATION_TTL_HOURS),
+        )
+        .await?;
+        mailer.send_email_verification(email, &token);
+        Ok(())
+    }
+
+    /// Mails a reset link to whoever owns the email. Nothing tells the caller
+    /// whether an account matched.
+    pub async fn request_password_reset(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        mailer: &AccountMailer,
+        email: &str,
+    ) -> Result<(), diesel::result::Error> {
+        let Some(user) = Repository::find_user_by_email(conn, &normalize_email(email)).await?
+        else {
+            return Ok(());
+        };
+        let Some(email) = user.email else {
+            return Ok(());
         };
-        Ok(token?)
+
+        let token = Self::issue_account_token(
+            conn,
+            user.id,
+            AccountTokenPurpose::PasswordReset,
+            None,
+            Duration::hours(PASSWORD_RESET_TTL_HOURS),
+        )
+        .await?;
+        mailer.send_password_reset(email, &token);
+        Ok(())
+    }
+
+    /// Issues a token for `purpose`, replacing any the user still holds.
+    async fn issue_account_token(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user_id: i64,
+        purpose: AccountTokenPurpose,
+        email: Option<String>,
+        ttl: Duration,
+    ) -> Result<String, diesel::result::Error> {
+        let (token, token_hash) = Auth::generate_token();
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                Repository::use_up_account_tokens(conn, &user_id, purpose).await?;
+                Repository::save_account_token(
+                    conn,
+                    &NewAccountToken {
+                        user_id,
+                        purpose: purpose.as_str().to_string(),
+                        token_hash,
+                        email,
+                        expires_at: (Utc::now() + ttl).naive_utc(),
+                    },
+                )
+                .await
+            }
+            .scope_boxed()
+        })
+        .await?;
+        Ok(token)
+    }
+
+    /// Uses up a mailed token, failing when it cannot be used.
+    async fn consume_account_token(
+        conn: &mut AsyncPgConnection,
+        token: &str,
+        purpose: AccountTokenPurpose,
+    ) -> Result<AccountToken, AccountTokenError> {
+        let account_token = Repository::find_account_token_by_hash_for_update(
+            conn,
+            &Auth::hash_token(token),
+            purpose,
+        )
+        .await?
+        .ok_or(AccountTokenError::InvalidToken)?;
+        if account_token.used_at.is_some() || account_token.expires_at <= Utc::now().naive_utc() {
+            return Err(AccountTokenError::InvalidToken);
+        }
+
+        Repository::use_up_account_tokens(conn, &account_token.user_id, purpose).await?;
+        Ok(account_token)
+    }
+
+    /// Sets a new password and signs the user out everywhere, so whoever
+    /// knew the old one loses access.
+    pub async fn reset_password(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        request: ResetPasswordRequest,
+    ) -> Result<(), AccountTokenError> {
+        let hashed_password = Auth::hash_password(request.password.clone())
+            .await
+            .map_err(AccountTokenError::Hashing)?;
+
+        conn.transaction::<_, AccountTokenError, _>(|conn| {
+            async move {
+                let token = Self::consume_account_token(
+                    conn,
+                    &request.token,
+                    AccountTokenPurpose::PasswordReset,
+                )
+                .await?;
+                Repository::update_user_password(conn, &token.user_id, &hashed_password).await?;
+                Repository::revoke_user_refresh_tokens(conn, &token.user_id).await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Marks the email the token was sent to as verified, unless the user has
+    /// changed it since.
+    pub async fn verify_email(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        token: String,
+    ) -> Result<(), AccountTokenError> {
+        conn.transaction::<_, AccountTokenError, _>(|conn| {
+            async move {
+                let token = Self::consume_account_token(
+                    conn,
+                    &token,
+                    AccountTokenPurpose::EmailVerification,
+                )
+                .await?;
+                let user = Repository::find_user(conn, &token.user_id).await?;
+                if user.email.is_none() || user.email != token.email {
+                    return Err(AccountTokenError::InvalidToken);
+                }
+                Repository::mark_user_email_verified(conn, &user.id).await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Starts (or restarts) TOTP enrolment; it only takes effect once a code
+    /// from the app is confirmed.
+    pub async fn enroll_mfa(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user: &User,
+    ) -> Result<MfaEnrollment, MfaError> {
+        let user_id = user.id;
+        let login = user.login.clone();
+
+        conn.transaction::<_, MfaError, _>(|conn| {
+            async move {
+                let existing = Repository::find_user_mfa_for_update(conn, &user_id).await?;
+                if existing.is_some_and(|mfa| mfa.confirmed_at.is_some()) {
+                    return Err(MfaError::AlreadyEnabled);
+                }
+
+                let secret = totp::generate_secret();
+                Repository::save_user_mfa(
+                    conn,
+                    &NewUserMfa {
+                        user_id,
+                        secret: secret.clone(),
+                    },
+                )
+                .await?;
+                Ok(MfaEnrollment {
+                    otpauth_uri: totp::provisioning_uri(&secret, &login),
+                    secret,
+                })
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Turns two-factor authentication on with a first code from the app,
+    /// handing out fresh recovery codes.
+    pub async fn confirm_mfa(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user_id: i64,
+        code: String,
+    ) -> Result<MfaRecoveryCodes, MfaError> {
+        conn.transaction::<_, MfaError, _>(|conn| {
+            async move {
+                let mfa = Repository::find_user_mfa_for_update(conn, &user_id)
+                    .await?
+                    .ok_or(MfaError::NotEnrolled)?;
+                if mfa.confirmed_at.is_some() {
+                    return Err(MfaError::AlreadyEnabled);
+                }
+                let step = totp::verify(&mfa.secret, &code, Utc::now().timestamp())
+                    .ok_or(MfaError::InvalidCode)?;
+                Repository::confirm_user_mfa(conn, &user_id, step).await?;
+
+                let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+                    .map(|_| new_recovery_code())
+                    .collect();
+                let new_codes: Vec<NewMfaRecoveryCode> = recovery_codes
+                    .iter()
+                    .map(|code| NewMfaRecoveryCode {
+                        user_id,
+                        code_hash: hash_recovery_code(code),
+                    })
+                    .collect();
+                Repository::delete_mfa_recovery_codes(conn, &user_id).await?;
+                Repository::save_mfa_recovery_codes(conn, &new_codes).await?;
+
+                Ok(MfaRecoveryCodes { recovery_codes })
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Turns two-factor authentication off, which takes a valid code.
+    pub async fn disable_mfa(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user_id: i64,
+        code: String,
+    ) -> Result<(), MfaError> {
+        conn.transaction::<_, MfaError, _>(|conn| {
+            async move {
+                if !Repository::user_mfa_enabled(conn, &user_id).await? {
+                    return Err(MfaError::NotEnrolled);
+                }
+                if !Self::use_mfa_code(conn, user_id, &code).await? {
+                    return Err(MfaError::InvalidCode);
+                }
+                Repository::delete_user_mfa(conn, &user_id).await?;
+                Repository::delete_mfa_recovery_codes(conn, &user_id).await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn mfa_enabled(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user_id: i64,
+    ) -> Result<bool, diesel::result::Error> {
+        Repository::user_mfa_enabled(conn, &user_id).await
+    }
+
+    /// Checks the second factor of a user signing in.
+    pub async fn verify_mfa_code(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user_id: i64,
+        code: String,
+    ) -> Result<bool, diesel::result::Error> {
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            async move { Self::use_mfa_code(conn, user_id, &code).await }.scope_boxed()
+        })
+        .await
+    }
+
+    /// Whether `code` is a TOTP code not used before or an unused recovery
+    /// code, using it up. Callers run this in a transaction.
+    async fn use_mfa_code(
+        conn: &mut AsyncPgConnection,
+        user_id: i64,
+        code: &str,
+    ) -> Result<bool, diesel::result::Error> {
+        let Some(mfa) = Repository::find_user_mfa_for_update(conn, &user_id)
+            .await?
+            .filter(|mfa| mfa.confirmed_at.is_some())
+        else {
+            return Ok(false);
+        };
+
+        if let Some(step) = totp::verify(&mfa.secret, code, Utc::now().timestamp()) {
+            if mfa.last_used_step.is_some_and(|last| step <= last) {
+                return Ok(false);
+            }
+            Repository::update_user_mfa_last_step(conn, &user_id, step).await?;
+            return Ok(true);
+        }
+
+        match Repository::find_unused_mfa_recovery_code_for_update(
+            conn,
+            &user_id,
+            &hash_recovery_code(code),
+        )
+        .await?
+        {
+            Some(recovery_code) => {
+                Repository::mark_mfa_recovery_code_used(conn, &recovery_code.id).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Starts a session for a user who just proved who they are: a new
+    /// refresh token family and an access token bound to it.
+    pub async fn start_session(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user: &User,
+    ) -> Result<TokenPair, SessionError> {
+        Self::issue_tokens(conn, user, Uuid::new_v4()).await
+    }
+
+    async fn issue_tokens(
+        conn: &mut AsyncPgConnection,
+        user: &User,
+        family_id: Uuid,
+    ) -> Result<TokenPair, SessionError> {
+        let config = Auth::jwt_config().map_err(SessionError::Token)?;
+        let (refresh_token, token_hash) = Auth::generate_token();
+        let new_token = NewRefreshToken {
+            user_id: user.id,
+            family_id,
+            token_hash,
+            expires_at: (Utc::now() + config.refresh_expiry).naive_utc(),
+        };
+        Repository::save_refresh_token(conn, &new_token).await?;
+
+        let roles = Self::user_roles(conn, user).await?;
+        let access_token = Auth::encode_jwt(user, roles, family_id).map_err(SessionError::Token)?;
+        Ok(TokenPair {
+            access_token,
+            refresh_token,
+            token_type: "Bearer",
+            expires_in: config.expiry.num_seconds(),
+        })
+    }
+
+    /// Trades a refresh token for a new pair in the same family. A token that
+    /// was already traded means it leaked, so the whole family is revoked and
+    /// whoever holds the newer tokens has to log in again.
+    pub async fn refresh_session(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        refresh_token: &str,
+    ) -> Result<TokenPair, SessionError> {
+        let token_hash = Auth::hash_token(refresh_token);
+        // The revocation on reuse has to be committed, so that outcome is
+        // returned as a successful transaction carrying an error.
+        conn.transaction::<_, SessionError, _>(|conn| {
+            async move {
+                let token = match Repository::find_refresh_token_by_hash_for_update(
+                    conn,
+                    &token_hash,
+                )
+                .await
+                {
+                    Ok(token) => token,
+                    Err(diesel::result::Error::NotFound) => {
+                        return Ok(Err(SessionError::InvalidToken))
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+                if token.revoked_at.is_some() {
+                    return Ok(Err(SessionError::InvalidToken));
+                }
+                if token.used_at.is_some() {
+                    Repository::revoke_refresh_token_family(conn, &token.family_id).await?;
+                    tracing::warn!(
+                        "Refresh token reused for user {}, revoked session {}",
+                        token.user_id,
+                        token.family_id
+                    );
+                    return Ok(Err(SessionError::TokenReused));
+                }
+                if token.expires_at < Utc::now().naive_utc() {
+                    return Ok(Err(SessionError::InvalidToken));
+                }
+
+                Repository::mark_refresh_token_used(conn, &token.id).await?;
+                let user = Repository::find_user(conn, &token.user_id).await?;
+                Self::issue_tokens(conn, &user, token.family_id)
+                    .await
+                    .map(Ok)
+            }
+            .scope_boxed()
+        })
+        .await?
+    }
+
+    /// Revokes one session; its access tokens stop working right away.
+    pub async fn end_session(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        session_id: &Uuid,
+    ) -> Result<usize, diesel::result::Error> {
+        Repository::revoke_refresh_token_family(conn, session_id).await
+    }
+
+    pub async fn end_all_sessions(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        Repository::revoke_user_refresh_tokens(conn, user_id).await
+    }
+
+    /// The user an access token was issued to, provided its session has not
+    /// been revoked.
+    pub async fn find_session_user(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        claims: &Claims,
+    ) -> Result<User, diesel::result::Error> {
+        let user = Repository::find_by_login(conn, &claims.login).await?;
+        if !Repository::refresh_token_family_active(conn, &claims.sid, &user.id).await? {
+            return Err(diesel::result::Error::NotFound);
+        }
+        Ok(user)
+    }
+
+    pub async fn apply_to_job(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        job_id: i64,
+        employee_id: i64,
+    ) -> Result<Json<JobApplication>, JobLifecycleError> {
+        let job = Repository::find_job_opportunity(conn, &job_id).await?;
+        if job.status != JobStatus::OPEN {
+            return Err(JobLifecycleError::NotOpen(job.status));
+        }
+
+        let new_application = NewJobApplication {
+            job_id: job.id,
+            employee_id,
+            status: ApplicationStatus::PENDING.as_str().to_string(),
+        };
+
+        Ok(Repository::save_job_application(conn, &new_application).await?)
+    }
+
+    pub async fn list_job_applications(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        job_id: i64,
+        company_id: i64,
+    ) -> Result<Vec<JobApplicationWithEmployee>, diesel::result::Error> {
+        Repository::find_job_applications_for_company(conn, &job_id, &company_id).await
+    }
+
+    /// Accepts or rejects a pending application on one of the company's jobs.
+    /// Applications that were already decided are reported as not found.
+    /// Accepting takes one of the job's slots, moving it from OPEN to PENDING
+    /// once none are left.
+    pub async fn decide_job_application(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        application_id: i64,
+        company_id: i64,
+        decision: ApplicationStatus,
+    ) -> Result<Json<JobApplication>, JobLifecycleError> {
+        conn.transaction::<_, JobLifecycleError, _>(|conn| {
+            async move {
+                let application = Repository::find_job_application_for_company(
+                    conn,
+                    &application_id,
+                    &company_id,
+                )
+                .await?;
+
+                if application.status != ApplicationStatus::PENDING.as_str() {
+                    return Err(diesel::result::Error::NotFound.into());
+                }
+
+                if decision == ApplicationStatus::ACCEPTED {
+                    let job = Repository::find_job_opportunity_for_update(
+                        conn,
+                        &application.job_id,
+                        &company_id,
+                    )
+                    .await?;
+                    if job.status != JobStatus::OPEN {
+                        return Err(JobLifecycleError::NotOpen(job.status));
+                    }
+                    let assigned = job.assigned_slots + 1;
+                    let next = if assigned == job.headcount {
+                        job.status.transition_to(JobStatus::PENDING)?
+                    } else {
+                        job.status
+                    };
+                    let _ = Repository::update_job_slots(conn, &job.id, assigned, next).await?;
+                }
+
+                // Only one decision wins when two arrive at once; the other
+                // finds the application decided and rolls back its slot.
+                let decided = Repository::decide_pending_job_application(
+                    conn,
+                    &application.id,
+                    decision.as_str(),
+                )
+                .await?
+                .ok_or(diesel::result::Error::NotFound)?;
+                Ok(Json(decided))
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Withdraws the employee's application to a job. Giving up an accepted
+    /// place frees its slot, opening the job again if it was full; once the
+    /// shift has started it is too late.
+    pub async fn withdraw_from_job(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        job_id: i64,
+        employee_id: i64,
+    ) -> Result<Json<JobApplication>, JobLifecycleError> {
+        conn.transaction::<_, JobLifecycleError, _>(|conn| {
+            async move {
+                let job = Repository::find_job_opportunity_for_update_by_id(conn, &job_id).await?;
+                let application =
+                    Repository::find_active_job_application(conn, &job_id, &employee_id)
+                        .await?
+                        .ok_or(diesel::result::Error::NotFound)?;
+                if matches!(job.status, JobStatus::COMPLETED | JobStatus::CANCELLED) {
+                    return Err(JobLifecycleError::Over(job.status));
+                }
+
+                if application.status == ApplicationStatus::ACCEPTED.as_str() {
+                    if Repository::find_shift_for_update(conn, &job_id, &employee_id)
+                        .await?
+                        .is_some()
+                    {
+                        return Err(JobLifecycleError::ShiftStarted);
+                    }
+                    let next = if job.status == JobStatus::PENDING {
+                        job.status.transition_to(JobStatus::OPEN)?
+                    } else {
+                        job.status
+                    };
+                    let _ =
+                        Repository::update_job_slots(conn, &job.id, job.assigned_slots - 1, next)
+                            .await?;
+                }
+
+                Ok(Repository::update_job_application_status(
+                    conn,
+                    &application.id,
+                    ApplicationStatus::WITHDRAWN.as_str(),
+                )
+                .await?)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Claims hours for the employee's finished shift on a job. Submitting
+    /// again replaces the claim until the company approves it.
+    pub async fn submit_timesheet(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        job_id: i64,
+        employee_id: i64,
+        user_id: i64,
+        submission: TimesheetSubmission,
+    ) -> Result<Timesheet, TimesheetError> {
+        conn.transaction::<_, TimesheetError, _>(|conn| {
+            async move {
+                let shift = Repository::find_shift_for_update(conn, &job_id, &employee_id)
+                    .await?
+                    .ok_or(TimesheetError::NotCheckedOut)?;
+                let worked_hours = shift.worked_hours.ok_or(TimesheetError::NotCheckedOut)?;
+                let hours = submission.hours.unwrap_or(worked_hours);
+                if submission.break_minutes > 0
+                    && f64::from(submission.break_minutes) / 60.0 >= hours
+                {
+                    return Err(TimesheetError::BreakTooLong);
+                }
+                let net_hours = timesheet::net_hours(hours, submission.break_minutes);
+                let submitted_at = Utc::now().naive_utc();
+
+                let existing =
+                    Repository::find_timesheet_by_shift_for_update(conn, &shift.id).await?;
+                let (saved, action) = match existing {
+                    Some(existing) if existing.status == TimesheetStatus::APPROVED.as_str() => {
+                        return Err(TimesheetError::AlreadyApproved);
+                    }
+                    Some(existing) => {
+                        let saved = Repository::update_timesheet_submission(
+                            conn,
+                            &existing.id,
+                            &TimesheetResubmission {
+                                status: TimesheetStatus::SUBMITTED.as_str().to_string(),
+                                submitted_hours: hours,
+                                break_minutes: submission.break_minutes,
+                                net_hours,
+                                note: submission.note.clone(),
+                                dispute_reason: None,
+                                submitted_at,
+                                decided_at: None,
+                                decided_by: None,
+                            },
+                        )
+                        .await?;
+                        (saved, TimesheetAction::Resubmitted)
+                    }
+                    None => {
+                        let job = Repository::find_job_opportunity(conn, &job_id).await?;
+                        let saved = Repository::save_timesheet(
+                            conn,
+                            &NewTimesheet {
+                                shift_id: shift.id,
+                                job_id,
+                                employee_id,
+                                status: TimesheetStatus::SUBMITTED.as_str().to_string(),
+                                submitted_hours: hours,
+                                break_minutes: submission.break_minutes,
+                                net_hours,
+                                pay_rate: job.pay_rate,
+                                note: submission.note.clone(),
+                                submitted_at,
+                            },
+                        )
+                        .await?;
+                        (saved, TimesheetAction::Submitted)
+                    }
+                };
+
+                Repository::save_timesheet_event(
+                    conn,
+                    &NewTimesheetEvent {
+                        timesheet_id: saved.id,
+                        action: action.as_str().to_string(),
+                        actor_user_id: Some(user_id),
+                        net_hours,
+                        note: submission.note,
+                    },
+                )
+                .await?;
+                Ok(saved)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn list_employee_timesheets(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        employee_id: i64,
+    ) -> Result<Vec<Timesheet>, diesel::result::Error> {
+        Repository::find_timesheets_for_employee(conn, &employee_id).await
+    }
+
+    pub async fn list_job_timesheets(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        job_id: i64,
+        company_id: i64,
+    ) -> Result<Vec<Timesheet>, diesel::result::Error> {
+        Repository::find_timesheets_for_job(conn, &job_id, &company_id).await
+    }
+
+    /// Accepts a submitted timesheet on one of the company's jobs, fixing
+    /// what the worker is owed.
+    pub async fn approve_timesheet(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        billing: &BillingConfig,
+        timesheet_id: i64,
+        company_id: i64,
+        decided_by: Option<i64>,
+    ) -> Result<Timesheet, TimesheetError> {
+        let billing = billing.clone();
+        conn.transaction::<_, TimesheetError, _>(|conn| {
+            async move {
+                let timesheet = Repository::find_timesheet_for_company_for_update(
+                    conn,
+                    &timesheet_id,
+                    &company_id,
+                )
+                .await?;
+                if timesheet.status != TimesheetStatus::SUBMITTED.as_str() {
+                    return Err(TimesheetError::NotSubmitted(timesheet.status));
+                }
+                Ok(timesheet::approve(conn, &billing, &timesheet, decided_by, false).await?)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Sends a submitted timesheet back to the worker with the reason.
+    pub async fn dispute_timesheet(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        timesheet_id: i64,
+        company_id: i64,
+        decided_by: Option<i64>,
+        reason: String,
+    ) -> Result<Timesheet, TimesheetError> {
+        conn.transaction::<_, TimesheetError, _>(|conn| {
+            async move {
+                let timesheet = Repository::find_timesheet_for_company_for_update(
+                    conn,
+                    &timesheet_id,
+                    &company_id,
+                )
+                .await?;
+                if timesheet.status != TimesheetStatus::SUBMITTED.as_str() {
+                    return Err(TimesheetError::NotSubmitted(timesheet.status));
+                }
+
+                let disputed = Repository::update_timesheet_decision(
+                    conn,
+                    &timesheet.id,
+                    &TimesheetDecision {
+                        status: TimesheetStatus::DISPUTED.as_str().to_string(),
+                        dispute_reason: Some(reason.clone()),
+                        approved_hours: None,
+                        payable_amount: None,
+                        decided_at: Utc::now().naive_utc(),
+                        decided_by,
+                        auto_approved: false,
+                    },
+                )
+                .await?;
+                Repository::save_timesheet_event(
+                    conn,
+                    &NewTimesheetEvent {
+                        timesheet_id: disputed.id,
+                        action: TimesheetAction::Disputed.as_str().to_string(),
+                        actor_user_id: decided_by,
+                        net_hours: disputed.net_hours,
+                        note: Some(reason),
+                    },
+                )
+                .await?;
+                Ok(disputed)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Approves timesheets submitted before `cutoff` that are still waiting
+    /// for review, and returns how many. Each is approved in its own
+    /// transaction, so one that fails is logged and left for the next run
+    /// without holding up the rest.
+    pub async fn auto_approve_timesheets(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        billing: &BillingConfig,
+        cutoff: NaiveDateTime,
+    ) -> Result<usize, diesel::result::Error> {
+        let due =
+            Repository::find_timesheet_ids_due_for_approval(conn, cutoff, AUTO_APPROVAL_BATCH)
+                .await?;
+
+        let mut approved = 0;
+        for timesheet_id in due {
+            let result = conn
+                .transaction::<_, diesel::result::Error, _>(|conn| {
+                    let billing = billing.clone();
+                    async move {
+                        let Some(timesheet) =
+                            Repository::find_due_timesheet_for_update(conn, &timesheet_id, cutoff)
+                                .await?
+                        else {
+                            return Ok(false);
+                        };
+                        timesheet::approve(conn, &billing, &timesheet, None, true).await?;
+                        Ok(true)
+                    }
+                    .scope_boxed()
+                })
+                .await;
+            match result {
+                Ok(true) => approved += 1,
+                Ok(false) => {}
+                Err(e) => tracing::error!("Unable to auto-approve timesheet {timesheet_id}: {e}"),
+            }
+        }
+        Ok(approved)
+    }
+
+    /// The company's review of a worker it accepted on one of its completed
+    /// jobs.
+    pub async fn review_employee(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        config: &ReviewConfig,
+        job_id: i64,
+        company_id: i64,
+        employee_id: i64,
+        author_user_id: i64,
+        request: NewReviewRequest,
+    ) -> Result<Review, ReviewError> {
+        let config = config.clone();
+        conn.transaction::<_, ReviewError, _>(|conn| {
+            async move {
+                let job = Repository::find_job_opportunity(conn, &job_id).await?;
+                if job.company_id != Some(company_id) {
+                    return Err(diesel::result::Error::NotFound.into());
+                }
+                if job.status != JobStatus::COMPLETED {
+                    return Err(ReviewError::JobNotCompleted(job.status));
+                }
+                Repository::find_accepted_job_application(conn, &job_id, &employee_id)
+                    .await?
+                    .ok_or(ReviewError::WorkerNotOnJob)?;
+
+                review::record(
+                    conn,
+                    &config,
+                    ReviewDirection::CompanyToEmployee,
+                    NewReview {
+                        job_id,
+                        employee_id,
+                        company_id,
+                        direction: ReviewDirection::CompanyToEmployee.as_str().to_string(),
+                        author_user_id: Some(author_user_id),
+                        rating: request.rating,
+                        comment: request.comment,
+                    },
+                )
+                .await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// A worker's review of the company behind a completed job they were
+    /// accepted on.
+    pub async fn review_company(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        config: &ReviewConfig,
+        job_id: i64,
+        employee_id: i64,
+        author_user_id: i64,
+        request: NewReviewRequest,
+    ) -> Result<Review, ReviewError> {
+        let config = config.clone();
+        conn.transaction::<_, ReviewError, _>(|conn| {
+            async move {
+                let job = Repository::find_job_opportunity(conn, &job_id).await?;
+                Repository::find_accepted_job_application(conn, &job_id, &employee_id)
+                    .await?
+                    .ok_or(ReviewError::NotAssigned)?;
+                if job.status != JobStatus::COMPLETED {
+                    return Err(ReviewError::JobNotCompleted(job.status));
+                }
+                let company_id = job.company_id.ok_or(diesel::result::Error::NotFound)?;
+
+                review::record(
+                    conn,
+                    &config,
+                    ReviewDirection::EmployeeToCompany,
+                    NewReview {
+                        job_id,
+                        employee_id,
+                        company_id,
+                        direction: ReviewDirection::EmployeeToCompany.as_str().to_string(),
+                        author_user_id: Some(author_user_id),
+                        rating: request.rating,
+                        comment: request.comment,
+                    },
+                )
+                .await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn list_employee_reviews(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        employee_id: i64,
+    ) -> Result<Vec<Review>, diesel::result::Error> {
+        Repository::find_reviews_for_employee(conn, &employee_id).await
+    }
+
+    pub async fn list_company_reviews(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        company_id: i64,
+    ) -> Result<Vec<Review>, diesel::result::Error> {
+        Repository::find_reviews_for_company(conn, &company_id).await
+    }
+
+    /// Moves one of the company's jobs to `next`, rejecting moves the
+    /// lifecycle does not allow. A job can only be completed once someone
+    /// was assigned to it, and completing it charges the company for the
+    /// timesheets approved so far.
+    pub async fn transition_job(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        billing: &BillingConfig,
+        job_id: i64,
+        company_id: i64,
+        next: JobStatus,
+    ) -> Result<Json<JobOpportunity>, JobLifecycleError> {
+        let billing = billing.clone();
+        conn.transaction::<_, JobLifecycleError, _>(|conn| {
+            async move {
+                let job =
+                    Repository::find_job_opportunity_for_update(conn, &job_id, &company_id).await?;
+                let next = job.status.transition_to(next)?;
+                if next == JobStatus::COMPLETED && job.assigned_slots == 0 {
+                    return Err(JobLifecycleError::NoWorkers);
+                }
+                let updated = Repository::update_job_status(conn, &job.id, next).await?;
+                if next == JobStatus::COMPLETED {
+                    for timesheet in
+                        Repository::find_approved_timesheets_for_job(conn, &job.id).await?
+                    {
+                        billing::post_timesheet_cost(conn, &billing, &timesheet, company_id)
+                            .await?;
+                    }
+                }
+                Ok(updated)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Invoices the companies and pays the employees for work charged before
+    /// `cutoff`, after settling payouts an earlier run left unfinished.
+    pub async fn run_billing_cycle(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        payments: &Payments,
+        cutoff: NaiveDateTime,
+    ) -> Result<BillingRun, diesel::result::Error> {
+        let stale_cutoff = Utc::now().naive_utc() - Duration::minutes(STALE_PAYOUT_MINUTES);
+        let settled = Service::settle_stale_payouts(conn, payments, stale_cutoff).await?;
+        if settled > 0 {
+            tracing::info!("Settled {settled} payouts pending since before {stale_cutoff}");
+        }
+
+        let mut invoices = Vec::new();
+        for company_id in Repository::find_companies_due_for_invoice(conn, cutoff).await? {
+            let invoice = conn
+                .transaction::<_, diesel::result::Error, _>(|conn| {
+                    async move { billing::issue_invoice(conn, company_id).await }.scope_boxed()
+                })
+                .await?;
+            invoices.extend(invoice);
+        }
+        let payout_batch = Service::run_payouts(conn, payments, cutoff).await?;
+        Ok(BillingRun {
+            invoices,
+            payout_batch,
+        })
+    }
+
+    /// Pays each employee what they earned before `cutoff` and was not paid
+    /// yet, as one batch. Failed payouts leave the earnings for the next
+    /// batch.
+    pub async fn run_payouts(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        payments: &Payments,
+        cutoff: NaiveDateTime,
+    ) -> Result<Option<PayoutBatch>, diesel::result::Error> {
+        let provider = payments.provider_name();
+        let created = conn
+            .transaction::<_, diesel::result::Error, _>(|conn| {
+                async move {
+                    let costs = Repository::find_unpaid_costs_for_update(conn, cutoff).await?;
+                    let cost_ids: Vec<i64> = costs.iter().map(|cost| cost.id).collect();
+                    let entries =
+                        Repository::find_ledger_entries_for_transactions(conn, &cost_ids).await?;
+
+                    // Earnings per employee, with the costs they come from.
+                    let mut earnings: Vec<(i64, i64, Vec<i64>)> = Vec::new();
+                    for cost in &costs {
+                        let Some(employee_id) = cost.employee_id else {
+                            continue;
+                        };
+                        let amount: i64 = entries
+                            .iter()
+                            .filter(|entry| {
+                                entry.transaction_id == cost.id
+                                    && entry.account == LedgerAccount::EmployeeEarnings.as_str()
+                            })
+                            .map(|entry| entry.amount_cents)
+                            .sum();
+                        match earnings.iter_mut().find(|(id, _, _)| *id == employee_id) {
+                            Some((_, total, ids)) => {
+                                *total += amount;
+                                ids.push(cost.id);
+                            }
+                            None => earnings.push((employee_id, amount, vec![cost.id])),
+                        }
+                    }
+                    earnings.retain(|(_, amount, _)| *amount > 0);
+                    if earnings.is_empty() {
+                        return Ok(None);
+                    }
+
+                    let batch =
+                        Repository::save_payout_batch(conn, PayoutBatchStatus::PROCESSING.as_str())
+                            .await?;
+                    let mut payouts = Vec::with_capacity(earnings.len());
+                    for (employee_id, amount_cents, cost_ids) in earnings {
+                        let payout = Repository::save_payout(
+                            conn,
+                            &NewPayout {
+                                batch_id: batch.id,
+                                employee_id,
+                                status: PayoutStatus::PENDING.as_str().to_string(),
+                                amount_cents,
+                                provider: provider.to_string(),
+                            },
+                        )
+                        .await?;
+                        Repository::set_transactions_payout(conn, &cost_ids, &payout.id).await?;
+                        payouts.push(payout);
+                    }
+                    Ok(Some((batch, payouts)))
+                }
+                .scope_boxed()
+            })
+            .await?;
+        let Some((batch, payouts)) = created else {
+            return Ok(None);
+        };
+
+        // Money moves outside of any transaction; each outcome is saved as
+        // soon as it is known. One that cannot be saved leaves its payout
+        // PENDING for the next cycle to settle.
+        let mut paid_count = 0;
+        let mut paid_cents = 0;
+        for payout in payouts {
+            let sent = payments
+                .send_payout(&billing::payout_instruction(&payout))
+                .await;
+            if sent.is_ok() {
+                paid_count += 1;
+                paid_cents += payout.amount_cents;
+            }
+            let recorded = conn
+                .transaction::<_, diesel::result::Error, _>(|conn| {
+                    async move { billing::record_payout_outcome(conn, payout.id, sent).await }
+                        .scope_boxed()
+                })
+                .await;
+            if let Err(e) = recorded {
+                tracing::error!("Unable to record the outcome of payout #{}: {e}", payout.id);
+            }
+        }
+
+        let batch = Repository::update_payout_batch(
+            conn,
+            &batch.id,
+            &PayoutBatchTotals {
+                status: PayoutBatchStatus::COMPLETED.as_str().to_string(),
+                payout_count: paid_count,
+                total_cents: paid_cents,
+                completed_at: Some(Utc::now().naive_utc()),
+            },
+        )
+        .await?;
+        Ok(Some(batch))
+    }
+
+    /// Settles payouts still PENDING since before `cutoff`, which happens
+    /// when a run stopped between sending a payout and saving the outcome.
+    /// Each is sent again under its reference, which the provider pays at
+    /// most once, and then marked paid or failed like in a batch. Returns
+    /// how many were settled.
+    pub async fn settle_stale_payouts(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        payments: &Payments,
+        cutoff: NaiveDateTime,
+    ) -> Result<usize, diesel::result::Error> {
+        let stale =
+            Repository::find_stale_pending_payouts(conn, cutoff, STALE_PAYOUT_BATCH).await?;
+
+        let mut settled = 0;
+        for payout in stale {
+            let sent = payments
+                .send_payout(&billing::payout_instruction(&payout))
+                .await;
+            let recorded = conn
+                .transaction::<_, diesel::result::Error, _>(|conn| {
+                    async move { billing::record_payout_outcome(conn, payout.id, sent).await }
+                        .scope_boxed()
+                })
+                .await;
+            match recorded {
+                Ok(()) => settled += 1,
+                Err(e) => {
+                    tracing::error!("Unable to settle payout #{}: {e}", payout.id)
+                }
+            }
+        }
+        Ok(settled)
+    }
+
+    pub async fn list_company_invoices(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        company_id: i64,
+    ) -> Result<Vec<Invoice>, diesel::result::Error> {
+        Repository::find_invoices_for_company(conn, &company_id).await
+    }
+
+    pub async fn find_company_invoice(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        invoice_id: i64,
+        company_id: i64,
+    ) -> Result<InvoiceWithLines, diesel::result::Error> {
+        let invoice = Repository::find_invoice_for_company(conn, &invoice_id, &company_id).await?;
+        let lines = Repository::find_invoice_lines(conn, &invoice.id).await?;
+        Ok(InvoiceWithLines { invoice, lines })
+    }
+
+    pub async fn list_employee_payouts(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        employee_id: i64,
+    ) -> Result<Vec<Payout>, diesel::result::Error> {
+        Repository::find_payouts_for_employee(conn, &employee_id).await
+    }
+
+    pub async fn list_payout_batches(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+    ) -> Result<Vec<PayoutBatch>, diesel::result::Error> {
+        Repository::find_payout_batches(conn).await
+    }
+
+    pub async fn find_payout_batch(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        batch_id: i64,
+    ) -> Result<PayoutBatchWithPayouts, diesel::result::Error> {
+        let batch = Repository::find_payout_batch(conn, &batch_id).await?;
+        let payouts = Repository::find_payouts_for_batch(conn, &batch.id).await?;
+        Ok(PayoutBatchWithPayouts { batch, payouts })
+    }
+
+    /// Stores an event for each of `user_ids` and returns its sequence number.
+    pub async fn record_ws_event(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        body: String,
+        user_ids: &[i64],
+    ) -> Result<i64, diesel::result::Error> {
+        let user_ids = user_ids.to_vec();
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let seq = Repository::save_ws_event(conn, &NewWsEvent { body }).await?;
+                let recipients: Vec<NewWsEventRecipient> = user_ids
+                    .iter()
+                    .map(|&user_id| NewWsEventRecipient {
+                        user_id,
+                        event_seq: seq,
+                    })
+                    .collect();
+                Repository::save_ws_event_recipients(conn, &recipients).await?;
+                Ok(seq)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn find_missed_ws_events(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        user_id: i64,
+        last_seen_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<WsEvent>, diesel::result::Error> {
+        Repository::find_ws_events_for_user_after(conn, &user_id, &last_seen_seq, limit).await
+    }
+
+    /// The stored event and which of `user_ids` it is addressed to, or
+    /// `None` when it concerns none of them.
+    pub async fn find_ws_event_for_users(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        seq: i64,
+        user_ids: &[i64],
+    ) -> Result<Option<(WsEvent, Vec<i64>)>, diesel::result::Error> {
+        let recipients = Repository::find_ws_event_recipients_among(conn, &seq, user_ids).await?;
+        if recipients.is_empty() {
+            return Ok(None);
+        }
+        let event = Repository::find_ws_event(conn, &seq).await?;
+        Ok(Some((event, recipients)))
+    }
+
+    pub async fn purge_ws_events(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        cutoff: NaiveDateTime,
+    ) -> Result<usize, diesel::result::Error> {
+        Repository::delete_ws_events_before(conn, cutoff).await
     }
 }
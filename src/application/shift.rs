@@ -0,0 +1,227 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel_async::{
+    pooled_connection::AsyncDieselConnectionManager, scoped_futures::ScopedFutureExt,
+    AsyncConnection, AsyncPgConnection,
+};
+use serde_json::json;
+
+use crate::{
+    application::{error::AppError, service::Service},
+    domain::{
+        enums::JobStatus,
+        models::{NewShift, ShiftCheckOut, ShiftLocation, ShiftReport},
+    },
+    infrastructure::repositories::Repository,
+};
+
+const DEFAULT_CHECK_IN_RADIUS_METERS: f64 = 200.0;
+
+/// Rules for checking in and out of shifts.
+#[derive(Clone, Debug)]
+pub struct ShiftConfig {
+    /// How far from the job a worker may be when checking in.
+    pub check_in_radius_km: f64,
+}
+
+impl ShiftConfig {
+    /// Reads the check-in radius from `SHIFT_CHECK_IN_RADIUS_METERS`, falling
+    /// back to 200 m when it is unset or not a positive number.
+    pub fn from_env() -> Self {
+        let radius_meters = std::env::var("SHIFT_CHECK_IN_RADIUS_METERS")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|radius| *radius > 0.0)
+            .unwrap_or(DEFAULT_CHECK_IN_RADIUS_METERS);
+
+        Self {
+            check_in_radius_km: radius_meters / 1000.0,
+        }
+    }
+}
+
+/// Hours between check-in and check-out, to the hundredth.
+pub fn worked_hours(check_in_at: NaiveDateTime, check_out_at: NaiveDateTime) -> f64 {
+    let seconds = (check_out_at - check_in_at).num_seconds().max(0);
+    (seconds as f64 / 36.0).round() / 100.0
+}
+
+pub enum ShiftError {
+    Database(diesel::result::Error),
+    /// The employee has no accepted application to the job.
+    NotAssigned,
+    JobNotActive(JobStatus),
+    TooFar {
+        distance_km: f64,
+        radius_km: f64,
+    },
+    AlreadyCheckedIn,
+    NotCheckedIn,
+    AlreadyCheckedOut,
+}
+
+impl From<diesel::result::Error> for ShiftError {
+    fn from(err: diesel::result::Error) -> Self {
+        ShiftError::Database(err)
+    }
+}
+
+impl From<ShiftError> for AppError {
+    fn from(err: ShiftError) -> Self {
+        match err {
+            ShiftError::Database(e) => e.into(),
+            ShiftError::NotAssigned => AppError::forbidden("You are not assigned to this job"),
+            ShiftError::JobNotActive(status) => AppError::Conflict {
+                message: "Job is no longer active".to_string(),
+                details: Some(json!({ "status": status })),
+            },
+            ShiftError::TooFar {
+                distance_km,
+                radius_km,
+            } => AppError::Validation {
+                message: "Too far from the job to check in".to_string(),
+                details: Some(json!({
+                    "distance_meters": (distance_km * 1000.0).round(),
+                    "max_distance_meters": (radius_km * 1000.0).round(),
+                })),
+            },
+            ShiftError::AlreadyCheckedIn => AppError::conflict("Already checked in"),
+            ShiftError::NotCheckedIn => AppError::conflict("Not checked in"),
+            ShiftError::AlreadyCheckedOut => AppError::conflict("Already checked out"),
+        }
+    }
+}
+
+impl Service {
+    /// Starts the employee's shift on a job they were accepted for, provided
+    /// they are close enough to it.
+    pub async fn check_in(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        config: &ShiftConfig,
+        job_id: i64,
+        employee_id: i64,
+        location: ShiftLocation,
+    ) -> Result<ShiftReport, ShiftError> {
+        let radius_km = config.check_in_radius_km;
+        conn.transaction::<_, ShiftError, _>(|conn| {
+            async move {
+                let job = Repository::find_job_opportunity(conn, &job_id).await?;
+                Repository::find_accepted_job_application(conn, &job_id, &employee_id)
+                    .await?
+                    .ok_or(ShiftError::NotAssigned)?;
+                if matches!(job.status, JobStatus::COMPLETED | JobStatus::CANCELLED) {
+                    return Err(ShiftError::JobNotActive(job.status));
+                }
+                if Repository::find_shift_for_update(conn, &job_id, &employee_id)
+                    .await?
+                    .is_some()
+                {
+                    return Err(ShiftError::AlreadyCheckedIn);
+                }
+
+                let distance_km = Repository::find_distance_to_job_km(
+                    conn,
+                    &job_id,
+                    location.latitude,
+                    location.longitude,
+                )
+                .await?;
+                if distance_km > radius_km {
+                    return Err(ShiftError::TooFar {
+                        distance_km,
+                        radius_km,
+                    });
+                }
+
+                let shift = Repository::save_shift(
+                    conn,
+                    &NewShift {
+                        job_id,
+                        employee_id,
+                        check_in_at: Utc::now().naive_utc(),
+                        check_in_latitude: location.latitude,
+                        check_in_longitude: location.longitude,
+                        check_in_distance_km: distance_km,
+                    },
+                )
+                .await?;
+                Ok(ShiftReport::new(shift, &job))
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Ends the employee's shift and works out the hours. Where they check
+    /// out from is recorded but not enforced; workers may have to leave in
+    /// a hurry.
+    pub async fn check_out(
+        conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+        job_id: i64,
+        employee_id: i64,
+        location: ShiftLocation,
+    ) -> Result<ShiftReport, ShiftError> {
+        conn.transaction::<_, ShiftError, _>(|conn| {
+            async move {
+                let job = Repository::find_job_opportunity(conn, &job_id).await?;
+                let shift = Repository::find_shift_for_update(conn, &job_id, &employee_id)
+                    .await?
+                    .ok_or(ShiftError::NotCheckedIn)?;
+                if shift.check_out_at.is_some() {
+                    return Err(ShiftError::AlreadyCheckedOut);
+                }
+
+                let distance_km = Repository::find_distance_to_job_km(
+                    conn,
+                    &job_id,
+                    location.latitude,
+                    location.longitude,
+                )
+                .await?;
+                let check_out_at = Utc::now().naive_utc();
+                let shift = Repository::update_shift_check_out(
+                    conn,
+                    &shift.id,
+                    &ShiftCheckOut {
+                        check_out_at,
+                        check_out_latitude: location.latitude,
+                        check_out_longitude: location.longitude,
+                        check_out_distance_km: distance_km,
+                        worked_hours: worked_hours(shift.check_in_at, check_out_at),
+                    },
+                )
+                .await?;
+                Ok(ShiftReport::new(shift, &job))
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 4, 14)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn worked_hours_round_to_the_hundredth() {
+        assert_eq!(worked_hours(at(9, 0, 0), at(17, 0, 0)), 8.0);
+        assert_eq!(worked_hours(at(9, 0, 0), at(10, 20, 0)), 1.33);
+        assert_eq!(worked_hours(at(9, 0, 0), at(9, 1, 30)), 0.03);
+        assert_eq!(worked_hours(at(9, 0, 0), at(16, 59, 59)), 8.0);
+    }
+
+    #[test]
+    fn check_out_before_check_in_counts_as_no_work() {
+        assert_eq!(worked_hours(at(17, 0, 0), at(9, 0, 0)), 0.0);
+        assert_eq!(worked_hours(at(9, 0, 0), at(9, 0, 0)), 0.0);
+    }
+}
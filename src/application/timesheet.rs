@@ -0,0 +1,201 @@
+use chrono::{Duration, Utc};
+use diesel_async::{pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection};
+use serde_json::json;
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    application::{
+        billing::{self, BillingConfig},
+        error::AppError,
+        service::Service,
+    },
+    domain::{
+        enums::{JobStatus, TimesheetAction, TimesheetStatus},
+        models::{NewTimesheetEvent, Timesheet, TimesheetDecision},
+    },
+    infrastructure::repositories::Repository,
+};
+
+const DEFAULT_AUTO_APPROVE_HOURS: i64 = 72;
+const AUTO_APPROVAL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// Rules for reviewing timesheets.
+#[derive(Clone, Debug)]
+pub struct TimesheetConfig {
+    /// How long a company has to review a timesheet before it is approved
+    /// as submitted.
+    pub auto_approve_after: Duration,
+}
+
+impl TimesheetConfig {
+    /// Reads the review window from `TIMESHEET_AUTO_APPROVE_HOURS`, falling
+    /// back to 72 hours when it is unset or not a positive number.
+    pub fn from_env() -> Self {
+        let hours = std::env::var("TIMESHEET_AUTO_APPROVE_HOURS")
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .filter(|hours| *hours > 0)
+            .unwrap_or(DEFAULT_AUTO_APPROVE_HOURS);
+
+        Self {
+            auto_approve_after: Duration::hours(hours),
+        }
+    }
+
+    /// Periodically approve timesheets left unreviewed past the window.
+    pub fn spawn_auto_approval(
+        &self,
+        pool: bb8::Pool<AsyncDieselConnectionManager<AsyncPgConnection>>,
+        billing: BillingConfig,
+    ) {
+        let window = self.auto_approve_after;
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(AUTO_APPROVAL_INTERVAL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let cutoff = chrono::Utc::now().naive_utc() - window;
+                let mut conn = match pool.get().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        tracing::error!("Unable to auto-approve timesheets: {e}");
+                        continue;
+                    }
+                };
+                match Service::auto_approve_timesheets(&mut conn, &billing, cutoff).await {
+                    Ok(0) => {}
+                    Ok(approved) => tracing::info!(
+                        "Auto-approved {approved} timesheets submitted before {cutoff}"
+                    ),
+                    Err(e) => tracing::error!("Unable to auto-approve timesheets: {e}"),
+                }
+            }
+        });
+    }
+}
+
+/// Claimed hours less breaks, to the hundredth.
+pub fn net_hours(hours: f64, break_minutes: i32) -> f64 {
+    ((hours - f64::from(break_minutes) / 60.0) * 100.0).round() / 100.0
+}
+
+/// What approved hours are worth at `pay_rate`, to the cent.
+pub fn payable_amount(pay_rate: f64, hours: f64) -> f64 {
+    (pay_rate * hours * 100.0).round() / 100.0
+}
+
+pub enum TimesheetError {
+    Database(diesel::result::Error),
+    /// There is no finished shift on the job to claim hours for.
+    NotCheckedOut,
+    BreakTooLong,
+    AlreadyApproved,
+    /// Only submitted timesheets can be approved or disputed.
+    NotSubmitted(String),
+}
+
+impl From<diesel::result::Error> for TimesheetError {
+    fn from(err: diesel::result::Error) -> Self {
+        TimesheetError::Database(err)
+    }
+}
+
+impl From<TimesheetError> for AppError {
+    fn from(err: TimesheetError) -> Self {
+        match err {
+            TimesheetError::Database(e) => e.into(),
+            TimesheetError::NotCheckedOut => {
+                AppError::conflict("Check out of the shift before submitting a timesheet")
+            }
+            TimesheetError::BreakTooLong => AppError::Validation {
+                message: "Validation failed".to_string(),
+                details: Some(json!({
+                    "fields": { "break_minutes": ["must be shorter than the hours worked"] }
+                })),
+            },
+            TimesheetError::AlreadyApproved => AppError::conflict("Timesheet is already approved"),
+            TimesheetError::NotSubmitted(status) => AppError::Conflict {
+                message: "Timesheet is not awaiting review".to_string(),
+                details: Some(json!({ "status": status })),
+            },
+        }
+    }
+}
+
+/// Approves a locked, submitted timesheet for its net hours and records who
+/// did it; `decided_by` is `None` for API keys and the auto-approval sweep.
+/// Approvals after the job completed are charged right away.
+pub(crate) async fn approve(
+    conn: &mut AsyncPgConnection,
+    billing: &BillingConfig,
+    timesheet: &Timesheet,
+    decided_by: Option<i64>,
+    auto_approved: bool,
+) -> Result<Timesheet, diesel::result::Error> {
+    let approved_hours = timesheet.net_hours;
+    let approved = Repository::update_timesheet_decision(
+        conn,
+        &timesheet.id,
+        &TimesheetDecision {
+            status: TimesheetStatus::APPROVED.as_str().to_string(),
+            dispute_reason: None,
+            approved_hours: Some(approved_hours),
+            payable_amount: Some(payable_amount(timesheet.pay_rate, approved_hours)),
+            decided_at: Utc::now().naive_utc(),
+            decided_by,
+            auto_approved,
+        },
+    )
+    .await?;
+
+    let action = if auto_approved {
+        TimesheetAction::AutoApproved
+    } else {
+        TimesheetAction::Approved
+    };
+    Repository::save_timesheet_event(
+        conn,
+        &NewTimesheetEvent {
+            timesheet_id: approved.id,
+            action: action.as_str().to_string(),
+            actor_user_id: decided_by,
+            net_hours: approved_hours,
+            note: None,
+        },
+    )
+    .await?;
+
+    let job = Repository::find_job_opportunity_for_share(conn, &approved.job_id).await?;
+    if job.status == JobStatus::COMPLETED {
+        if let Some(company_id) = job.company_id {
+            billing::post_timesheet_cost(conn, billing, &approved, company_id).await?;
+        }
+    }
+    Ok(approved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn net_hours_subtract_breaks_to_the_hundredth() {
+        assert_eq!(net_hours(8.0, 0), 8.0);
+        assert_eq!(net_hours(8.0, 30), 7.5);
+        assert_eq!(net_hours(8.0, 20), 7.67);
+        assert_eq!(net_hours(8.0, 40), 7.33);
+        assert_eq!(net_hours(7.333, 0), 7.33);
+        assert_eq!(net_hours(7.999, 0), 8.0);
+        assert_eq!(net_hours(0.5, 10), 0.33);
+    }
+
+    #[test]
+    fn payable_amount_rounds_to_the_cent() {
+        assert_eq!(payable_amount(15.25, 8.0), 122.0);
+        assert_eq!(payable_amount(20.0, 7.67), 153.4);
+        assert_eq!(payable_amount(12.5, 7.33), 91.63);
+        assert_eq!(payable_amount(19.99, 0.01), 0.2);
+        assert_eq!(payable_amount(10.0, 0.0), 0.0);
+    }
+}
@@ -1,9 +1,389 @@
+#![allow(clippy::upper_case_acronyms)]
+
+use diesel_derive_enum::DbEnum;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, DbEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[ExistingTypePath = "crate::infrastructure::schema::sql_types::JobStatus"]
+#[DbValueStyle = "verbatim"]
 pub enum JobStatus {
+    #[default]
     OPEN,
     PENDING,
     COMPLETED,
     CANCELLED,
 }
+
+impl JobStatus {
+    /// Whether the job lifecycle allows moving from `self` to `next`.
+    ///
+    /// OPEN -> PENDING once every slot is filled, PENDING -> OPEN when an
+    /// assigned worker drops out, OPEN or PENDING -> COMPLETED when the shift
+    /// is done, and any non-final status may be CANCELLED.
+    pub fn can_transition_to(&self, next: JobStatus) -> bool {
+        matches!(
+            (self, next),
+            (JobStatus::OPEN, JobStatus::PENDING)
+                | (JobStatus::OPEN, JobStatus::COMPLETED)
+                | (JobStatus::OPEN, JobStatus::CANCELLED)
+                | (JobStatus::PENDING, JobStatus::OPEN)
+                | (JobStatus::PENDING, JobStatus::COMPLETED)
+                | (JobStatus::PENDING, JobStatus::CANCELLED)
+        )
+    }
+
+    pub fn transition_to(self, next: JobStatus) -> Result<JobStatus, InvalidJobTransition> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(InvalidJobTransition {
+                from: self,
+                to: next,
+            })
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct InvalidJobTransition {
+    pub from: JobStatus,
+    pub to: JobStatus,
+}
+
+impl fmt::Display for InvalidJobTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "job cannot move from {:?} to {:?}", self.from, self.to)
+    }
+}
+
+impl std::error::Error for InvalidJobTransition {}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum ApplicationStatus {
+    PENDING,
+    ACCEPTED,
+    REJECTED,
+    /// The applicant pulled out, freeing their slot if they had one.
+    WITHDRAWN,
+}
+
+impl ApplicationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApplicationStatus::PENDING => "PENDING",
+            ApplicationStatus::ACCEPTED => "ACCEPTED",
+            ApplicationStatus::REJECTED => "REJECTED",
+            ApplicationStatus::WITHDRAWN => "WITHDRAWN",
+        }
+    }
+}
+
+/// What a user may act as, as carried in token claims.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Employee,
+    CompanyAdmin,
+    CompanyMember,
+    PlatformAdmin,
+    /// A company API key rather than a person, see [`ApiKeyScope`].
+    ApiClient,
+}
+
+/// A user's standing within the company they belong to.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CompanyRole {
+    ADMIN,
+    #[default]
+    MEMBER,
+}
+
+impl CompanyRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompanyRole::ADMIN => "ADMIN",
+            CompanyRole::MEMBER => "MEMBER",
+        }
+    }
+
+    /// Reads a role as stored; anything unknown gets the least privileges.
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "ADMIN" => CompanyRole::ADMIN,
+            _ => CompanyRole::MEMBER,
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        match self {
+            CompanyRole::ADMIN => Role::CompanyAdmin,
+            CompanyRole::MEMBER => Role::CompanyMember,
+        }
+    }
+}
+
+/// What a mailed account token may be used for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccountTokenPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+impl AccountTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountTokenPurpose::PasswordReset => "PASSWORD_RESET",
+            AccountTokenPurpose::EmailVerification => "EMAIL_VERIFICATION",
+        }
+    }
+}
+
+/// Why a login was turned down, as kept in the audit trail.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FailedLoginReason {
+    InvalidCredentials,
+    /// Right password, wrong second factor.
+    InvalidMfaCode,
+    /// Refused without checking the password while backing off or locked.
+    Throttled,
+}
+
+impl FailedLoginReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailedLoginReason::InvalidCredentials => "INVALID_CREDENTIALS",
+            FailedLoginReason::InvalidMfaCode => "INVALID_MFA_CODE",
+            FailedLoginReason::Throttled => "THROTTLED",
+        }
+    }
+}
+
+/// What a company API key may be used for.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ApiKeyScope {
+    #[serde(rename = "company:read")]
+    CompanyRead,
+    #[serde(rename = "jobs:write")]
+    JobsWrite,
+    #[serde(rename = "applications:read")]
+    ApplicationsRead,
+    #[serde(rename = "applications:write")]
+    ApplicationsWrite,
+    #[serde(rename = "timesheets:read")]
+    TimesheetsRead,
+    #[serde(rename = "timesheets:write")]
+    TimesheetsWrite,
+    #[serde(rename = "invoices:read")]
+    InvoicesRead,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::CompanyRead => "company:read",
+            ApiKeyScope::JobsWrite => "jobs:write",
+            ApiKeyScope::ApplicationsRead => "applications:read",
+            ApiKeyScope::ApplicationsWrite => "applications:write",
+            ApiKeyScope::TimesheetsRead => "timesheets:read",
+            ApiKeyScope::TimesheetsWrite => "timesheets:write",
+            ApiKeyScope::InvoicesRead => "invoices:read",
+        }
+    }
+}
+
+/// Where a timesheet is in review. Disputed ones go back to the worker, who
+/// can correct and resubmit them.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum TimesheetStatus {
+    SUBMITTED,
+    APPROVED,
+    DISPUTED,
+}
+
+impl TimesheetStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimesheetStatus::SUBMITTED => "SUBMITTED",
+            TimesheetStatus::APPROVED => "APPROVED",
+            TimesheetStatus::DISPUTED => "DISPUTED",
+        }
+    }
+}
+
+/// What happened to a timesheet, as kept in its audit trail.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimesheetAction {
+    Submitted,
+    Resubmitted,
+    Approved,
+    AutoApproved,
+    Disputed,
+}
+
+impl TimesheetAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimesheetAction::Submitted => "SUBMITTED",
+            TimesheetAction::Resubmitted => "RESUBMITTED",
+            TimesheetAction::Approved => "APPROVED",
+            TimesheetAction::AutoApproved => "AUTO_APPROVED",
+            TimesheetAction::Disputed => "DISPUTED",
+        }
+    }
+}
+
+/// Who reviewed whom.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ReviewDirection {
+    CompanyToEmployee,
+    EmployeeToCompany,
+}
+
+impl ReviewDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewDirection::CompanyToEmployee => "COMPANY_TO_EMPLOYEE",
+            ReviewDirection::EmployeeToCompany => "EMPLOYEE_TO_COMPANY",
+        }
+    }
+}
+
+/// Accounts of the double-entry ledger, as seen from the platform.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LedgerAccount {
+    /// What a company owes for the work done on its jobs.
+    CompanyPayable,
+    /// What the platform owes an employee until it pays them out.
+    EmployeeEarnings,
+    PlatformFees,
+    /// Money leaving the platform through the payment provider.
+    PlatformCash,
+}
+
+impl LedgerAccount {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedgerAccount::CompanyPayable => "COMPANY_PAYABLE",
+            LedgerAccount::EmployeeEarnings => "EMPLOYEE_EARNINGS",
+            LedgerAccount::PlatformFees => "PLATFORM_FEES",
+            LedgerAccount::PlatformCash => "PLATFORM_CASH",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LedgerSide {
+    Debit,
+    Credit,
+}
+
+impl LedgerSide {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedgerSide::Debit => "DEBIT",
+            LedgerSide::Credit => "CREDIT",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LedgerTransactionKind {
+    /// The cost of an approved timesheet on a completed job.
+    JobCost,
+    Payout,
+}
+
+impl LedgerTransactionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedgerTransactionKind::JobCost => "JOB_COST",
+            LedgerTransactionKind::Payout => "PAYOUT",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum PayoutStatus {
+    PENDING,
+    PAID,
+    FAILED,
+}
+
+impl PayoutStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayoutStatus::PENDING => "PENDING",
+            PayoutStatus::PAID => "PAID",
+            PayoutStatus::FAILED => "FAILED",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum PayoutBatchStatus {
+    PROCESSING,
+    COMPLETED,
+}
+
+impl PayoutBatchStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayoutBatchStatus::PROCESSING => "PROCESSING",
+            PayoutBatchStatus::COMPLETED => "COMPLETED",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [JobStatus; 4] = [
+        JobStatus::OPEN,
+        JobStatus::PENDING,
+        JobStatus::COMPLETED,
+        JobStatus::CANCELLED,
+    ];
+
+    #[test]
+    fn job_transitions_follow_the_lifecycle() {
+        use JobStatus::*;
+        let allowed = [
+            (OPEN, PENDING),
+            (OPEN, COMPLETED),
+            (OPEN, CANCELLED),
+            (PENDING, OPEN),
+            (PENDING, COMPLETED),
+            (PENDING, CANCELLED),
+        ];
+
+        for from in ALL {
+            for to in ALL {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    allowed.contains(&(from, to)),
+                    "{from:?} -> {to:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn final_statuses_cannot_move() {
+        for from in [JobStatus::COMPLETED, JobStatus::CANCELLED] {
+            for to in ALL {
+                let err = from.transition_to(to).unwrap_err();
+                assert_eq!((err.from, err.to), (from, to));
+            }
+        }
+    }
+
+    #[test]
+    fn transition_to_returns_the_next_status() {
+        assert_eq!(
+            JobStatus::OPEN.transition_to(JobStatus::PENDING).unwrap(),
+            JobStatus::PENDING
+        );
+    }
+}
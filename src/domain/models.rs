@@ -1,6 +1,9 @@
+use crate::domain::enums::{ApiKeyScope, CompanyRole, JobStatus};
 use crate::infrastructure::schema::*;
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 #[derive(Deserialize, Serialize, Queryable, Selectable, Identifiable, AsChangeset)]
 #[diesel(table_name = employees)]
@@ -15,7 +18,10 @@ pub struct Employee {
     pub is_available: bool,
     pub latitude: f64,
     pub longitude: f64,
+    /// Worked out from company reviews, see `ReviewConfig`.
     pub rating: f64,
+    #[serde(default)]
+    pub review_count: i32,
 }
 
 #[derive(Deserialize, Insertable, Queryable, Clone)]
@@ -42,6 +48,11 @@ pub struct Company {
     pub description: String,
     pub address: String,
     pub logo_url: String,
+    /// Worked out from worker reviews, see `ReviewConfig`.
+    #[serde(default)]
+    pub rating: f64,
+    #[serde(default)]
+    pub review_count: i32,
 }
 
 #[derive(Deserialize, Insertable, Queryable, Clone)]
@@ -60,7 +71,11 @@ pub struct User {
     pub login: String,
     pub password: String,
     pub companyid: Option<i64>,
-    pub employeeid: Option<i64>
+    pub employeeid: Option<i64>,
+    #[serde(default)]
+    pub is_platform_admin: bool,
+    pub email: Option<String>,
+    pub email_verified_at: Option<NaiveDateTime>,
 }
 
 #[derive(Deserialize, Insertable, Queryable, Clone)]
@@ -68,9 +83,11 @@ pub struct User {
 pub struct NewUser {
     pub login: String,
     pub password: String,
+    /// Where password resets are sent once verified.
+    pub email: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Queryable, Selectable, Identifiable, AsChangeset)]
+#[derive(Deserialize, Serialize, Queryable, Selectable, Identifiable, AsChangeset, Clone)]
 #[diesel(table_name = job_opportunities)]
 pub struct JobOpportunity {
     pub id: i64,
@@ -83,8 +100,16 @@ pub struct JobOpportunity {
     pub start_date_time: String,
     pub duration_in_hours: i32,
     pub pay_rate: f64,
-    pub status: String,
+    pub status: JobStatus,
     pub company_id: Option<i64>,
+    /// How many workers the job needs.
+    #[serde(default)]
+    pub headcount: i32,
+    /// Slots taken by accepted applications.
+    #[serde(default)]
+    pub assigned_slots: i32,
+    #[serde(default)]
+    pub remaining_slots: i32,
 }
 
 
@@ -101,7 +126,14 @@ pub struct NewJobOpportunity {
     pub start_date_time: String,
     pub duration_in_hours: i32,
     pub pay_rate: f64,
-    pub status: String,
+    #[serde(skip_deserializing)]
+    pub status: JobStatus,
+    #[serde(default = "one_worker")]
+    pub headcount: i32,
+}
+
+fn one_worker() -> i32 {
+    1
 }
 
 #[derive(Serialize)]
@@ -116,8 +148,681 @@ pub struct JobOpportunityWithCompany {
     pub start_date_time: String,
     pub duration_in_hours: i32,
     pub pay_rate: f64,
-    pub status: String,
+    pub status: JobStatus,
     pub company_id: Option<i64>,
+    pub headcount: i32,
+    pub assigned_slots: i32,
+    pub remaining_slots: i32,
     pub company_name: Option<String>,
-    pub company_logo_url: Option<String>
-}
\ No newline at end of file
+    pub company_logo_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance_km: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JobSearchQuery {
+    pub lat: f64,
+    pub lng: f64,
+    pub radius_km: f64,
+    pub category: Option<String>,
+    pub min_pay: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Queryable, Selectable, Identifiable, AsChangeset, Debug)]
+#[diesel(table_name = job_applications)]
+pub struct JobApplication {
+    pub id: i64,
+    pub job_id: i64,
+    pub employee_id: i64,
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = job_applications)]
+pub struct NewJobApplication {
+    pub job_id: i64,
+    pub employee_id: i64,
+    pub status: String,
+}
+
+#[derive(Serialize)]
+pub struct JobApplicationWithEmployee {
+    pub id: i64,
+    pub job_id: i64,
+    pub employee_id: i64,
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub employee_full_name: String,
+    pub employee_phone: String,
+    pub employee_rating: f64,
+}
+
+#[derive(Deserialize)]
+pub struct NewChatMessage {
+    pub text: String,
+}
+
+/// An event pushed to users over WebSocket, kept so reconnecting clients can
+/// catch up on what they missed. `body` is the encoded `ServerEvent`.
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = ws_events)]
+pub struct WsEvent {
+    pub seq: i64,
+    pub body: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ws_events)]
+pub struct NewWsEvent {
+    pub body: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ws_event_recipients)]
+pub struct NewWsEventRecipient {
+    pub user_id: i64,
+    pub event_seq: i64,
+}
+
+/// A refresh token, stored by hash. Tokens rotated from the same login share
+/// a `family_id`, which access tokens carry as their session id.
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = refresh_tokens)]
+pub struct RefreshToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub family_id: Uuid,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = refresh_tokens)]
+pub struct NewRefreshToken {
+    pub user_id: i64,
+    pub family_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Queryable, Selectable, Debug)]
+#[diesel(table_name = company_members)]
+pub struct CompanyMember {
+    pub id: i64,
+    pub company_id: i64,
+    pub user_id: i64,
+    pub role: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = company_members)]
+pub struct NewCompanyMember {
+    pub company_id: i64,
+    pub user_id: i64,
+    pub role: String,
+}
+
+#[derive(Serialize)]
+pub struct CompanyMemberWithLogin {
+    pub user_id: i64,
+    pub login: String,
+    pub role: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = company_invitations)]
+pub struct CompanyInvitation {
+    pub id: i64,
+    pub company_id: i64,
+    pub role: String,
+    pub invited_login: Option<String>,
+    pub expires_at: NaiveDateTime,
+    pub accepted_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = company_invitations)]
+pub struct NewCompanyInvitation {
+    pub company_id: i64,
+    pub role: String,
+    pub invited_login: Option<String>,
+    pub token_hash: String,
+    pub invited_by: i64,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct InviteMemberRequest {
+    #[serde(default)]
+    pub role: CompanyRole,
+    /// Restricts the invitation to this login.
+    pub login: Option<String>,
+}
+
+/// Returned once when inviting; only the token's hash is kept.
+#[derive(Serialize)]
+pub struct CompanyInvitationToken {
+    pub token: String,
+    pub role: CompanyRole,
+    pub expires_at: NaiveDateTime,
+}
+
+/// A single-use token mailed to a user, see [`AccountTokenPurpose`].
+///
+/// [`AccountTokenPurpose`]: crate::domain::enums::AccountTokenPurpose
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = account_tokens)]
+pub struct AccountToken {
+    pub user_id: i64,
+    pub email: Option<String>,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = account_tokens)]
+pub struct NewAccountToken {
+    pub user_id: i64,
+    pub purpose: String,
+    pub token_hash: String,
+    pub email: Option<String>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+/// Failed login counter for one throttle key, see `LoginThrottleConfig`.
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Debug)]
+#[diesel(table_name = login_throttles)]
+#[diesel(treat_none_as_null = true)]
+pub struct LoginThrottle {
+    pub key: String,
+    pub failures: i32,
+    pub last_failure_at: NaiveDateTime,
+    pub blocked_until: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = failed_logins)]
+pub struct NewFailedLogin {
+    pub login: String,
+    pub ip: String,
+    pub user_id: Option<i64>,
+    pub reason: String,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = user_mfa)]
+pub struct UserMfa {
+    pub secret: String,
+    pub confirmed_at: Option<NaiveDateTime>,
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = user_mfa)]
+pub struct NewUserMfa {
+    pub user_id: i64,
+    pub secret: String,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = mfa_recovery_codes)]
+pub struct MfaRecoveryCode {
+    pub id: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = mfa_recovery_codes)]
+pub struct NewMfaRecoveryCode {
+    pub user_id: i64,
+    pub code_hash: String,
+}
+
+/// What an authenticator app needs to start generating codes.
+#[derive(Serialize)]
+pub struct MfaEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// Shown once, when two-factor authentication is turned on.
+#[derive(Serialize)]
+pub struct MfaRecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// A TOTP code or one of the recovery codes.
+#[derive(Deserialize)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+/// A company API key as listed; the key itself is only shown on creation.
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = company_api_keys)]
+pub struct CompanyApiKey {
+    pub id: i64,
+    #[serde(skip)]
+    pub company_id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = company_api_keys)]
+pub struct NewCompanyApiKey {
+    pub company_id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub created_by: i64,
+}
+
+#[derive(Deserialize)]
+pub struct NewApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: CompanyApiKey,
+    /// Sent as `X-API-Key`; never shown again.
+    pub key: String,
+}
+
+/// A worker's attendance on a job, from check-in to check-out.
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = shifts)]
+pub struct Shift {
+    pub id: i64,
+    pub job_id: i64,
+    pub employee_id: i64,
+    pub check_in_at: NaiveDateTime,
+    pub check_in_latitude: f64,
+    pub check_in_longitude: f64,
+    pub check_in_distance_km: f64,
+    pub check_out_at: Option<NaiveDateTime>,
+    pub check_out_latitude: Option<f64>,
+    pub check_out_longitude: Option<f64>,
+    pub check_out_distance_km: Option<f64>,
+    pub worked_hours: Option<f64>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = shifts)]
+pub struct NewShift {
+    pub job_id: i64,
+    pub employee_id: i64,
+    pub check_in_at: NaiveDateTime,
+    pub check_in_latitude: f64,
+    pub check_in_longitude: f64,
+    pub check_in_distance_km: f64,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = shifts)]
+pub struct ShiftCheckOut {
+    pub check_out_at: NaiveDateTime,
+    pub check_out_latitude: f64,
+    pub check_out_longitude: f64,
+    pub check_out_distance_km: f64,
+    pub worked_hours: f64,
+}
+
+/// Where the worker says they are when checking in or out.
+#[derive(Deserialize)]
+pub struct ShiftLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// A shift next to what the job planned for.
+#[derive(Serialize)]
+pub struct ShiftReport {
+    #[serde(flatten)]
+    pub shift: Shift,
+    pub scheduled_hours: i32,
+    /// Worked minus scheduled hours once checked out; negative when the
+    /// worker left early.
+    pub hours_over_schedule: Option<f64>,
+}
+
+impl ShiftReport {
+    pub fn new(shift: Shift, job: &JobOpportunity) -> Self {
+        let scheduled_hours = job.duration_in_hours;
+        let hours_over_schedule = shift
+            .worked_hours
+            .map(|worked| ((worked - f64::from(scheduled_hours)) * 100.0).round() / 100.0);
+        Self {
+            shift,
+            scheduled_hours,
+            hours_over_schedule,
+        }
+    }
+}
+
+/// The hours a worker claims for a shift and, once reviewed, what they are
+/// owed for it.
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = timesheets)]
+pub struct Timesheet {
+    pub id: i64,
+    pub shift_id: i64,
+    pub job_id: i64,
+    pub employee_id: i64,
+    pub status: String,
+    pub submitted_hours: f64,
+    pub break_minutes: i32,
+    pub net_hours: f64,
+    pub pay_rate: f64,
+    pub note: Option<String>,
+    pub dispute_reason: Option<String>,
+    pub approved_hours: Option<f64>,
+    /// `pay_rate` times `approved_hours`, set on approval.
+    pub payable_amount: Option<f64>,
+    pub submitted_at: NaiveDateTime,
+    pub decided_at: Option<NaiveDateTime>,
+    pub decided_by: Option<i64>,
+    pub auto_approved: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = timesheets)]
+pub struct NewTimesheet {
+    pub shift_id: i64,
+    pub job_id: i64,
+    pub employee_id: i64,
+    pub status: String,
+    pub submitted_hours: f64,
+    pub break_minutes: i32,
+    pub net_hours: f64,
+    pub pay_rate: f64,
+    pub note: Option<String>,
+    pub submitted_at: NaiveDateTime,
+}
+
+/// New hours for a timesheet that has not been approved yet. Clears the
+/// previous decision, which stays in the audit trail.
+#[derive(AsChangeset)]
+#[diesel(table_name = timesheets, treat_none_as_null = true)]
+pub struct TimesheetResubmission {
+    pub status: String,
+    pub submitted_hours: f64,
+    pub break_minutes: i32,
+    pub net_hours: f64,
+    pub note: Option<String>,
+    pub dispute_reason: Option<String>,
+    pub submitted_at: NaiveDateTime,
+    pub decided_at: Option<NaiveDateTime>,
+    pub decided_by: Option<i64>,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = timesheets, treat_none_as_null = true)]
+pub struct TimesheetDecision {
+    pub status: String,
+    pub dispute_reason: Option<String>,
+    pub approved_hours: Option<f64>,
+    pub payable_amount: Option<f64>,
+    pub decided_at: NaiveDateTime,
+    pub decided_by: Option<i64>,
+    pub auto_approved: bool,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = timesheet_events)]
+pub struct NewTimesheetEvent {
+    pub timesheet_id: i64,
+    pub action: String,
+    pub actor_user_id: Option<i64>,
+    pub net_hours: f64,
+    pub note: Option<String>,
+}
+
+/// Hours claimed for the caller's shift on a job. `hours` defaults to the
+/// time between check-in and check-out.
+#[derive(Deserialize)]
+pub struct TimesheetSubmission {
+    pub hours: Option<f64>,
+    #[serde(default)]
+    pub break_minutes: i32,
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DisputeTimesheetRequest {
+    pub reason: String,
+}
+
+/// One side's verdict on the other after a completed job.
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = reviews)]
+pub struct Review {
+    pub id: i64,
+    pub job_id: i64,
+    pub employee_id: i64,
+    pub company_id: i64,
+    pub direction: String,
+    pub rating: i32,
+    pub comment: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = reviews)]
+pub struct NewReview {
+    pub job_id: i64,
+    pub employee_id: i64,
+    pub company_id: i64,
+    pub direction: String,
+    pub author_user_id: Option<i64>,
+    pub rating: i32,
+    pub comment: Option<String>,
+}
+
+/// A rating from 1 to 5 with an optional comment.
+#[derive(Deserialize)]
+pub struct NewReviewRequest {
+    pub rating: i32,
+    pub comment: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = ledger_transactions)]
+pub struct LedgerTransaction {
+    pub id: i64,
+    pub company_id: Option<i64>,
+    pub employee_id: Option<i64>,
+    pub job_id: Option<i64>,
+    pub timesheet_id: Option<i64>,
+    pub description: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ledger_transactions)]
+pub struct NewLedgerTransaction {
+    pub kind: String,
+    pub company_id: Option<i64>,
+    pub employee_id: Option<i64>,
+    pub job_id: Option<i64>,
+    pub timesheet_id: Option<i64>,
+    pub description: String,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = ledger_entries)]
+pub struct LedgerEntry {
+    pub transaction_id: i64,
+    pub account: String,
+    pub side: String,
+    pub amount_cents: i64,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = ledger_entries)]
+pub struct NewLedgerEntry {
+    pub transaction_id: i64,
+    pub account: String,
+    pub company_id: Option<i64>,
+    pub employee_id: Option<i64>,
+    pub side: String,
+    pub amount_cents: i64,
+}
+
+/// A company's bill for the work done on its jobs over a period. Amounts are
+/// in cents.
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = invoices)]
+pub struct Invoice {
+    pub id: i64,
+    pub company_id: i64,
+    pub period_start: NaiveDateTime,
+    pub period_end: NaiveDateTime,
+    pub subtotal_cents: i64,
+    pub fee_cents: i64,
+    pub total_cents: i64,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = invoices)]
+pub struct NewInvoice {
+    pub company_id: i64,
+    pub period_start: NaiveDateTime,
+    pub period_end: NaiveDateTime,
+    pub subtotal_cents: i64,
+    pub fee_cents: i64,
+    pub total_cents: i64,
+}
+
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = invoice_lines)]
+pub struct InvoiceLine {
+    pub id: i64,
+    pub invoice_id: i64,
+    pub ledger_transaction_id: i64,
+    pub job_id: Option<i64>,
+    pub employee_id: Option<i64>,
+    pub description: String,
+    pub hours: f64,
+    pub amount_cents: i64,
+    pub fee_cents: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = invoice_lines)]
+pub struct NewInvoiceLine {
+    pub invoice_id: i64,
+    pub ledger_transaction_id: i64,
+    pub job_id: Option<i64>,
+    pub employee_id: Option<i64>,
+    pub description: String,
+    pub hours: f64,
+    pub amount_cents: i64,
+    pub fee_cents: i64,
+}
+
+#[derive(Serialize)]
+pub struct InvoiceWithLines {
+    #[serde(flatten)]
+    pub invoice: Invoice,
+    pub lines: Vec<InvoiceLine>,
+}
+
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = payout_batches)]
+pub struct PayoutBatch {
+    pub id: i64,
+    pub status: String,
+    pub payout_count: i32,
+    pub total_cents: i64,
+    pub created_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = payout_batches)]
+pub struct PayoutBatchTotals {
+    pub status: String,
+    pub payout_count: i32,
+    pub total_cents: i64,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+/// What one employee is paid in a batch, in cents.
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = payouts)]
+pub struct Payout {
+    pub id: i64,
+    pub batch_id: i64,
+    pub employee_id: i64,
+    pub status: String,
+    pub amount_cents: i64,
+    pub provider: String,
+    pub provider_reference: Option<String>,
+    pub failure_reason: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub paid_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = payouts)]
+pub struct NewPayout {
+    pub batch_id: i64,
+    pub employee_id: i64,
+    pub status: String,
+    pub amount_cents: i64,
+    pub provider: String,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = payouts)]
+pub struct PayoutOutcome {
+    pub status: String,
+    pub provider_reference: Option<String>,
+    pub failure_reason: Option<String>,
+    pub paid_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct PayoutBatchWithPayouts {
+    #[serde(flatten)]
+    pub batch: PayoutBatch,
+    pub payouts: Vec<Payout>,
+}
+
+/// What one run of the billing cycle did.
+#[derive(Serialize)]
+pub struct BillingRun {
+    pub invoices: Vec<Invoice>,
+    pub payout_batch: Option<PayoutBatch>,
+}
@@ -0,0 +1,458 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::domain::models::{
+    DisputeTimesheetRequest, JobSearchQuery, NewApiKeyRequest, NewChatMessage, NewCompany,
+    NewEmployee, NewJobOpportunity, NewReviewRequest, NewUser, ResetPasswordRequest, ShiftLocation,
+    TimesheetSubmission,
+};
+
+/// What is wrong with a request, as messages per field.
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationErrors(BTreeMap<&'static str, Vec<String>>);
+
+/// Payloads checked before they reach the service, see [`ValidJson`].
+///
+/// [`ValidJson`]: crate::infrastructure::extract::ValidJson
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// Outcome of one rule: the message to report when the value breaks it.
+pub type Rule = Result<(), String>;
+
+/// Collects the rules of each field, reporting every broken one at once.
+#[derive(Default)]
+pub struct Validator {
+    errors: ValidationErrors,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn field(mut self, name: &'static str, rules: impl IntoIterator<Item = Rule>) -> Self {
+        for message in rules.into_iter().filter_map(Result::err) {
+            self.errors.0.entry(name).or_default().push(message);
+        }
+        self
+    }
+
+    pub fn finish(self) -> Result<(), ValidationErrors> {
+        if self.errors.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+pub fn required(value: &str) -> Rule {
+    if value.trim().is_empty() {
+        return Err("is required".to_string());
+    }
+    Ok(())
+}
+
+pub fn min_length(value: &str, min: usize) -> Rule {
+    if value.chars().count() < min {
+        return Err(format!("must be at least {min} characters"));
+    }
+    Ok(())
+}
+
+pub fn max_length(value: &str, max: usize) -> Rule {
+    if value.chars().count() > max {
+        return Err(format!("must be at most {max} characters"));
+    }
+    Ok(())
+}
+
+pub fn no_spaces(value: &str) -> Rule {
+    if value.contains(char::is_whitespace) {
+        return Err("must not contain spaces".to_string());
+    }
+    Ok(())
+}
+
+pub fn not_empty<T>(values: &[T]) -> Rule {
+    if values.is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    Ok(())
+}
+
+pub fn range<T: PartialOrd + Display>(value: T, min: T, max: T) -> Rule {
+    if !(min <= value && value <= max) {
+        return Err(format!("must be between {min} and {max}"));
+    }
+    Ok(())
+}
+
+pub fn positive(value: f64) -> Rule {
+    if !(value > 0.0 && value.is_finite()) {
+        return Err("must be greater than 0".to_string());
+    }
+    Ok(())
+}
+
+pub fn non_negative(value: f64) -> Rule {
+    if !(value >= 0.0 && value.is_finite()) {
+        return Err("must be 0 or more".to_string());
+    }
+    Ok(())
+}
+
+/// Deliberately loose: one `@`, something on each side and a dot in the
+/// domain. Whether the address works is up to the verification email.
+pub fn email(value: &str) -> Rule {
+    let valid = match value.trim().split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+                && !value.trim().contains(char::is_whitespace)
+        }
+        None => false,
+    };
+    if !valid || value.len() > 254 {
+        return Err("must be a valid email address".to_string());
+    }
+    Ok(())
+}
+
+/// An RFC 3339 timestamp such as `2030-01-01T10:00:00Z`, later than now.
+pub fn future_timestamp(value: &str) -> Rule {
+    let at = DateTime::parse_from_rfc3339(value)
+        .map_err(|_| "must be an RFC 3339 timestamp".to_string())?;
+    if at <= Utc::now() {
+        return Err("must be in the future".to_string());
+    }
+    Ok(())
+}
+
+/// Applies `rule` only when there is a value.
+pub fn optional<T>(value: Option<T>, rule: impl FnOnce(T) -> Rule) -> Rule {
+    value.map_or(Ok(()), rule)
+}
+
+/// For values that only make sense together, like coordinates.
+pub fn together<A, B>(value: &Option<A>, other: &Option<B>, other_name: &str) -> Rule {
+    if value.is_some() != other.is_some() {
+        return Err(format!("must be given along with {other_name}"));
+    }
+    Ok(())
+}
+
+/// bcrypt ignores anything past 72 bytes, so longer passwords are refused
+/// rather than silently cut.
+fn password(value: &str) -> [Rule; 2] {
+    let too_long = if value.len() > 72 {
+        Err("must be at most 72 bytes".to_string())
+    } else {
+        Ok(())
+    };
+    [min_length(value, 8), too_long]
+}
+
+impl Validate for NewUser {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field(
+                "login",
+                [
+                    min_length(self.login.trim(), 3),
+                    max_length(&self.login, 64),
+                    no_spaces(&self.login),
+                ],
+            )
+            .field("password", password(&self.password))
+            .field("email", [optional(self.email.as_deref(), email)])
+            .finish()
+    }
+}
+
+impl Validate for ResetPasswordRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field("token", [required(&self.token)])
+            .field("password", password(&self.password))
+            .finish()
+    }
+}
+
+impl Validate for NewEmployee {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field(
+                "full_name",
+                [required(&self.full_name), max_length(&self.full_name, 200)],
+            )
+            .field("date_of_birth", [required(&self.date_of_birth)])
+            .field("email", [email(&self.email)])
+            .field(
+                "phone",
+                [required(&self.phone), max_length(&self.phone, 32)],
+            )
+            .field("residential_address", [required(&self.residential_address)])
+            .field(
+                "latitude",
+                [
+                    optional(self.latitude, |lat| range(lat, -90.0, 90.0)),
+                    together(&self.latitude, &self.longitude, "longitude"),
+                ],
+            )
+            .field(
+                "longitude",
+                [
+                    optional(self.longitude, |lng| range(lng, -180.0, 180.0)),
+                    together(&self.longitude, &self.latitude, "latitude"),
+                ],
+            )
+            .field("rating", [range(self.rating, 0.0, 5.0)])
+            .finish()
+    }
+}
+
+impl Validate for NewCompany {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field("name", [required(&self.name), max_length(&self.name, 200)])
+            .field("description", [max_length(&self.description, 5000)])
+            .field("address", [required(&self.address)])
+            .finish()
+    }
+}
+
+impl Validate for NewJobOpportunity {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field(
+                "title",
+                [required(&self.title), max_length(&self.title, 200)],
+            )
+            .field("description", [max_length(&self.description, 5000)])
+            .field("address", [required(&self.address)])
+            .field("category", [required(&self.category)])
+            .field("latitude", [range(self.latitude, -90.0, 90.0)])
+            .field("longitude", [range(self.longitude, -180.0, 180.0)])
+            .field("start_date_time", [future_timestamp(&self.start_date_time)])
+            .field("duration_in_hours", [range(self.duration_in_hours, 1, 24)])
+            .field("pay_rate", [positive(self.pay_rate)])
+            .field("headcount", [range(self.headcount, 1, 100)])
+            .finish()
+    }
+}
+
+impl Validate for JobSearchQuery {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field("lat", [range(self.lat, -90.0, 90.0)])
+            .field("lng", [range(self.lng, -180.0, 180.0)])
+            .field(
+                "radius_km",
+                [positive(self.radius_km), range(self.radius_km, 0.0, 200.0)],
+            )
+            .field(
+                "category",
+                [optional(self.category.as_deref(), |category| {
+                    max_length(category, 100)
+                })],
+            )
+            .field("min_pay", [optional(self.min_pay, non_negative)])
+            .finish()
+    }
+}
+
+impl Validate for NewApiKeyRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field("name", [required(&self.name), max_length(&self.name, 100)])
+            .field("scopes", [not_empty(&self.scopes)])
+            .finish()
+    }
+}
+
+impl Validate for NewChatMessage {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field("text", [required(&self.text), max_length(&self.text, 2000)])
+            .finish()
+    }
+}
+
+impl Validate for ShiftLocation {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field("latitude", [range(self.latitude, -90.0, 90.0)])
+            .field("longitude", [range(self.longitude, -180.0, 180.0)])
+            .finish()
+    }
+}
+
+impl Validate for TimesheetSubmission {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field(
+                "hours",
+                [
+                    optional(self.hours, positive),
+                    optional(self.hours, |hours| range(hours, 0.0, 24.0)),
+                ],
+            )
+            .field("break_minutes", [range(self.break_minutes, 0, 24 * 60)])
+            .field(
+                "note",
+                [optional(self.note.as_deref(), |note| {
+                    max_length(note, 1000)
+                })],
+            )
+            .finish()
+    }
+}
+
+impl Validate for DisputeTimesheetRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field(
+                "reason",
+                [required(&self.reason), max_length(&self.reason, 1000)],
+            )
+            .finish()
+    }
+}
+
+impl Validate for NewReviewRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .field("rating", [range(self.rating, 1, 5)])
+            .field(
+                "comment",
+                [optional(self.comment.as_deref(), |comment| {
+                    max_length(comment, 2000)
+                })],
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::enums::JobStatus;
+
+    fn job() -> NewJobOpportunity {
+        NewJobOpportunity {
+            company_id: None,
+            title: "Waiter".to_string(),
+            description: "Evening service".to_string(),
+            address: "Rua A, 1".to_string(),
+            category: "food".to_string(),
+            latitude: -23.5,
+            longitude: -46.6,
+            start_date_time: "2099-01-01T10:00:00Z".to_string(),
+            duration_in_hours: 4,
+            pay_rate: 20.0,
+            status: JobStatus::OPEN,
+            headcount: 1,
+        }
+    }
+
+    fn broken_fields(result: Result<(), ValidationErrors>) -> Vec<&'static str> {
+        result
+            .err()
+            .map_or(Vec::new(), |errors| errors.0.into_keys().collect())
+    }
+
+    #[test]
+    fn email_takes_anything_shaped_like_an_address() {
+        assert_eq!(email("ana@example.com"), Ok(()));
+        assert_eq!(email(" ana@mail.example.com "), Ok(()));
+
+        for invalid in [
+            "",
+            "ana",
+            "@example.com",
+            "ana@example",
+            "ana@.example.com",
+            "ana@example.com.",
+            "ana@b@example.com",
+            "ana maria@example.com",
+        ] {
+            assert!(email(invalid).is_err(), "{invalid}");
+        }
+        assert!(email(&format!("{}@example.com", "a".repeat(250))).is_err());
+    }
+
+    #[test]
+    fn future_timestamp_wants_rfc3339_after_now() {
+        assert_eq!(future_timestamp("2099-01-01T10:00:00Z"), Ok(()));
+        assert_eq!(future_timestamp("2099-01-01T10:00:00-03:00"), Ok(()));
+
+        assert_eq!(
+            future_timestamp("2000-01-01T10:00:00Z"),
+            Err("must be in the future".to_string())
+        );
+        assert_eq!(
+            future_timestamp("2099-01-01 10:00"),
+            Err("must be an RFC 3339 timestamp".to_string())
+        );
+    }
+
+    #[test]
+    fn passwords_are_limited_to_72_bytes() {
+        let fits = "a".repeat(72);
+        assert!(password(&fits).iter().all(Result::is_ok));
+
+        let too_long = "a".repeat(73);
+        assert_eq!(
+            password(&too_long)[1],
+            Err("must be at most 72 bytes".to_string())
+        );
+
+        // 36 two-byte characters fit; 37 do not, though far under 72 chars.
+        assert!(password(&"é".repeat(36)).iter().all(Result::is_ok));
+        assert!(password(&"é".repeat(37))[1].is_err());
+
+        assert!(password("short")[0].is_err());
+    }
+
+    #[test]
+    fn together_wants_both_values_or_neither() {
+        assert_eq!(together(&Some(1.0), &Some(2.0), "longitude"), Ok(()));
+        assert_eq!(together::<f64, f64>(&None, &None, "longitude"), Ok(()));
+        assert_eq!(
+            together(&Some(1.0), &None::<f64>, "longitude"),
+            Err("must be given along with longitude".to_string())
+        );
+        assert!(together(&None::<f64>, &Some(2.0), "latitude").is_err());
+    }
+
+    #[test]
+    fn job_reports_every_broken_field_at_once() {
+        assert_eq!(broken_fields(job().validate()), Vec::<&str>::new());
+
+        let job = NewJobOpportunity {
+            title: " ".to_string(),
+            latitude: 91.0,
+            start_date_time: "2000-01-01T10:00:00Z".to_string(),
+            pay_rate: 0.0,
+            headcount: 0,
+            ..job()
+        };
+        assert_eq!(
+            broken_fields(job.validate()),
+            [
+                "headcount",
+                "latitude",
+                "pay_rate",
+                "start_date_time",
+                "title"
+            ]
+        );
+    }
+}
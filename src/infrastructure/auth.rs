@@ -1,122 +1,298 @@
 use axum::{
     body::Body,
-    extract::{Json, Request, State},
-    http::{self, Response, StatusCode},
+    extract::{MatchedPath, Request, State},
+    http::{self, HeaderMap, Response},
     middleware::Next,
-    response::IntoResponse,
 };
-use bcrypt::{hash, verify, DEFAULT_COST};
-use chrono::{Duration, Utc};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bcrypt::{hash, verify, BcryptError, DEFAULT_COST};
+use chrono::{Duration, NaiveDateTime, Utc};
 use diesel_async::{pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use jsonwebtoken::{decode, decode_header, encode, TokenData};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{net::IpAddr, sync::LazyLock};
+use uuid::Uuid;
 
-use crate::{application::service::Service, Pool};
+use super::{
+    jwt::JwtConfig,
+    roles::{required_scope, ApiKeyPrincipal, Roles},
+};
+use crate::{
+    application::{
+        error::AppError,
+        login_throttle::LoginThrottleConfig,
+        service::{Service, API_KEY_PREFIX},
+    },
+    domain::{
+        enums::{FailedLoginReason, Role},
+        models::{NewUser, User},
+        validation::{Validate, ValidationErrors},
+    },
+    Pool,
+};
 
-#[derive(Serialize, Deserialize)]
+/// Header company API keys can be sent in.
+const API_KEY_HEADER: &str = "x-api-key";
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub exp: usize,
     pub iat: usize,
+    pub iss: String,
+    pub aud: String,
     pub login: String,
+    /// Session the token belongs to: the family of the refresh token issued
+    /// alongside it. Revoking the family invalidates the token.
+    pub sid: Uuid,
+    /// Roles when the token was issued, for clients to adapt their UI.
+    /// Requests are checked against the current ones, see `Roles`.
+    #[serde(default)]
+    pub roles: Vec<Role>,
 }
 
-pub struct AuthError {
-    message: String,
-    status_code: StatusCode,
+/// What `/login`, `/register` and `/auth/refresh` hand back.
+#[derive(Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: &'static str,
+    /// Seconds until the access token expires.
+    pub expires_in: i64,
 }
 
-impl IntoResponse for AuthError {
-    fn into_response(self) -> Response<Body> {
-        let body = Json(json!({ "error": self.message }));
-        (self.status_code, body).into_response()
-    }
+/// Handed out by `/login` instead of tokens when the account has two-factor
+/// authentication on; exchanged for tokens at `/auth/mfa/verify`.
+#[derive(Serialize)]
+pub struct MfaChallenge {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+    /// Seconds left to send the code.
+    pub expires_in: i64,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum SignInResponse {
+    Tokens(TokenPair),
+    MfaRequired(MfaChallenge),
+}
+
+/// Claims of an "mfa pending" token. It carries its own audience, so it is
+/// never accepted where an access token is expected.
+#[derive(Serialize, Deserialize)]
+pub struct MfaClaims {
+    pub exp: usize,
+    pub iat: usize,
+    pub iss: String,
+    pub aud: String,
+    pub sub: i64,
+    /// Invitation given with the password, accepted once the code checks out.
+    #[serde(default)]
+    pub invitation_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MfaVerifyRequest {
+    pub mfa_token: String,
+    pub code: String,
+}
+
+/// How long a user has to enter their code after the password.
+const MFA_TOKEN_TTL_MINUTES: i64 = 5;
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
 }
 
 pub struct Auth;
 
 impl Auth {
-    pub fn verify_password(password: &str, hash: &str) -> Result<bool, bcrypt::BcryptError> {
-        verify(password, hash)
+    pub async fn verify_password(password: String, hash: String) -> Result<bool, BcryptError> {
+        run_blocking(move || verify(password, &hash)).await
     }
 
-    pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
-        hash(password, DEFAULT_COST)
+    pub async fn hash_password(password: String) -> Result<String, BcryptError> {
+        run_blocking(move || hash(password, DEFAULT_COST)).await
     }
 
-    pub fn encode_jwt(login: String) -> Result<String, StatusCode> {
-        let secret = "randomstring".to_string();
+    /// Takes as long as checking a real password, so a login that does not
+    /// exist cannot be told apart by how fast it is refused.
+    pub async fn verify_dummy_password(password: String) {
+        let _ = run_blocking(move || verify(password, &DUMMY_HASH)).await;
+    }
+
+    /// The installed JWT configuration; missing only if startup skipped it.
+    pub fn jwt_config() -> Result<&'static JwtConfig, AppError> {
+        JwtConfig::get().ok_or_else(|| AppError::internal("JWT configuration is not installed"))
+    }
+
+    pub fn encode_jwt(user: &User, roles: Vec<Role>, session_id: Uuid) -> Result<String, AppError> {
+        let config = Auth::jwt_config()?;
         let now = Utc::now();
-        let expire = Duration::hours(24);
-        let exp = (now + expire).timestamp() as usize;
+        let exp = (now + config.expiry).timestamp() as usize;
         let iat = now.timestamp() as usize;
 
-        let claim = Claims { iat, exp, login };
+        let claim = Claims {
+            iat,
+            exp,
+            iss: config.issuer.clone(),
+            aud: config.audience.clone(),
+            login: user.login.clone(),
+            sid: session_id,
+            roles,
+        };
 
-        encode(
-            &Header::default(),
-            &claim,
-            &EncodingKey::from_secret(secret.as_ref()),
-        )
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+        Ok(encode(&config.header(), &claim, config.encoding_key())?)
     }
 
-    pub fn decode_jwt(jwt: String) -> Result<TokenData<Claims>, StatusCode> {
-        let secret = "randomstring".to_string();
+    /// Checks the signature against the key named by the token's `kid`, then
+    /// the expiry, issuer and audience.
+    pub fn decode_jwt(jwt: String) -> Result<TokenData<Claims>, AppError> {
+        let config = Auth::jwt_config()?;
+        let header = decode_header(&jwt)?;
+        let key = config
+            .decoding_key(header.kid.as_deref())
+            .ok_or_else(|| AppError::unauthorized("Unknown signing key"))?;
 
-        decode(
-            &jwt,
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::default(),
-        )
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+        Ok(decode(&jwt, key, &config.validation())?)
     }
 
+    pub fn encode_mfa_token(
+        user: &User,
+        invitation_token: Option<String>,
+    ) -> Result<MfaChallenge, AppError> {
+        let config = Auth::jwt_config()?;
+        let now = Utc::now();
+        let ttl = Duration::minutes(MFA_TOKEN_TTL_MINUTES);
+
+        let claims = MfaClaims {
+            exp: (now + ttl).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            iss: config.issuer.clone(),
+            aud: mfa_audience(config),
+            sub: user.id,
+            invitation_token,
+        };
+        let mfa_token = encode(&config.header(), &claims, config.encoding_key())?;
+
+        Ok(MfaChallenge {
+            mfa_required: true,
+            mfa_token,
+            expires_in: ttl.num_seconds(),
+        })
+    }
+
+    pub fn decode_mfa_token(token: &str) -> Result<MfaClaims, AppError> {
+        let config = Auth::jwt_config()?;
+        let header = decode_header(token)?;
+        let key = config
+            .decoding_key(header.kid.as_deref())
+            .ok_or_else(|| AppError::unauthorized("Unknown signing key"))?;
+
+        let mut validation = config.validation();
+        validation.set_audience(&[mfa_audience(config)]);
+        Ok(decode(token, key, &validation)?.claims)
+    }
+
+    /// A new random bearer token (refresh token, invitation...) and the hash
+    /// it is stored under.
+    pub fn generate_token() -> (String, String) {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        let hash = Auth::hash_token(&token);
+        (token, hash)
+    }
+
+    /// Generated tokens are long random strings, so a plain SHA-256 is enough
+    /// to keep a database leak from handing out working tokens.
+    pub fn hash_token(token: &str) -> String {
+        format!("{:x}", Sha256::digest(token.as_bytes()))
+    }
+
+    /// Authenticates the request with an access token, or with a company API
+    /// key sent as `X-API-Key` or as a `bz_` bearer token.
     pub async fn authorize(
         State(pool): State<Pool>,
         mut req: Request<Body>,
         next: Next,
-    ) -> Result<Response<Body>, AuthError> {
-        let auth_header = req.headers_mut().get(http::header::AUTHORIZATION);
-
-        let auth_header = match auth_header {
-            Some(header) => header.to_str().map_err(|_| AuthError {
-                message: "Empty header is not allowed".to_string(),
-                status_code: StatusCode::FORBIDDEN,
-            })?,
-            None => {
-                return Err(AuthError {
-                    message: "Please add the JWT token to the header".to_string(),
-                    status_code: StatusCode::FORBIDDEN,
-                })
-            }
-        };
+    ) -> Result<Response<Body>, AppError> {
+        if let Some(key) = Auth::api_key(req.headers()) {
+            let key = key.to_string();
+            return Auth::authorize_api_key(pool, key, req, next).await;
+        }
+
+        let auth_header = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .ok_or_else(|| AppError::forbidden("Please add the JWT token to the header"))?
+            .to_str()
+            .map_err(|_| AppError::forbidden("Empty header is not allowed"))?;
 
         let mut header = auth_header.split_whitespace();
         let (_, token) = (header.next(), header.next());
+        let token = token.ok_or_else(|| AppError::unauthorized("Unable to decode token"))?;
+        let token_data = Auth::decode_jwt(token.to_string())?;
 
-        let token_data = match Auth::decode_jwt(token.unwrap().to_string()) {
-            Ok(data) => data,
-            Err(_) => {
-                return Err(AuthError {
-                    message: "Unable to decode token".to_string(),
-                    status_code: StatusCode::UNAUTHORIZED,
-                })
-            }
-        };
-
-        let mut conn = pool.get().await.map_err(|_| AuthError {
-            message: "Unable to connect to database".to_string(),
-            status_code: StatusCode::FORBIDDEN,
-        })?;
-        let current_user = Service::find_by_login(&mut conn, &token_data.claims.login)
+        let mut conn = pool.get().await?;
+        let current_user = Service::find_session_user(&mut conn, &token_data.claims)
             .await
-            .map_err(|_| AuthError {
-                message: "Unable to find user".to_string(),
-                status_code: StatusCode::UNAUTHORIZED,
-            })?;
+            .map_err(|_| AppError::unauthorized("Unable to find user or session"))?;
+        let roles = Service::user_roles(&mut conn, &current_user).await?;
 
+        req.extensions_mut().insert(Roles(roles));
         req.extensions_mut().insert(current_user);
+        req.extensions_mut().insert(token_data.claims);
+        Ok(next.run(req).await)
+    }
+
+    /// The API key of the request, if it was sent with one.
+    fn api_key(headers: &HeaderMap) -> Option<&str> {
+        if let Some(key) = headers.get(API_KEY_HEADER) {
+            return key.to_str().ok();
+        }
+        headers
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| token.starts_with(API_KEY_PREFIX))
+    }
+
+    async fn authorize_api_key(
+        pool: Pool,
+        key: String,
+        mut req: Request<Body>,
+        next: Next,
+    ) -> Result<Response<Body>, AppError> {
+        let mut conn = pool.get().await?;
+        let api_key = Service::find_api_key(&mut conn, &key)
+            .await?
+            .ok_or_else(|| AppError::unauthorized("Invalid API key"))?;
+
+        let scope = req
+            .extensions()
+            .get::<MatchedPath>()
+            .and_then(|path| required_scope(req.method(), path.as_str()))
+            .ok_or_else(|| AppError::forbidden("API keys cannot be used for this route"))?;
+        if !api_key
+            .scopes
+            .iter()
+            .any(|granted| granted == scope.as_str())
+        {
+            return Err(AppError::forbidden(format!(
+                "API key lacks the `{}` scope",
+                scope.as_str()
+            )));
+        }
+
+        req.extensions_mut().insert(Roles(vec![Role::ApiClient]));
+        req.extensions_mut().insert(ApiKeyPrincipal {
+            company_id: api_key.company_id,
+        });
         Ok(next.run(req).await)
     }
 }
@@ -125,21 +301,170 @@ impl Auth {
 pub struct SignInData {
     pub login: String,
     pub password: String,
+    /// Joins the inviting company on the way in.
+    pub invitation_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterData {
+    #[serde(flatten)]
+    pub user: NewUser,
+    /// Joins the inviting company on the way in.
+    pub invitation_token: Option<String>,
+}
+
+impl Validate for RegisterData {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        self.user.validate()
+    }
+}
+
+fn mfa_audience(config: &JwtConfig) -> String {
+    format!("{}#mfa", config.audience)
 }
 
+/// bcrypt is slow on purpose, so it runs on the blocking pool rather than
+/// holding up an executor thread.
+async fn run_blocking<T, F>(f: F) -> Result<T, BcryptError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, BcryptError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| BcryptError::Io(std::io::Error::other(e)))?
+}
+
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| hash("not a password", DEFAULT_COST).expect("bcrypt hashing failed"));
+
+fn too_many_attempts(blocked_until: NaiveDateTime) -> AppError {
+    let retry_after = (blocked_until - Utc::now().naive_utc())
+        .num_seconds()
+        .max(1);
+    AppError::TooManyRequests {
+        message: "Too many failed login attempts".to_string(),
+        retry_after,
+    }
+}
+
+/// Refuses the attempt outright while the login or the client IP is backing
+/// off, see [`LoginThrottleConfig`].
+async fn check_throttle(
+    conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+    throttle: &LoginThrottleConfig,
+    login: &str,
+    client_ip: IpAddr,
+) -> Result<(), AppError> {
+    let keys = [
+        LoginThrottleConfig::login_key(login),
+        LoginThrottleConfig::ip_key(client_ip),
+    ];
+    let blocked_until = Service::login_blocked_until(conn, &keys).await?;
+    if let Some(blocked_until) = blocked_until {
+        let reason = FailedLoginReason::Throttled;
+        if let Err(e) =
+            Service::record_failed_login(conn, throttle, login, client_ip, None, reason).await
+        {
+            tracing::error!("Unable to audit failed login: {e}");
+        }
+        return Err(too_many_attempts(blocked_until));
+    }
+    Ok(())
+}
+
+/// Checks the credentials unless the login or the client IP failed too
+/// often lately. Accounts with two-factor authentication get an
+/// [`MfaChallenge`] rather than tokens.
 pub async fn sign_in(
     conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+    throttle: &LoginThrottleConfig,
+    client_ip: IpAddr,
     user_data: SignInData,
-) -> Result<String, StatusCode> {
-    let user = Service::find_by_login(conn, &user_data.login)
+) -> Result<SignInResponse, AppError> {
+    check_throttle(conn, throttle, &user_data.login, client_ip).await?;
+
+    let user = Service::find_by_login(conn, &user_data.login).await.ok();
+    let verified = match &user {
+        Some(user) => {
+            Auth::verify_password(user_data.password.clone(), user.password.clone()).await?
+        }
+        None => {
+            Auth::verify_dummy_password(user_data.password.clone()).await;
+            false
+        }
+    };
+    let user = match user {
+        Some(user) if verified => user,
+        user => {
+            Service::record_failed_login(
+                conn,
+                throttle,
+                &user_data.login,
+                client_ip,
+                user.map(|user| user.id),
+                FailedLoginReason::InvalidCredentials,
+            )
+            .await?;
+            return Err(AppError::unauthorized("Invalid login or password"));
+        }
+    };
+
+    // Failures are only forgotten once the second factor checks out too, or
+    // a known password would allow guessing codes endlessly.
+    if Service::mfa_enabled(conn, user.id).await? {
+        return Auth::encode_mfa_token(&user, user_data.invitation_token)
+            .map(SignInResponse::MfaRequired);
+    }
+
+    finish_sign_in(conn, user, user_data.invitation_token)
         .await
-        .map_err(|_| StatusCode::UNAUTHORIZED)?;
-    if !Auth::verify_password(&user_data.password, &user.password)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    {
-        return Err(StatusCode::UNAUTHORIZED);
+        .map(SignInResponse::Tokens)
+}
+
+/// Second step of signing in to an account with two-factor authentication.
+pub async fn complete_mfa_sign_in(
+    conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+    throttle: &LoginThrottleConfig,
+    client_ip: IpAddr,
+    request: MfaVerifyRequest,
+) -> Result<TokenPair, AppError> {
+    let claims = Auth::decode_mfa_token(&request.mfa_token)?;
+    let user = Service::find_user(conn, claims.sub)
+        .await
+        .map_err(|_| AppError::unauthorized("Invalid token"))?;
+    check_throttle(conn, throttle, &user.login, client_ip).await?;
+
+    let verified = Service::verify_mfa_code(conn, user.id, request.code).await?;
+    if !verified {
+        Service::record_failed_login(
+            conn,
+            throttle,
+            &user.login,
+            client_ip,
+            Some(user.id),
+            FailedLoginReason::InvalidMfaCode,
+        )
+        .await?;
+        return Err(AppError::unauthorized("Invalid code"));
     }
 
-    let token = Auth::encode_jwt(user.login).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(token)
+    finish_sign_in(conn, user, claims.invitation_token).await
+}
+
+/// Clears the failed attempts, joins the inviting company if asked to and
+/// starts the session.
+async fn finish_sign_in(
+    conn: &mut bb8::PooledConnection<'_, AsyncDieselConnectionManager<AsyncPgConnection>>,
+    user: User,
+    invitation_token: Option<String>,
+) -> Result<TokenPair, AppError> {
+    Service::clear_login_failures(conn, &user.login).await?;
+
+    let user = match invitation_token {
+        Some(token) => Service::join_company(conn, user, token).await?,
+        None => user,
+    };
+
+    Ok(Service::start_session(conn, &user).await?)
 }
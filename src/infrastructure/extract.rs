@@ -0,0 +1,51 @@
+use axum::{
+    async_trait,
+    extract::{FromRequest, FromRequestParts, Query, Request},
+    http::request::Parts,
+    Json,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{application::error::AppError, domain::validation::Validate};
+
+/// A JSON body that passed [`Validate`]. Bodies that do not parse or break a
+/// rule are refused with a 422 listing what is wrong.
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|rejection| AppError::validation(rejection.body_text()))?;
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
+
+/// Query parameters that passed [`Validate`], refused like [`ValidJson`]
+/// otherwise.
+pub struct ValidQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::validation(rejection.body_text()))?;
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
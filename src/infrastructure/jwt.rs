@@ -0,0 +1,299 @@
+use chrono::Duration;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use std::{collections::HashMap, fmt, sync::OnceLock};
+
+const DEFAULT_ISSUER: &str = "biizi";
+const DEFAULT_AUDIENCE: &str = "biizi-api";
+const DEFAULT_EXPIRY_MINUTES: i64 = 15;
+const DEFAULT_REFRESH_EXPIRY_DAYS: i64 = 30;
+const DEFAULT_KID: &str = "default";
+
+/// Keys and claims policy loaded once at startup, see [`JwtConfig::from_env`].
+static CONFIG: OnceLock<JwtConfig> = OnceLock::new();
+
+/// Why the JWT configuration could not be loaded.
+#[derive(Debug)]
+pub enum JwtConfigError {
+    Missing(&'static str),
+    Invalid(&'static str, String),
+    UnreadableFile(String, std::io::Error),
+    InvalidKey(String, jsonwebtoken::errors::Error),
+    AlreadyConfigured,
+}
+
+impl fmt::Display for JwtConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtConfigError::Missing(var) => write!(f, "{var} must be set"),
+            JwtConfigError::Invalid(var, reason) => write!(f, "{var} is invalid: {reason}"),
+            JwtConfigError::UnreadableFile(path, e) => write!(f, "unable to read {path}: {e}"),
+            JwtConfigError::InvalidKey(kid, e) => write!(f, "key `{kid}` is invalid: {e}"),
+            JwtConfigError::AlreadyConfigured => write!(f, "JWT configuration was already loaded"),
+        }
+    }
+}
+
+impl std::error::Error for JwtConfigError {}
+
+/// How tokens are signed and what a valid token must contain.
+pub struct JwtConfig {
+    pub algorithm: Algorithm,
+    pub issuer: String,
+    pub audience: String,
+    /// Lifetime of access tokens.
+    pub expiry: Duration,
+    /// Lifetime of refresh tokens; rotating one starts a fresh lifetime.
+    pub refresh_expiry: Duration,
+    /// `kid` stamped on new tokens.
+    kid: String,
+    encoding_key: EncodingKey,
+    /// Every key tokens may be verified with, by `kid`. Holds the signing key
+    /// plus retired ones kept around while their tokens expire.
+    decoding_keys: HashMap<String, DecodingKey>,
+}
+
+impl JwtConfig {
+    /// Reads the configuration from the environment:
+    ///
+    /// - `JWT_ALGORITHM`: `HS256` (default), `RS256` or `EdDSA`.
+    /// - `JWT_SECRET` or `JWT_SECRET_FILE`: the HS256 signing secret.
+    /// - `JWT_PRIVATE_KEY_FILE` and `JWT_PUBLIC_KEY_FILE`: PEM key pair for
+    ///   RS256 and EdDSA.
+    /// - `JWT_KID`: id of the signing key, `default` when unset.
+    /// - `JWT_ACCEPTED_KEYS`: comma separated `kid=path` pairs of retired keys
+    ///   still accepted, each file holding a secret or a public PEM key.
+    /// - `JWT_ISSUER`, `JWT_AUDIENCE` and `JWT_EXPIRY_MINUTES`.
+    /// - `REFRESH_TOKEN_EXPIRY_DAYS`.
+    pub fn from_env() -> Result<Self, JwtConfigError> {
+        let algorithm = match std::env::var("JWT_ALGORITHM").as_deref() {
+            Err(_) | Ok("HS256") => Algorithm::HS256,
+            Ok("RS256") => Algorithm::RS256,
+            Ok("EdDSA") => Algorithm::EdDSA,
+            Ok(other) => {
+                return Err(JwtConfigError::Invalid(
+                    "JWT_ALGORITHM",
+                    format!("unsupported algorithm `{other}`"),
+                ))
+            }
+        };
+
+        let kid = std::env::var("JWT_KID").unwrap_or_else(|_| DEFAULT_KID.to_string());
+
+        let (encoding_key, decoding_key) = match algorithm {
+            Algorithm::HS256 => {
+                let secret = match std::env::var("JWT_SECRET") {
+                    Ok(secret) => secret.into_bytes(),
+                    Err(_) => {
+                        let path = std::env::var("JWT_SECRET_FILE").map_err(|_| {
+                            JwtConfigError::Missing("JWT_SECRET or JWT_SECRET_FILE")
+                        })?;
+                        read_file(&path)?
+                    }
+                };
+                let secret = trim_secret(secret);
+                if secret.is_empty() {
+                    return Err(JwtConfigError::Invalid("JWT_SECRET", "empty".to_string()));
+                }
+                (
+                    EncodingKey::from_secret(&secret),
+                    DecodingKey::from_secret(&secret),
+                )
+            }
+            _ => {
+                let private_path = std::env::var("JWT_PRIVATE_KEY_FILE")
+                    .map_err(|_| JwtConfigError::Missing("JWT_PRIVATE_KEY_FILE"))?;
+                let public_path = std::env::var("JWT_PUBLIC_KEY_FILE")
+                    .map_err(|_| JwtConfigError::Missing("JWT_PUBLIC_KEY_FILE"))?;
+                let private_pem = read_file(&private_path)?;
+                let encoding_key = match algorithm {
+                    Algorithm::RS256 => EncodingKey::from_rsa_pem(&private_pem),
+                    _ => EncodingKey::from_ed_pem(&private_pem),
+                }
+                .map_err(|e| JwtConfigError::InvalidKey(kid.clone(), e))?;
+                let decoding_key = load_decoding_key(algorithm, &kid, &read_file(&public_path)?)?;
+                (encoding_key, decoding_key)
+            }
+        };
+
+        let mut decoding_keys = HashMap::from([(kid.clone(), decoding_key)]);
+        if let Ok(accepted) = std::env::var("JWT_ACCEPTED_KEYS") {
+            for entry in accepted.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let (accepted_kid, path) = entry.split_once('=').ok_or_else(|| {
+                    JwtConfigError::Invalid(
+                        "JWT_ACCEPTED_KEYS",
+                        format!("`{entry}` is not kid=path"),
+                    )
+                })?;
+                let key = load_decoding_key(algorithm, accepted_kid, &read_file(path)?)?;
+                decoding_keys.insert(accepted_kid.to_string(), key);
+            }
+        }
+
+        let expiry_minutes = match std::env::var("JWT_EXPIRY_MINUTES") {
+            Ok(value) => value
+                .parse::<i64>()
+                .ok()
+                .filter(|minutes| *minutes > 0)
+                .ok_or_else(|| {
+                    JwtConfigError::Invalid("JWT_EXPIRY_MINUTES", format!("`{value}`"))
+                })?,
+            Err(_) => DEFAULT_EXPIRY_MINUTES,
+        };
+
+        let refresh_expiry_days = match std::env::var("REFRESH_TOKEN_EXPIRY_DAYS") {
+            Ok(value) => value
+                .parse::<i64>()
+                .ok()
+                .filter(|days| *days > 0)
+                .ok_or_else(|| {
+                    JwtConfigError::Invalid("REFRESH_TOKEN_EXPIRY_DAYS", format!("`{value}`"))
+                })?,
+            Err(_) => DEFAULT_REFRESH_EXPIRY_DAYS,
+        };
+
+        Ok(Self {
+            algorithm,
+            issuer: std::env::var("JWT_ISSUER").unwrap_or_else(|_| DEFAULT_ISSUER.to_string()),
+            audience: std::env::var("JWT_AUDIENCE")
+                .unwrap_or_else(|_| DEFAULT_AUDIENCE.to_string()),
+            expiry: Duration::minutes(expiry_minutes),
+            refresh_expiry: Duration::days(refresh_expiry_days),
+            kid,
+            encoding_key,
+            decoding_keys,
+        })
+    }
+
+    /// Makes this the configuration used by `Auth` for the rest of the process.
+    pub fn install(self) -> Result<(), JwtConfigError> {
+        CONFIG
+            .set(self)
+            .map_err(|_| JwtConfigError::AlreadyConfigured)
+    }
+
+    /// The installed configuration, if startup got that far.
+    pub fn get() -> Option<&'static JwtConfig> {
+        CONFIG.get()
+    }
+
+    pub fn header(&self) -> Header {
+        let mut header = Header::new(self.algorithm);
+        header.kid = Some(self.kid.clone());
+        header
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding_key
+    }
+
+    /// The key for a token's `kid`. Tokens without one predate rotation and
+    /// are checked against the signing key.
+    pub fn decoding_key(&self, kid: Option<&str>) -> Option<&DecodingKey> {
+        self.decoding_keys.get(kid.unwrap_or(&self.kid))
+    }
+
+    pub fn validation(&self) -> Validation {
+        let mut validation = Validation::new(self.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        validation
+    }
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, JwtConfigError> {
+    std::fs::read(path).map_err(|e| JwtConfigError::UnreadableFile(path.to_string(), e))
+}
+
+/// Secrets read from files usually end with a newline that is not part of
+/// them, so whitespace around a secret is dropped.
+fn trim_secret(secret: Vec<u8>) -> Vec<u8> {
+    secret.trim_ascii().to_vec()
+}
+
+fn load_decoding_key(
+    algorithm: Algorithm,
+    kid: &str,
+    key: &[u8],
+) -> Result<DecodingKey, JwtConfigError> {
+    match algorithm {
+        Algorithm::RS256 => DecodingKey::from_rsa_pem(key),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(key),
+        _ => Ok(DecodingKey::from_secret(&trim_secret(key.to_vec()))),
+    }
+    .map_err(|e| JwtConfigError::InvalidKey(kid.to_string(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const CURRENT: &[u8] = b"current-secret";
+    const RETIRED: &[u8] = b"retired-secret";
+
+    fn config() -> JwtConfig {
+        JwtConfig {
+            algorithm: Algorithm::HS256,
+            issuer: DEFAULT_ISSUER.to_string(),
+            audience: DEFAULT_AUDIENCE.to_string(),
+            expiry: Duration::minutes(DEFAULT_EXPIRY_MINUTES),
+            refresh_expiry: Duration::days(DEFAULT_REFRESH_EXPIRY_DAYS),
+            kid: "current".to_string(),
+            encoding_key: EncodingKey::from_secret(CURRENT),
+            decoding_keys: HashMap::from([
+                ("current".to_string(), DecodingKey::from_secret(CURRENT)),
+                ("retired".to_string(), DecodingKey::from_secret(RETIRED)),
+            ]),
+        }
+    }
+
+    fn token(secret: &[u8]) -> String {
+        let claims = json!({
+            "exp": chrono::Utc::now().timestamp() + 60,
+            "iss": DEFAULT_ISSUER,
+            "aud": DEFAULT_AUDIENCE,
+        });
+        let key = EncodingKey::from_secret(secret);
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &key).unwrap()
+    }
+
+    fn verifies(key: &DecodingKey, token: &str) -> bool {
+        jsonwebtoken::decode::<Value>(token, key, &config().validation()).is_ok()
+    }
+
+    #[test]
+    fn tokens_are_checked_against_the_key_for_their_kid() {
+        let config = config();
+        let current = config.decoding_key(Some("current")).unwrap();
+        let retired = config.decoding_key(Some("retired")).unwrap();
+
+        assert!(verifies(current, &token(CURRENT)));
+        assert!(verifies(retired, &token(RETIRED)));
+        assert!(!verifies(retired, &token(CURRENT)));
+    }
+
+    #[test]
+    fn unknown_kids_have_no_key() {
+        assert!(config().decoding_key(Some("unknown")).is_none());
+    }
+
+    #[test]
+    fn tokens_without_a_kid_use_the_signing_key() {
+        let config = config();
+        let key = config.decoding_key(None).unwrap();
+
+        assert!(verifies(key, &token(CURRENT)));
+        assert!(!verifies(key, &token(RETIRED)));
+    }
+
+    #[test]
+    fn whitespace_around_secrets_is_dropped() {
+        assert_eq!(trim_secret(b" \tsecret \r\n".to_vec()), b"secret");
+        assert_eq!(trim_secret(b"se cret\n".to_vec()), b"se cret");
+        assert_eq!(trim_secret(b" \n".to_vec()), b"");
+
+        let key = load_decoding_key(Algorithm::HS256, "file", b"  secret\n").unwrap();
+        assert!(verifies(&key, &token(b"secret")));
+    }
+}
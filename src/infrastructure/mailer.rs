@@ -0,0 +1,267 @@
+use axum::async_trait;
+use chrono::Utc;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use std::{fmt, path::PathBuf, sync::Arc};
+use uuid::Uuid;
+
+const DEFAULT_FROM: &str = "Biizi <no-reply@localhost>";
+const DEFAULT_BASE_URL: &str = "http://localhost:9854";
+const DEFAULT_MAIL_DIR: &str = "./mail";
+
+/// Why the mail configuration could not be loaded.
+#[derive(Debug)]
+pub enum MailerConfigError {
+    Missing(&'static str),
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for MailerConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailerConfigError::Missing(var) => write!(f, "{var} must be set"),
+            MailerConfigError::Invalid(var, reason) => write!(f, "{var} is invalid: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for MailerConfigError {}
+
+#[derive(Debug)]
+pub struct MailError(String);
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for MailError {}
+
+/// A plain text email.
+#[derive(Debug)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Somewhere emails can be handed to.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), MailError>;
+}
+
+fn build_message(from: &Mailbox, email: Email) -> Result<Message, MailError> {
+    let to: Mailbox = email
+        .to
+        .parse()
+        .map_err(|e| MailError(format!("invalid recipient `{}`: {e}", email.to)))?;
+    Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body)
+        .map_err(|e| MailError(e.to_string()))
+}
+
+/// Delivers through an SMTP relay.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        let message = build_message(&self.from, email)?;
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| MailError(e.to_string()))
+    }
+}
+
+/// Writes each email to its own `.eml` file, for local development.
+pub struct FileMailer {
+    dir: PathBuf,
+    from: Mailbox,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        let message = build_message(&self.from, email)?;
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            Uuid::new_v4()
+        ));
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| MailError(e.to_string()))?;
+        tokio::fs::write(&path, message.formatted())
+            .await
+            .map_err(|e| MailError(format!("unable to write {}: {e}", path.display())))?;
+        tracing::info!(
+            "Mail to {:?} written to {}",
+            message.envelope().to(),
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+/// Only logs emails, links included. Never use it where logs are shared.
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        tracing::info!(
+            "Mail to {} about \"{}\":\n{}",
+            email.to,
+            email.subject,
+            email.body
+        );
+        Ok(())
+    }
+}
+
+/// Sends the account emails. Delivery happens in the background so requests
+/// never wait on the mail server, and failures are only logged.
+#[derive(Clone)]
+pub struct AccountMailer {
+    mailer: Arc<dyn Mailer>,
+    /// Public address of the app, used to build links.
+    base_url: String,
+}
+
+impl AccountMailer {
+    /// Reads the configuration from the environment:
+    ///
+    /// - `MAIL_TRANSPORT`: `log` (default), `file` or `smtp`.
+    /// - `MAIL_FROM`: sender of every email.
+    /// - `MAIL_DIR`: where `file` writes emails, `./mail` when unset.
+    /// - `SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME` and `SMTP_PASSWORD`.
+    /// - `SMTP_TLS`: `starttls` (default), `tls` or `none`.
+    /// - `APP_BASE_URL`: prefix of links in emails.
+    pub fn from_env() -> Result<Self, MailerConfigError> {
+        let from: Mailbox = std::env::var("MAIL_FROM")
+            .unwrap_or_else(|_| DEFAULT_FROM.to_string())
+            .parse()
+            .map_err(|e| MailerConfigError::Invalid("MAIL_FROM", format!("{e}")))?;
+
+        let mailer: Arc<dyn Mailer> = match std::env::var("MAIL_TRANSPORT").as_deref() {
+            Err(_) | Ok("log") => Arc::new(LogMailer),
+            Ok("file") => Arc::new(FileMailer {
+                dir: std::env::var("MAIL_DIR")
+                    .unwrap_or_else(|_| DEFAULT_MAIL_DIR.to_string())
+                    .into(),
+                from,
+            }),
+            Ok("smtp") => Arc::new(SmtpMailer {
+                transport: smtp_transport_from_env()?,
+                from,
+            }),
+            Ok(other) => {
+                return Err(MailerConfigError::Invalid(
+                    "MAIL_TRANSPORT",
+                    format!("unsupported transport `{other}`"),
+                ))
+            }
+        };
+
+        let base_url = std::env::var("APP_BASE_URL")
+            .unwrap_or_else(|_| DEFAULT_BASE_URL.to_string())
+            .trim_end_matches('/')
+            .to_string();
+
+        Ok(Self { mailer, base_url })
+    }
+
+    pub fn send_password_reset(&self, to: String, token: &str) {
+        self.deliver(Email {
+            to,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Someone asked to reset the password of your account.\n\n\
+                 Choose a new one at {}/reset-password?token={token}\n\n\
+                 The link expires in an hour. If it was not you, ignore this email.",
+                self.base_url
+            ),
+        });
+    }
+
+    pub fn send_email_verification(&self, to: String, token: &str) {
+        self.deliver(Email {
+            to,
+            subject: "Confirm your email".to_string(),
+            body: format!(
+                "Confirm this is your email by opening {}/auth/verify-email?token={token}\n\n\
+                 The link expires in a day.",
+                self.base_url
+            ),
+        });
+    }
+
+    fn deliver(&self, email: Email) {
+        let mailer = self.mailer.clone();
+        tokio::spawn(async move {
+            let to = email.to.clone();
+            if let Err(e) = mailer.send(email).await {
+                tracing::error!("Unable to send mail to {to}: {e}");
+            }
+        });
+    }
+}
+
+fn smtp_transport_from_env() -> Result<AsyncSmtpTransport<Tokio1Executor>, MailerConfigError> {
+    let host = std::env::var("SMTP_HOST").map_err(|_| MailerConfigError::Missing("SMTP_HOST"))?;
+    let invalid_host =
+        |e: lettre::transport::smtp::Error| MailerConfigError::Invalid("SMTP_HOST", e.to_string());
+
+    let (builder, default_port) = match std::env::var("SMTP_TLS").as_deref() {
+        Err(_) | Ok("starttls") => (
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host).map_err(invalid_host)?,
+            587,
+        ),
+        Ok("tls") => (
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&host).map_err(invalid_host)?,
+            465,
+        ),
+        Ok("none") => (
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+            25,
+        ),
+        Ok(other) => {
+            return Err(MailerConfigError::Invalid(
+                "SMTP_TLS",
+                format!("unsupported mode `{other}`"),
+            ))
+        }
+    };
+
+    let port = match std::env::var("SMTP_PORT") {
+        Ok(value) => value
+            .parse::<u16>()
+            .map_err(|_| MailerConfigError::Invalid("SMTP_PORT", format!("`{value}`")))?,
+        Err(_) => default_port,
+    };
+
+    let builder = match (
+        std::env::var("SMTP_USERNAME"),
+        std::env::var("SMTP_PASSWORD"),
+    ) {
+        (Ok(username), Ok(password)) => builder.credentials(Credentials::new(username, password)),
+        (Ok(_), Err(_)) => return Err(MailerConfigError::Missing("SMTP_PASSWORD")),
+        _ => builder,
+    };
+
+    Ok(builder.port(port).build())
+}
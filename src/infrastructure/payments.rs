@@ -0,0 +1,117 @@
+use axum::async_trait;
+use std::{fmt, sync::Arc};
+use uuid::Uuid;
+
+/// Why the payment configuration could not be loaded.
+#[derive(Debug)]
+pub struct PaymentsConfigError(&'static str, String);
+
+impl fmt::Display for PaymentsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is invalid: {}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for PaymentsConfigError {}
+
+#[derive(Debug)]
+pub struct PaymentError(String);
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PaymentError {}
+
+/// Money to send to an employee.
+#[derive(Debug)]
+pub struct PayoutInstruction {
+    /// Stable for a payout, so providers can drop a transfer sent twice.
+    pub reference: String,
+    pub employee_id: i64,
+    pub amount_cents: i64,
+}
+
+/// Something that can move money to employees.
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    /// Short name stored with each payout.
+    fn name(&self) -> &'static str;
+
+    /// Sends the payout and returns the provider's reference for it.
+    async fn send_payout(&self, payout: &PayoutInstruction) -> Result<String, PaymentError>;
+}
+
+/// Pretends to pay, for local development. Payouts above `fail_above_cents`
+/// fail, to try out the unhappy path.
+pub struct FakePaymentProvider {
+    fail_above_cents: Option<i64>,
+}
+
+#[async_trait]
+impl PaymentProvider for FakePaymentProvider {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    async fn send_payout(&self, payout: &PayoutInstruction) -> Result<String, PaymentError> {
+        if let Some(limit) = self.fail_above_cents {
+            if payout.amount_cents > limit {
+                return Err(PaymentError(format!(
+                    "amount over the fake limit of {limit} cents"
+                )));
+            }
+        }
+        tracing::info!(
+            "Fake payout {} of {} cents to employee {}",
+            payout.reference,
+            payout.amount_cents,
+            payout.employee_id
+        );
+        Ok(format!("fake_{}", Uuid::new_v4().simple()))
+    }
+}
+
+/// The configured payment provider.
+#[derive(Clone)]
+pub struct Payments {
+    provider: Arc<dyn PaymentProvider>,
+}
+
+impl Payments {
+    /// Reads the configuration from the environment:
+    ///
+    /// - `PAYMENT_PROVIDER`: only `fake` (default) for now.
+    /// - `FAKE_PAYMENT_FAIL_ABOVE_CENTS`: makes `fake` refuse larger payouts.
+    pub fn from_env() -> Result<Self, PaymentsConfigError> {
+        let provider: Arc<dyn PaymentProvider> = match std::env::var("PAYMENT_PROVIDER").as_deref()
+        {
+            Err(_) | Ok("fake") => {
+                let fail_above_cents = match std::env::var("FAKE_PAYMENT_FAIL_ABOVE_CENTS") {
+                    Ok(value) => Some(value.parse::<i64>().map_err(|_| {
+                        PaymentsConfigError("FAKE_PAYMENT_FAIL_ABOVE_CENTS", format!("`{value}`"))
+                    })?),
+                    Err(_) => None,
+                };
+                Arc::new(FakePaymentProvider { fail_above_cents })
+            }
+            Ok(other) => {
+                return Err(PaymentsConfigError(
+                    "PAYMENT_PROVIDER",
+                    format!("unsupported provider `{other}`"),
+                ))
+            }
+        };
+        Ok(Self { provider })
+    }
+
+    pub fn provider_name(&self) -> &'static str {
+        self.provider.name()
+    }
+
+    pub async fn send_payout(&self, payout: &PayoutInstruction) -> Result<String, PaymentError> {
+        self.provider.send_payout(payout).await
+    }
+}
@@ -1,17 +1,31 @@
 use crate::{
+    domain::enums::{AccountTokenPurpose, ApplicationStatus, CompanyRole, JobStatus, LedgerTransactionKind, PayoutStatus, ReviewDirection, TimesheetStatus},
     domain::models::{
-        Company, Employee, JobOpportunity, JobOpportunityWithCompany, NewCompany, NewEmployee,
-        NewJobOpportunity, NewUser, User,
+        AccountToken, Company, CompanyApiKey, CompanyInvitation, CompanyMember, CompanyMemberWithLogin, Employee, JobApplication, JobApplicationWithEmployee, JobOpportunity,
+        JobOpportunityWithCompany, JobSearchQuery, LoginThrottle, MfaRecoveryCode, NewCompany, NewEmployee, NewJobApplication,
+        NewAccountToken, NewCompanyApiKey, NewCompanyInvitation, NewCompanyMember, NewFailedLogin, NewJobOpportunity, NewMfaRecoveryCode, NewRefreshToken, NewUser, NewUserMfa, NewWsEvent, NewWsEventRecipient,
+        Invoice, InvoiceLine, LedgerEntry, LedgerTransaction, NewInvoice, NewInvoiceLine, NewLedgerEntry, NewLedgerTransaction, NewPayout, NewReview, Payout, PayoutBatch, PayoutBatchTotals, PayoutOutcome, RefreshToken, Review, Shift, ShiftCheckOut, NewShift, NewTimesheet, NewTimesheetEvent, Timesheet, TimesheetDecision, TimesheetResubmission, User, UserMfa, WsEvent,
     },
     infrastructure::schema::*,
 };
 use axum::response::Json;
+use chrono::NaiveDateTime;
 use companies::{address, description, logo_url, name};
-use diesel::SelectableHelper;
-use diesel::{ExpressionMethods, QueryDsl};
+use diesel::sql_types::Double;
+use diesel::{BoolExpressionMethods, NullableExpressionMethods, SelectableHelper};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use employees::*;
 use users::{companyid, employeeid};
+use uuid::Uuid;
+
+/// Roughly how many kilometres one degree of latitude spans.
+const KM_PER_DEGREE_LATITUDE: f64 = 111.0;
+
+diesel::define_sql_function! {
+    /// Haversine distance in kilometres, see the `great_circle_distance` migration.
+    fn great_circle_km(lat1: Double, lng1: Double, lat2: Double, lng2: Double) -> Double;
+}
 
 pub struct Repository;
 
@@ -35,8 +49,8 @@ impl Repository {
                         phone.eq(new_employee.phone.clone()),
                         is_available.eq(new_employee.is_available),
                         residential_address.eq(new_employee.residential_address.clone()),
-                        latitude.eq(new_employee.latitude.unwrap_or_else(|| 0.0)),
-                        longitude.eq(new_employee.longitude.unwrap_or_else(|| 0.0)),
+                        latitude.eq(new_employee.latitude.unwrap_or(0.0)),
+                        longitude.eq(new_employee.longitude.unwrap_or(0.0)),
                         date_of_birth.eq(new_employee.date_of_birth.clone()),
                     ))
                     .get_result(conn)
@@ -70,16 +84,13 @@ impl Repository {
     ) -> Result<Json<Company>, diesel::result::Error> {
         let res: Company;
 
-        if user.companyid.is_some() {
-            res = diesel::update(companies::table.find(user.companyid.unwrap()))
+        if let Some(user_company_id) = user.companyid {
+            res = diesel::update(companies::table.find(user_company_id))
                 .set((
                     name.eq(new_company.name.clone()),
                     description.eq(new_company.description.clone()),
                     address.eq(new_company.address.clone()),
-                    logo_url.clone().eq(new_company
-                        .logo_url
-                        .clone()
-                        .unwrap_or_else(|| "".to_string())),
+                    logo_url.eq(new_company.logo_url.clone().unwrap_or_default()),
                 ))
                 .get_result(conn)
                 .await?;
@@ -93,6 +104,15 @@ impl Repository {
                 .set(companyid.eq(res.id))
                 .execute(conn)
                 .await?;
+
+            diesel::insert_into(company_members::table)
+                .values(NewCompanyMember {
+                    company_id: res.id,
+                    user_id: user.id,
+                    role: CompanyRole::ADMIN.as_str().to_string(),
+                })
+                .execute(conn)
+                .await?;
         }
 
         Ok(Json(res))
@@ -161,14 +181,109 @@ impl Repository {
                 pay_rate: job.pay_rate,
                 status: job.status,
                 company_id: job.company_id,
+                headcount: job.headcount,
+                assigned_slots: job.assigned_slots,
+                remaining_slots: job.remaining_slots,
                 company_name: Some(comp.name.clone()),
                 company_logo_url: Some(comp.logo_url.clone()),
+                distance_km: None,
             })
             .collect();
 
         Ok(result)
     }
 
+    /// Open jobs within `radius_km` of the given point, nearest first.
+    pub async fn search_job_opportunities(
+        conn: &mut AsyncPgConnection,
+        search: &JobSearchQuery,
+    ) -> Result<Vec<JobOpportunityWithCompany>, diesel::result::Error> {
+        let distance = great_circle_km(
+            job_opportunities::latitude,
+            job_opportunities::longitude,
+            search.lat,
+            search.lng,
+        );
+        // Cheap latitude band so the index prunes rows before the distance is computed.
+        let lat_delta = search.radius_km / KM_PER_DEGREE_LATITUDE;
+
+        let mut query = job_opportunities::table
+            .left_join(companies::table)
+            .filter(job_opportunities::status.eq(JobStatus::OPEN))
+            .filter(
+                job_opportunities::latitude.between(search.lat - lat_delta, search.lat + lat_delta),
+            )
+            .filter(distance.le(search.radius_km))
+            .select((
+                JobOpportunity::as_select(),
+                (companies::name, companies::logo_url).nullable(),
+                distance,
+            ))
+            .order(distance.asc())
+            .into_boxed();
+
+        if let Some(pcategory) = &search.category {
+            query = query.filter(job_opportunities::category.eq(pcategory.clone()));
+        }
+        if let Some(min_pay) = search.min_pay {
+            query = query.filter(job_opportunities::pay_rate.ge(min_pay));
+        }
+
+        let rows = query
+            .load::<(JobOpportunity, Option<(String, String)>, f64)>(conn)
+            .await?;
+
+        let result = rows
+            .into_iter()
+            .map(|(job, company, distance_km)| {
+                let (company_name, company_logo_url) = company.unzip();
+                JobOpportunityWithCompany {
+                    id: job.id,
+                    title: job.title,
+                    description: job.description,
+                    category: job.category,
+                    address: job.address,
+                    latitude: job.latitude,
+                    longitude: job.longitude,
+                    start_date_time: job.start_date_time,
+                    duration_in_hours: job.duration_in_hours,
+                    pay_rate: job.pay_rate,
+                    status: job.status,
+                    company_id: job.company_id,
+                    headcount: job.headcount,
+                    assigned_slots: job.assigned_slots,
+                    remaining_slots: job.remaining_slots,
+                    company_name,
+                    company_logo_url,
+                    distance_km: Some(distance_km),
+                }
+            })
+            .collect();
+
+        Ok(result)
+    }
+
+    /// Ids of the users behind available employees within `radius_km` of a point.
+    pub async fn find_available_employee_users_near(
+        conn: &mut AsyncPgConnection,
+        lat: f64,
+        lng: f64,
+        radius_km: f64,
+    ) -> Result<Vec<i64>, diesel::result::Error> {
+        let lat_delta = radius_km / KM_PER_DEGREE_LATITUDE;
+
+        users::table
+            .inner_join(employees::table)
+            .filter(employees::is_available.eq(true))
+            .filter(employees::latitude.between(lat - lat_delta, lat + lat_delta))
+            .filter(
+                great_circle_km(employees::latitude, employees::longitude, lat, lng).le(radius_km),
+            )
+            .select(users::id)
+            .load::<i64>(conn)
+            .await
+    }
+
     pub async fn find_by_login(
         conn: &mut AsyncPgConnection,
         user_login: &str,
@@ -182,6 +297,37 @@ impl Repository {
         Ok(user)
     }
 
+    pub async fn find_user(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+    ) -> Result<User, diesel::result::Error> {
+        users::table.find(user_id).first::<User>(conn).await
+    }
+
+    /// Id of the user account an employee profile belongs to.
+    pub async fn find_employee_user_id(
+        conn: &mut AsyncPgConnection,
+        employee_id: &i64,
+    ) -> Result<i64, diesel::result::Error> {
+        users::table
+            .filter(users::employeeid.eq(employee_id))
+            .select(users::id)
+            .first::<i64>(conn)
+            .await
+    }
+
+    pub async fn update_employee_location(
+        conn: &mut AsyncPgConnection,
+        employee_id: &i64,
+        lat: f64,
+        lng: f64,
+    ) -> Result<Employee, diesel::result::Error> {
+        diesel::update(employees::table.find(employee_id))
+            .set((employees::latitude.eq(lat), employees::longitude.eq(lng)))
+            .get_result(conn)
+            .await
+    }
+
     pub async fn find_employe(
         conn: &mut AsyncPgConnection,
         employe_id: &i64,
@@ -207,4 +353,1460 @@ impl Repository {
 
         Ok(company)
     }
+
+    pub async fn find_job_opportunity(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+    ) -> Result<JobOpportunity, diesel::result::Error> {
+        job_opportunities::table
+            .find(job_id)
+            .select(JobOpportunity::as_select())
+            .first::<JobOpportunity>(conn)
+            .await
+    }
+
+    /// Loads a job and keeps its status from changing until the surrounding
+    /// transaction ends.
+    pub async fn find_job_opportunity_for_share(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+    ) -> Result<JobOpportunity, diesel::result::Error> {
+        job_opportunities::table
+            .find(job_id)
+            .select(JobOpportunity::as_select())
+            .for_share()
+            .first::<JobOpportunity>(conn)
+            .await
+    }
+
+    /// Loads a job and locks its row until the surrounding transaction ends,
+    /// for changes made by its workers rather than its company.
+    pub async fn find_job_opportunity_for_update_by_id(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+    ) -> Result<JobOpportunity, diesel::result::Error> {
+        job_opportunities::table
+            .find(job_id)
+            .select(JobOpportunity::as_select())
+            .for_update()
+            .first::<JobOpportunity>(conn)
+            .await
+    }
+
+    /// Loads one of the company's jobs and locks its row until the surrounding
+    /// transaction ends, so concurrent status changes are serialized.
+    pub async fn find_job_opportunity_for_update(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+        company_id: &i64,
+    ) -> Result<JobOpportunity, diesel::result::Error> {
+        job_opportunities::table
+            .filter(job_opportunities::id.eq(job_id))
+            .filter(job_opportunities::company_id.eq(company_id))
+            .select(JobOpportunity::as_select())
+            .for_update()
+            .first::<JobOpportunity>(conn)
+            .await
+    }
+
+    pub async fn update_job_status(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+        new_status: JobStatus,
+    ) -> Result<Json<JobOpportunity>, diesel::result::Error> {
+        let res = diesel::update(job_opportunities::table.find(job_id))
+            .set(job_opportunities::status.eq(new_status))
+            .get_result(conn)
+            .await?;
+        Ok(Json(res))
+    }
+
+    /// Sets how many of the job's slots are taken and the status that goes
+    /// with it.
+    pub async fn update_job_slots(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+        assigned: i32,
+        new_status: JobStatus,
+    ) -> Result<Json<JobOpportunity>, diesel::result::Error> {
+        let res = diesel::update(job_opportunities::table.find(job_id))
+            .set((
+                job_opportunities::assigned_slots.eq(assigned),
+                job_opportunities::status.eq(new_status),
+            ))
+            .get_result(conn)
+            .await?;
+        Ok(Json(res))
+    }
+
+    pub async fn save_job_application(
+        conn: &mut AsyncPgConnection,
+        new_application: &NewJobApplication,
+    ) -> Result<Json<JobApplication>, diesel::result::Error> {
+        let res = diesel::insert_into(job_applications::table)
+            .values(new_application.clone())
+            .get_result(conn)
+            .await?;
+        Ok(Json(res))
+    }
+
+    pub async fn find_job_applications_for_company(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+        company_id: &i64,
+    ) -> Result<Vec<JobApplicationWithEmployee>, diesel::result::Error> {
+        let rows = job_applications::table
+            .inner_join(employees::table)
+            .inner_join(job_opportunities::table)
+            .filter(job_applications::job_id.eq(job_id))
+            .filter(job_opportunities::company_id.eq(company_id))
+            .order(job_applications::created_at.asc())
+            .select((JobApplication::as_select(), Employee::as_select()))
+            .load::<(JobApplication, Employee)>(conn)
+            .await?;
+
+        let result = rows
+            .into_iter()
+            .map(|(application, employee)| JobApplicationWithEmployee {
+                id: application.id,
+                job_id: application.job_id,
+                employee_id: application.employee_id,
+                status: application.status,
+                created_at: application.created_at,
+                employee_full_name: employee.full_name,
+                employee_phone: employee.phone,
+                employee_rating: employee.rating,
+            })
+            .collect();
+
+        Ok(result)
+    }
+
+    pub async fn find_job_application_for_company(
+        conn: &mut AsyncPgConnection,
+        application_id: &i64,
+        company_id: &i64,
+    ) -> Result<JobApplication, diesel::result::Error> {
+        job_applications::table
+            .inner_join(job_opportunities::table)
+            .filter(
+                job_applications::id
+                    .eq(application_id)
+                    .and(job_opportunities::company_id.eq(company_id)),
+            )
+            .select(JobApplication::as_select())
+            .first::<JobApplication>(conn)
+            .await
+    }
+
+    pub async fn update_job_application_status(
+        conn: &mut AsyncPgConnection,
+        application_id: &i64,
+        new_status: &str,
+    ) -> Result<Json<JobApplication>, diesel::result::Error> {
+        let res = diesel::update(job_applications::table.find(application_id))
+            .set(job_applications::status.eq(new_status))
+            .get_result(conn)
+            .await?;
+        Ok(Json(res))
+    }
+
+    /// Decides an application that is still PENDING; `None` when it was
+    /// decided or withdrawn in the meantime.
+    pub async fn decide_pending_job_application(
+        conn: &mut AsyncPgConnection,
+        application_id: &i64,
+        new_status: &str,
+    ) -> Result<Option<JobApplication>, diesel::result::Error> {
+        diesel::update(
+            job_applications::table
+                .find(application_id)
+                .filter(job_applications::status.eq(ApplicationStatus::PENDING.as_str())),
+        )
+        .set(job_applications::status.eq(new_status))
+        .returning(JobApplication::as_returning())
+        .get_result(conn)
+        .await
+        .optional()
+    }
+
+    /// The employee's application to the job, if it was accepted.
+    pub async fn find_accepted_job_application(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+        employee_id: &i64,
+    ) -> Result<Option<JobApplication>, diesel::result::Error> {
+        job_applications::table
+            .filter(job_applications::job_id.eq(job_id))
+            .filter(job_applications::employee_id.eq(employee_id))
+            .filter(job_applications::status.eq(ApplicationStatus::ACCEPTED.as_str()))
+            .select(JobApplication::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// The employee's application to the job, unless it was decided against
+    /// or withdrawn.
+    pub async fn find_active_job_application(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+        employee_id: &i64,
+    ) -> Result<Option<JobApplication>, diesel::result::Error> {
+        job_applications::table
+            .filter(job_applications::job_id.eq(job_id))
+            .filter(job_applications::employee_id.eq(employee_id))
+            .filter(job_applications::status.eq_any([
+                ApplicationStatus::PENDING.as_str(),
+                ApplicationStatus::ACCEPTED.as_str(),
+            ]))
+            .select(JobApplication::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Kilometres between the job and the given point.
+    pub async fn find_distance_to_job_km(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+        lat: f64,
+        lng: f64,
+    ) -> Result<f64, diesel::result::Error> {
+        job_opportunities::table
+            .find(job_id)
+            .select(great_circle_km(
+                job_opportunities::latitude,
+                job_opportunities::longitude,
+                lat,
+                lng,
+            ))
+            .first(conn)
+            .await
+    }
+
+    pub async fn save_shift(
+        conn: &mut AsyncPgConnection,
+        new_shift: &NewShift,
+    ) -> Result<Shift, diesel::result::Error> {
+        diesel::insert_into(shifts::table)
+            .values(new_shift)
+            .returning(Shift::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// Loads the employee's shift on the job and locks it until the
+    /// surrounding transaction ends.
+    pub async fn find_shift_for_update(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+        employee_id: &i64,
+    ) -> Result<Option<Shift>, diesel::result::Error> {
+        shifts::table
+            .filter(shifts::job_id.eq(job_id))
+            .filter(shifts::employee_id.eq(employee_id))
+            .select(Shift::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn update_shift_check_out(
+        conn: &mut AsyncPgConnection,
+        shift_id: &i64,
+        check_out: &ShiftCheckOut,
+    ) -> Result<Shift, diesel::result::Error> {
+        diesel::update(shifts::table.find(shift_id))
+            .set(check_out)
+            .returning(Shift::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// Stores an event and returns its sequence number.
+    pub async fn save_ws_event(
+        conn: &mut AsyncPgConnection,
+        new_event: &NewWsEvent,
+    ) -> Result<i64, diesel::result::Error> {
+        diesel::insert_into(ws_events::table)
+            .values(new_event)
+            .returning(ws_events::seq)
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn save_ws_event_recipients(
+        conn: &mut AsyncPgConnection,
+        recipients: &[NewWsEventRecipient],
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::insert_into(ws_event_recipients::table)
+            .values(recipients)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await
+    }
+
+    pub async fn find_ws_event(
+        conn: &mut AsyncPgConnection,
+        seq: &i64,
+    ) -> Result<WsEvent, diesel::result::Error> {
+        ws_events::table
+            .find(seq)
+            .select(WsEvent::as_select())
+            .first(conn)
+            .await
+    }
+
+    /// Which of `user_ids` the event is addressed to.
+    pub async fn find_ws_event_recipients_among(
+        conn: &mut AsyncPgConnection,
+        seq: &i64,
+        user_ids: &[i64],
+    ) -> Result<Vec<i64>, diesel::result::Error> {
+        ws_event_recipients::table
+            .filter(ws_event_recipients::event_seq.eq(seq))
+            .filter(ws_event_recipients::user_id.eq_any(user_ids))
+            .select(ws_event_recipients::user_id)
+            .load(conn)
+            .await
+    }
+
+    /// The oldest `limit` events addressed to `user_id` after `after_seq`.
+    pub async fn find_ws_events_for_user_after(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+        after_seq: &i64,
+        limit: i64,
+    ) -> Result<Vec<WsEvent>, diesel::result::Error> {
+        ws_events::table
+            .inner_join(ws_event_recipients::table)
+            .filter(ws_event_recipients::user_id.eq(user_id))
+            .filter(ws_events::seq.gt(after_seq))
+            .order(ws_events::seq.asc())
+            .limit(limit)
+            .select(WsEvent::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// The shift's timesheet, if any, locked until the transaction ends.
+    pub async fn find_timesheet_by_shift_for_update(
+        conn: &mut AsyncPgConnection,
+        shift_id: &i64,
+    ) -> Result<Option<Timesheet>, diesel::result::Error> {
+        timesheets::table
+            .filter(timesheets::shift_id.eq(shift_id))
+            .select(Timesheet::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Loads a timesheet on one of the company's jobs and locks it until the
+    /// transaction ends.
+    pub async fn find_timesheet_for_company_for_update(
+        conn: &mut AsyncPgConnection,
+        timesheet_id: &i64,
+        company_id: &i64,
+    ) -> Result<Timesheet, diesel::result::Error> {
+        timesheets::table
+            .inner_join(job_opportunities::table)
+            .filter(timesheets::id.eq(timesheet_id))
+            .filter(job_opportunities::company_id.eq(company_id))
+            .select(Timesheet::as_select())
+            .for_update()
+            .first(conn)
+            .await
+    }
+
+    pub async fn find_timesheets_for_job(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+        company_id: &i64,
+    ) -> Result<Vec<Timesheet>, diesel::result::Error> {
+        timesheets::table
+            .inner_join(job_opportunities::table)
+            .filter(timesheets::job_id.eq(job_id))
+            .filter(job_opportunities::company_id.eq(company_id))
+            .order(timesheets::submitted_at.asc())
+            .select(Timesheet::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn find_timesheets_for_employee(
+        conn: &mut AsyncPgConnection,
+        employee_id: &i64,
+    ) -> Result<Vec<Timesheet>, diesel::result::Error> {
+        timesheets::table
+            .filter(timesheets::employee_id.eq(employee_id))
+            .order(timesheets::submitted_at.desc())
+            .select(Timesheet::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Ids of submitted timesheets nobody reviewed since `cutoff`, oldest
+    /// first.
+    pub async fn find_timesheet_ids_due_for_approval(
+        conn: &mut AsyncPgConnection,
+        cutoff: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<i64>, diesel::result::Error> {
+        timesheets::table
+            .filter(timesheets::status.eq(TimesheetStatus::SUBMITTED.as_str()))
+            .filter(timesheets::submitted_at.le(cutoff))
+            .order(timesheets::submitted_at.asc())
+            .limit(limit)
+            .select(timesheets::id)
+            .load(conn)
+            .await
+    }
+
+    /// The timesheet, locked, if it is still due for approval. `None` when
+    /// it was reviewed meanwhile or another instance is handling it.
+    pub async fn find_due_timesheet_for_update(
+        conn: &mut AsyncPgConnection,
+        timesheet_id: &i64,
+        cutoff: NaiveDateTime,
+    ) -> Result<Option<Timesheet>, diesel::result::Error> {
+        timesheets::table
+            .filter(timesheets::id.eq(timesheet_id))
+            .filter(timesheets::status.eq(TimesheetStatus::SUBMITTED.as_str()))
+            .filter(timesheets::submitted_at.le(cutoff))
+            .select(Timesheet::as_select())
+            .for_update()
+            .skip_locked()
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn save_timesheet(
+        conn: &mut AsyncPgConnection,
+        new_timesheet: &NewTimesheet,
+    ) -> Result<Timesheet, diesel::result::Error> {
+        diesel::insert_into(timesheets::table)
+            .values(new_timesheet)
+            .returning(Timesheet::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn update_timesheet_submission(
+        conn: &mut AsyncPgConnection,
+        timesheet_id: &i64,
+        resubmission: &TimesheetResubmission,
+    ) -> Result<Timesheet, diesel::result::Error> {
+        diesel::update(timesheets::table.find(timesheet_id))
+            .set(resubmission)
+            .returning(Timesheet::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn update_timesheet_decision(
+        conn: &mut AsyncPgConnection,
+        timesheet_id: &i64,
+        decision: &TimesheetDecision,
+    ) -> Result<Timesheet, diesel::result::Error> {
+        diesel::update(timesheets::table.find(timesheet_id))
+            .set(decision)
+            .returning(Timesheet::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn save_timesheet_event(
+        conn: &mut AsyncPgConnection,
+        event: &NewTimesheetEvent,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::insert_into(timesheet_events::table)
+            .values(event)
+            .execute(conn)
+            .await
+    }
+
+    /// Saves a review; a second one from the same side for the same job and
+    /// worker is a unique violation.
+    pub async fn save_review(
+        conn: &mut AsyncPgConnection,
+        new_review: &NewReview,
+    ) -> Result<Review, diesel::result::Error> {
+        diesel::insert_into(reviews::table)
+            .values(new_review)
+            .returning(Review::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn find_reviews_for_employee(
+        conn: &mut AsyncPgConnection,
+        employee_id: &i64,
+    ) -> Result<Vec<Review>, diesel::result::Error> {
+        reviews::table
+            .filter(reviews::employee_id.eq(employee_id))
+            .filter(reviews::direction.eq(ReviewDirection::CompanyToEmployee.as_str()))
+            .order(reviews::created_at.desc())
+            .select(Review::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn find_reviews_for_company(
+        conn: &mut AsyncPgConnection,
+        company_id: &i64,
+    ) -> Result<Vec<Review>, diesel::result::Error> {
+        reviews::table
+            .filter(reviews::company_id.eq(company_id))
+            .filter(reviews::direction.eq(ReviewDirection::EmployeeToCompany.as_str()))
+            .order(reviews::created_at.desc())
+            .select(Review::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// How many reviews companies left for the employee and their total
+    /// stars. Locks the employee so concurrent reviews are counted in turn.
+    pub async fn find_employee_review_totals_for_update(
+        conn: &mut AsyncPgConnection,
+        employee_id: &i64,
+    ) -> Result<(i64, i64), diesel::result::Error> {
+        employees::table
+            .find(employee_id)
+            .select(employees::id)
+            .for_update()
+            .first::<i64>(conn)
+            .await?;
+        let (count, stars) = reviews::table
+            .filter(reviews::employee_id.eq(employee_id))
+            .filter(reviews::direction.eq(ReviewDirection::CompanyToEmployee.as_str()))
+            .select((diesel::dsl::count_star(), diesel::dsl::sum(reviews::rating)))
+            .first::<(i64, Option<i64>)>(conn)
+            .await?;
+        Ok((count, stars.unwrap_or(0)))
+    }
+
+    /// Like `find_employee_review_totals_for_update`, for the reviews
+    /// workers left for the company.
+    pub async fn find_company_review_totals_for_update(
+        conn: &mut AsyncPgConnection,
+        company_id: &i64,
+    ) -> Result<(i64, i64), diesel::result::Error> {
+        companies::table
+            .find(company_id)
+            .select(companies::id)
+            .for_update()
+            .first::<i64>(conn)
+            .await?;
+        let (count, stars) = reviews::table
+            .filter(reviews::company_id.eq(company_id))
+            .filter(reviews::direction.eq(ReviewDirection::EmployeeToCompany.as_str()))
+            .select((diesel::dsl::count_star(), diesel::dsl::sum(reviews::rating)))
+            .first::<(i64, Option<i64>)>(conn)
+            .await?;
+        Ok((count, stars.unwrap_or(0)))
+    }
+
+    pub async fn update_employee_rating(
+        conn: &mut AsyncPgConnection,
+        employee_id: &i64,
+        new_rating: f64,
+        count: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(employees::table.find(employee_id))
+            .set((employees::rating.eq(new_rating), employees::review_count.eq(count)))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn update_company_rating(
+        conn: &mut AsyncPgConnection,
+        company_id: &i64,
+        new_rating: f64,
+        count: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(companies::table.find(company_id))
+            .set((companies::rating.eq(new_rating), companies::review_count.eq(count)))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn find_approved_timesheets_for_job(
+        conn: &mut AsyncPgConnection,
+        job_id: &i64,
+    ) -> Result<Vec<Timesheet>, diesel::result::Error> {
+        timesheets::table
+            .filter(timesheets::job_id.eq(job_id))
+            .filter(timesheets::status.eq(TimesheetStatus::APPROVED.as_str()))
+            .select(Timesheet::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Approved hours of the given timesheets, by id.
+    pub async fn find_timesheet_hours(
+        conn: &mut AsyncPgConnection,
+        timesheet_ids: &[i64],
+    ) -> Result<Vec<(i64, Option<f64>)>, diesel::result::Error> {
+        timesheets::table
+            .filter(timesheets::id.eq_any(timesheet_ids))
+            .select((timesheets::id, timesheets::approved_hours))
+            .load(conn)
+            .await
+    }
+
+    /// Saves a ledger transaction, or returns `None` when it is for a
+    /// timesheet that was already charged.
+    pub async fn save_ledger_transaction(
+        conn: &mut AsyncPgConnection,
+        new_transaction: &NewLedgerTransaction,
+    ) -> Result<Option<LedgerTransaction>, diesel::result::Error> {
+        diesel::insert_into(ledger_transactions::table)
+            .values(new_transaction)
+            .on_conflict(ledger_transactions::timesheet_id)
+            .do_nothing()
+            .returning(LedgerTransaction::as_returning())
+            .get_result(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn save_ledger_entries(
+        conn: &mut AsyncPgConnection,
+        entries: &[NewLedgerEntry],
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::insert_into(ledger_entries::table)
+            .values(entries)
+            .execute(conn)
+            .await
+    }
+
+    pub async fn find_ledger_entries_for_transactions(
+        conn: &mut AsyncPgConnection,
+        transaction_ids: &[i64],
+    ) -> Result<Vec<LedgerEntry>, diesel::result::Error> {
+        ledger_entries::table
+            .filter(ledger_entries::transaction_id.eq_any(transaction_ids))
+            .select(LedgerEntry::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Companies with job costs not invoiced yet, the oldest from before
+    /// `cutoff`.
+    pub async fn find_companies_due_for_invoice(
+        conn: &mut AsyncPgConnection,
+        cutoff: NaiveDateTime,
+    ) -> Result<Vec<i64>, diesel::result::Error> {
+        let company_ids = ledger_transactions::table
+            .filter(ledger_transactions::kind.eq(LedgerTransactionKind::JobCost.as_str()))
+            .filter(ledger_transactions::invoice_id.is_null())
+            .filter(ledger_transactions::company_id.is_not_null())
+            .group_by(ledger_transactions::company_id)
+            .having(diesel::dsl::min(ledger_transactions::created_at).le(cutoff))
+            .select(ledger_transactions::company_id)
+            .load::<Option<i64>>(conn)
+            .await?;
+        Ok(company_ids.into_iter().flatten().collect())
+    }
+
+    /// The company's job costs not invoiced yet, locked. Rows another
+    /// instance is already invoicing are skipped.
+    pub async fn find_uninvoiced_costs_for_update(
+        conn: &mut AsyncPgConnection,
+        company_id: &i64,
+    ) -> Result<Vec<LedgerTransaction>, diesel::result::Error> {
+        ledger_transactions::table
+            .filter(ledger_transactions::kind.eq(LedgerTransactionKind::JobCost.as_str()))
+            .filter(ledger_transactions::invoice_id.is_null())
+            .filter(ledger_transactions::company_id.eq(company_id))
+            .order(ledger_transactions::created_at.asc())
+            .select(LedgerTransaction::as_select())
+            .for_update()
+            .skip_locked()
+            .load(conn)
+            .await
+    }
+
+    /// Job costs from before `cutoff` whose earnings no payout covers yet,
+    /// locked like `find_uninvoiced_costs_for_update`.
+    pub async fn find_unpaid_costs_for_update(
+        conn: &mut AsyncPgConnection,
+        cutoff: NaiveDateTime,
+    ) -> Result<Vec<LedgerTransaction>, diesel::result::Error> {
+        ledger_transactions::table
+            .filter(ledger_transactions::kind.eq(LedgerTransactionKind::JobCost.as_str()))
+            .filter(ledger_transactions::payout_id.is_null())
+            .filter(ledger_transactions::employee_id.is_not_null())
+            .filter(ledger_transactions::created_at.le(cutoff))
+            .select(LedgerTransaction::as_select())
+            .for_update()
+            .skip_locked()
+            .load(conn)
+            .await
+    }
+
+    pub async fn set_transactions_invoice(
+        conn: &mut AsyncPgConnection,
+        transaction_ids: &[i64],
+        invoice: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(
+            ledger_transactions::table.filter(ledger_transactions::id.eq_any(transaction_ids)),
+        )
+        .set(ledger_transactions::invoice_id.eq(invoice))
+        .execute(conn)
+        .await
+    }
+
+    pub async fn set_transactions_payout(
+        conn: &mut AsyncPgConnection,
+        transaction_ids: &[i64],
+        payout: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(
+            ledger_transactions::table.filter(ledger_transactions::id.eq_any(transaction_ids)),
+        )
+        .set(ledger_transactions::payout_id.eq(payout))
+        .execute(conn)
+        .await
+    }
+
+    /// Frees the earnings of a failed payout for the next batch.
+    pub async fn release_payout_transactions(
+        conn: &mut AsyncPgConnection,
+        payout: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(
+            ledger_transactions::table.filter(ledger_transactions::payout_id.eq(payout)),
+        )
+        .set(ledger_transactions::payout_id.eq(None::<i64>))
+        .execute(conn)
+        .await
+    }
+
+    pub async fn save_invoice(
+        conn: &mut AsyncPgConnection,
+        new_invoice: &NewInvoice,
+    ) -> Result<Invoice, diesel::result::Error> {
+        diesel::insert_into(invoices::table)
+            .values(new_invoice)
+            .returning(Invoice::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn save_invoice_lines(
+        conn: &mut AsyncPgConnection,
+        lines: &[NewInvoiceLine],
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::insert_into(invoice_lines::table)
+            .values(lines)
+            .execute(conn)
+            .await
+    }
+
+    pub async fn find_invoices_for_company(
+        conn: &mut AsyncPgConnection,
+        company_id: &i64,
+    ) -> Result<Vec<Invoice>, diesel::result::Error> {
+        invoices::table
+            .filter(invoices::company_id.eq(company_id))
+            .order(invoices::created_at.desc())
+            .select(Invoice::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn find_invoice_for_company(
+        conn: &mut AsyncPgConnection,
+        invoice_id: &i64,
+        company_id: &i64,
+    ) -> Result<Invoice, diesel::result::Error> {
+        invoices::table
+            .filter(invoices::id.eq(invoice_id))
+            .filter(invoices::company_id.eq(company_id))
+            .select(Invoice::as_select())
+            .first(conn)
+            .await
+    }
+
+    pub async fn find_invoice_lines(
+        conn: &mut AsyncPgConnection,
+        invoice_id: &i64,
+    ) -> Result<Vec<InvoiceLine>, diesel::result::Error> {
+        invoice_lines::table
+            .filter(invoice_lines::invoice_id.eq(invoice_id))
+            .order(invoice_lines::id.asc())
+            .select(InvoiceLine::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn save_payout_batch(
+        conn: &mut AsyncPgConnection,
+        status: &str,
+    ) -> Result<PayoutBatch, diesel::result::Error> {
+        diesel::insert_into(payout_batches::table)
+            .values(payout_batches::status.eq(status))
+            .returning(PayoutBatch::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn update_payout_batch(
+        conn: &mut AsyncPgConnection,
+        batch_id: &i64,
+        totals: &PayoutBatchTotals,
+    ) -> Result<PayoutBatch, diesel::result::Error> {
+        diesel::update(payout_batches::table.find(batch_id))
+            .set(totals)
+            .returning(PayoutBatch::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn find_payout_batches(
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<PayoutBatch>, diesel::result::Error> {
+        payout_batches::table
+            .order(payout_batches::created_at.desc())
+            .select(PayoutBatch::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn find_payout_batch(
+        conn: &mut AsyncPgConnection,
+        batch_id: &i64,
+    ) -> Result<PayoutBatch, diesel::result::Error> {
+        payout_batches::table
+            .find(batch_id)
+            .select(PayoutBatch::as_select())
+            .first(conn)
+            .await
+    }
+
+    pub async fn save_payout(
+        conn: &mut AsyncPgConnection,
+        new_payout: &NewPayout,
+    ) -> Result<Payout, diesel::result::Error> {
+        diesel::insert_into(payouts::table)
+            .values(new_payout)
+            .returning(Payout::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn update_payout(
+        conn: &mut AsyncPgConnection,
+        payout_id: &i64,
+        outcome: &PayoutOutcome,
+    ) -> Result<Payout, diesel::result::Error> {
+        diesel::update(payouts::table.find(payout_id))
+            .set(outcome)
+            .returning(Payout::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// The payout, locked, while it still waits for its outcome.
+    pub async fn find_pending_payout_for_update(
+        conn: &mut AsyncPgConnection,
+        payout_id: &i64,
+    ) -> Result<Option<Payout>, diesel::result::Error> {
+        payouts::table
+            .find(payout_id)
+            .filter(payouts::status.eq(PayoutStatus::PENDING.as_str()))
+            .select(Payout::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Payouts created before `cutoff` that still wait for their outcome,
+    /// oldest first.
+    pub async fn find_stale_pending_payouts(
+        conn: &mut AsyncPgConnection,
+        cutoff: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<Payout>, diesel::result::Error> {
+        payouts::table
+            .filter(payouts::status.eq(PayoutStatus::PENDING.as_str()))
+            .filter(payouts::created_at.lt(cutoff))
+            .order(payouts::created_at.asc())
+            .limit(limit)
+            .select(Payout::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn find_payouts_for_batch(
+        conn: &mut AsyncPgConnection,
+        batch_id: &i64,
+    ) -> Result<Vec<Payout>, diesel::result::Error> {
+        payouts::table
+            .filter(payouts::batch_id.eq(batch_id))
+            .order(payouts::id.asc())
+            .select(Payout::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn find_payouts_for_employee(
+        conn: &mut AsyncPgConnection,
+        employee_id: &i64,
+    ) -> Result<Vec<Payout>, diesel::result::Error> {
+        payouts::table
+            .filter(payouts::employee_id.eq(employee_id))
+            .order(payouts::created_at.desc())
+            .select(Payout::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Deletes events older than `cutoff`; their recipients go with them.
+    pub async fn delete_ws_events_before(
+        conn: &mut AsyncPgConnection,
+        cutoff: NaiveDateTime,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(ws_events::table.filter(ws_events::created_at.lt(cutoff)))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn save_refresh_token(
+        conn: &mut AsyncPgConnection,
+        new_token: &NewRefreshToken,
+    ) -> Result<RefreshToken, diesel::result::Error> {
+        diesel::insert_into(refresh_tokens::table)
+            .values(new_token)
+            .returning(RefreshToken::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    /// Locks the token so two refreshes racing with the same token cannot
+    /// both rotate it.
+    pub async fn find_refresh_token_by_hash_for_update(
+        conn: &mut AsyncPgConnection,
+        hash: &str,
+    ) -> Result<RefreshToken, diesel::result::Error> {
+        refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(hash))
+            .select(RefreshToken::as_select())
+            .for_update()
+            .first(conn)
+            .await
+    }
+
+    pub async fn mark_refresh_token_used(
+        conn: &mut AsyncPgConnection,
+        token_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(refresh_tokens::table.find(token_id))
+            .set(refresh_tokens::used_at.eq(diesel::dsl::now))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn revoke_refresh_token_family(
+        conn: &mut AsyncPgConnection,
+        family_id: &Uuid,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(
+            refresh_tokens::table
+                .filter(refresh_tokens::family_id.eq(family_id))
+                .filter(refresh_tokens::revoked_at.is_null()),
+        )
+        .set(refresh_tokens::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await
+    }
+
+    pub async fn revoke_user_refresh_tokens(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(
+            refresh_tokens::table
+                .filter(refresh_tokens::user_id.eq(user_id))
+                .filter(refresh_tokens::revoked_at.is_null()),
+        )
+        .set(refresh_tokens::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await
+    }
+
+    /// Whether the session still has a token that was not revoked.
+    pub async fn refresh_token_family_active(
+        conn: &mut AsyncPgConnection,
+        family_id: &Uuid,
+        user_id: &i64,
+    ) -> Result<bool, diesel::result::Error> {
+        diesel::select(diesel::dsl::exists(
+            refresh_tokens::table
+                .filter(refresh_tokens::family_id.eq(family_id))
+                .filter(refresh_tokens::user_id.eq(user_id))
+                .filter(refresh_tokens::revoked_at.is_null()),
+        ))
+        .get_result(conn)
+        .await
+    }
+
+    /// The membership behind `users.companyid`, if the user belongs to a company.
+    pub async fn find_company_membership(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+    ) -> Result<Option<CompanyMember>, diesel::result::Error> {
+        company_members::table
+            .filter(company_members::user_id.eq(user_id))
+            .select(CompanyMember::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn save_company_member(
+        conn: &mut AsyncPgConnection,
+        new_member: &NewCompanyMember,
+    ) -> Result<CompanyMember, diesel::result::Error> {
+        diesel::insert_into(company_members::table)
+            .values(new_member)
+            .returning(CompanyMember::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn find_company_members(
+        conn: &mut AsyncPgConnection,
+        company_id: &i64,
+    ) -> Result<Vec<CompanyMemberWithLogin>, diesel::result::Error> {
+        let rows = company_members::table
+            .inner_join(users::table)
+            .filter(company_members::company_id.eq(company_id))
+            .order(company_members::created_at.asc())
+            .select((
+                company_members::user_id,
+                users::login,
+                company_members::role,
+                company_members::created_at,
+            ))
+            .load::<(i64, String, String, NaiveDateTime)>(conn)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(user_id, login, role, created_at)| CompanyMemberWithLogin {
+                user_id,
+                login,
+                role,
+                created_at,
+            })
+            .collect())
+    }
+
+    /// Locks every membership of the company, so concurrent removals cannot
+    /// both see another admin left.
+    pub async fn find_company_members_for_update(
+        conn: &mut AsyncPgConnection,
+        company_id: &i64,
+    ) -> Result<Vec<CompanyMember>, diesel::result::Error> {
+        company_members::table
+            .filter(company_members::company_id.eq(company_id))
+            .select(CompanyMember::as_select())
+            .for_update()
+            .load(conn)
+            .await
+    }
+
+    pub async fn delete_company_member(
+        conn: &mut AsyncPgConnection,
+        member_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(company_members::table.find(member_id))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn update_user_company(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+        company_id: Option<i64>,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(users::table.find(user_id))
+            .set(companyid.eq(company_id))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn save_company_invitation(
+        conn: &mut AsyncPgConnection,
+        new_invitation: &NewCompanyInvitation,
+    ) -> Result<CompanyInvitation, diesel::result::Error> {
+        diesel::insert_into(company_invitations::table)
+            .values(new_invitation)
+            .returning(CompanyInvitation::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn find_company_invitation_by_hash_for_update(
+        conn: &mut AsyncPgConnection,
+        hash: &str,
+    ) -> Result<Option<CompanyInvitation>, diesel::result::Error> {
+        company_invitations::table
+            .filter(company_invitations::token_hash.eq(hash))
+            .select(CompanyInvitation::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn mark_company_invitation_accepted(
+        conn: &mut AsyncPgConnection,
+        invitation_id: &i64,
+        user_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(company_invitations::table.find(invitation_id))
+            .set((
+                company_invitations::accepted_by.eq(user_id),
+                company_invitations::accepted_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn find_user_by_email(
+        conn: &mut AsyncPgConnection,
+        user_email: &str,
+    ) -> Result<Option<User>, diesel::result::Error> {
+        users::table
+            .filter(users::email.eq(user_email))
+            .first::<User>(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn update_user_password(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+        hashed_password: &str,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(users::table.find(user_id))
+            .set(users::password.eq(hashed_password))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn mark_user_email_verified(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(users::table.find(user_id))
+            .set(users::email_verified_at.eq(diesel::dsl::now))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn save_account_token(
+        conn: &mut AsyncPgConnection,
+        new_token: &NewAccountToken,
+    ) -> Result<AccountToken, diesel::result::Error> {
+        diesel::insert_into(account_tokens::table)
+            .values(new_token)
+            .returning(AccountToken::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn find_account_token_by_hash_for_update(
+        conn: &mut AsyncPgConnection,
+        hash: &str,
+        purpose: AccountTokenPurpose,
+    ) -> Result<Option<AccountToken>, diesel::result::Error> {
+        account_tokens::table
+            .filter(account_tokens::token_hash.eq(hash))
+            .filter(account_tokens::purpose.eq(purpose.as_str()))
+            .select(AccountToken::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Uses up every outstanding token of the user for `purpose`, so only the
+    /// latest mail, or none, stays valid.
+    pub async fn use_up_account_tokens(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+        purpose: AccountTokenPurpose,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(
+            account_tokens::table
+                .filter(account_tokens::user_id.eq(user_id))
+                .filter(account_tokens::purpose.eq(purpose.as_str()))
+                .filter(account_tokens::used_at.is_null()),
+        )
+        .set(account_tokens::used_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await
+    }
+
+    pub async fn find_login_throttles(
+        conn: &mut AsyncPgConnection,
+        keys: &[String],
+    ) -> Result<Vec<LoginThrottle>, diesel::result::Error> {
+        login_throttles::table
+            .filter(login_throttles::key.eq_any(keys))
+            .select(LoginThrottle::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn find_login_throttle_for_update(
+        conn: &mut AsyncPgConnection,
+        throttle_key: &str,
+    ) -> Result<Option<LoginThrottle>, diesel::result::Error> {
+        login_throttles::table
+            .find(throttle_key)
+            .select(LoginThrottle::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn save_login_throttle(
+        conn: &mut AsyncPgConnection,
+        throttle: &LoginThrottle,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::insert_into(login_throttles::table)
+            .values(throttle)
+            .on_conflict(login_throttles::key)
+            .do_update()
+            .set(throttle)
+            .execute(conn)
+            .await
+    }
+
+    pub async fn delete_login_throttle(
+        conn: &mut AsyncPgConnection,
+        throttle_key: &str,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(login_throttles::table.find(throttle_key))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn save_failed_login(
+        conn: &mut AsyncPgConnection,
+        failed_login: &NewFailedLogin,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::insert_into(failed_logins::table)
+            .values(failed_login)
+            .execute(conn)
+            .await
+    }
+
+    pub async fn find_user_mfa_for_update(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+    ) -> Result<Option<UserMfa>, diesel::result::Error> {
+        user_mfa::table
+            .find(user_id)
+            .select(UserMfa::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn user_mfa_enabled(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+    ) -> Result<bool, diesel::result::Error> {
+        diesel::select(diesel::dsl::exists(
+            user_mfa::table
+                .filter(user_mfa::user_id.eq(user_id))
+                .filter(user_mfa::confirmed_at.is_not_null()),
+        ))
+        .get_result(conn)
+        .await
+    }
+
+    /// Starts enrolment over with a new secret.
+    pub async fn save_user_mfa(
+        conn: &mut AsyncPgConnection,
+        new_mfa: &NewUserMfa,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::insert_into(user_mfa::table)
+            .values(new_mfa)
+            .on_conflict(user_mfa::user_id)
+            .do_update()
+            .set((
+                user_mfa::secret.eq(&new_mfa.secret),
+                user_mfa::confirmed_at.eq(None::<NaiveDateTime>),
+                user_mfa::last_used_step.eq(None::<i64>),
+                user_mfa::created_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn confirm_user_mfa(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+        step: i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(user_mfa::table.find(user_id))
+            .set((
+                user_mfa::confirmed_at.eq(diesel::dsl::now),
+                user_mfa::last_used_step.eq(step),
+            ))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn update_user_mfa_last_step(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+        step: i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(user_mfa::table.find(user_id))
+            .set(user_mfa::last_used_step.eq(step))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn delete_user_mfa(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(user_mfa::table.find(user_id))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn save_mfa_recovery_codes(
+        conn: &mut AsyncPgConnection,
+        codes: &[NewMfaRecoveryCode],
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::insert_into(mfa_recovery_codes::table)
+            .values(codes)
+            .execute(conn)
+            .await
+    }
+
+    pub async fn delete_mfa_recovery_codes(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(user_id)))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn find_unused_mfa_recovery_code_for_update(
+        conn: &mut AsyncPgConnection,
+        user_id: &i64,
+        hash: &str,
+    ) -> Result<Option<MfaRecoveryCode>, diesel::result::Error> {
+        mfa_recovery_codes::table
+            .filter(mfa_recovery_codes::user_id.eq(user_id))
+            .filter(mfa_recovery_codes::code_hash.eq(hash))
+            .filter(mfa_recovery_codes::used_at.is_null())
+            .select(MfaRecoveryCode::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn mark_mfa_recovery_code_used(
+        conn: &mut AsyncPgConnection,
+        code_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(mfa_recovery_codes::table.find(code_id))
+            .set(mfa_recovery_codes::used_at.eq(diesel::dsl::now))
+            .execute(conn)
+            .await
+    }
+
+    pub async fn save_company_api_key(
+        conn: &mut AsyncPgConnection,
+        new_key: &NewCompanyApiKey,
+    ) -> Result<CompanyApiKey, diesel::result::Error> {
+        diesel::insert_into(company_api_keys::table)
+            .values(new_key)
+            .returning(CompanyApiKey::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn find_company_api_keys(
+        conn: &mut AsyncPgConnection,
+        company_id: &i64,
+    ) -> Result<Vec<CompanyApiKey>, diesel::result::Error> {
+        company_api_keys::table
+            .filter(company_api_keys::company_id.eq(company_id))
+            .order(company_api_keys::created_at.desc())
+            .select(CompanyApiKey::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn find_active_company_api_key_by_hash(
+        conn: &mut AsyncPgConnection,
+        hash: &str,
+    ) -> Result<Option<CompanyApiKey>, diesel::result::Error> {
+        company_api_keys::table
+            .filter(company_api_keys::key_hash.eq(hash))
+            .filter(company_api_keys::revoked_at.is_null())
+            .select(CompanyApiKey::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Records a use of the key, at most once a minute so busy integrations
+    /// do not write on every request.
+    pub async fn touch_company_api_key(
+        conn: &mut AsyncPgConnection,
+        key_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        let a_minute_ago = diesel::dsl::now - diesel::dsl::IntervalDsl::minutes(1);
+        diesel::update(
+            company_api_keys::table.find(key_id).filter(
+                company_api_keys::last_used_at
+                    .is_null()
+                    .or(company_api_keys::last_used_at.lt(a_minute_ago.nullable())),
+            ),
+        )
+        .set(company_api_keys::last_used_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await
+    }
+
+    pub async fn revoke_company_api_key(
+        conn: &mut AsyncPgConnection,
+        company_id: &i64,
+        key_id: &i64,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(
+            company_api_keys::table
+                .find(key_id)
+                .filter(company_api_keys::company_id.eq(company_id))
+                .filter(company_api_keys::revoked_at.is_null()),
+        )
+        .set(company_api_keys::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await
+    }
 }
@@ -0,0 +1,191 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, Method},
+};
+
+use crate::{
+    application::error::AppError,
+    domain::{
+        enums::{ApiKeyScope, Role},
+        models::User,
+    },
+};
+
+/// Roles of the caller, put on the request by `Auth::authorize`. They are
+/// worked out from the user as stored rather than taken from the token, so a
+/// role that was revoked stops working before the token expires.
+#[derive(Clone, Debug)]
+pub struct Roles(pub Vec<Role>);
+
+impl Roles {
+    pub fn has_any(&self, allowed: &[Role]) -> bool {
+        self.0.iter().any(|role| allowed.contains(role))
+    }
+}
+
+/// The company API key a request was made with, put on the request by
+/// `Auth::authorize` instead of a `User`. Its only role is `Role::ApiClient`.
+#[derive(Clone, Debug)]
+pub struct ApiKeyPrincipal {
+    pub company_id: i64,
+}
+
+/// The scope an API key needs for a route, `None` when API keys cannot be
+/// used there at all. Routes must be listed here to be reachable with a key.
+pub fn required_scope(method: &Method, path: &str) -> Option<ApiKeyScope> {
+    match (method.as_str(), path) {
+        ("GET", "/companies") => Some(ApiKeyScope::CompanyRead),
+        ("POST", "/jobs" | "/jobs/:id/complete" | "/jobs/:id/cancel") => {
+            Some(ApiKeyScope::JobsWrite)
+        }
+        ("GET", "/jobs/:id/applications") => Some(ApiKeyScope::ApplicationsRead),
+        ("POST", "/applications/:id/accept" | "/applications/:id/reject") => {
+            Some(ApiKeyScope::ApplicationsWrite)
+        }
+        ("GET", "/jobs/:id/timesheets") => Some(ApiKeyScope::TimesheetsRead),
+        ("POST", "/timesheets/:id/approve" | "/timesheets/:id/dispute") => {
+            Some(ApiKeyScope::TimesheetsWrite)
+        }
+        ("GET", "/invoices" | "/invoices/:id" | "/invoices/:id/export") => {
+            Some(ApiKeyScope::InvoicesRead)
+        }
+        _ => None,
+    }
+}
+
+/// Fails when the route is not behind `Auth::authorize` or the caller holds
+/// none of `allowed`.
+fn check_roles(parts: &Parts, allowed: &[Role]) -> Result<(), AppError> {
+    let Some(roles) = parts.extensions.get::<Roles>() else {
+        return Err(AppError::unauthorized("Authentication required"));
+    };
+
+    if !roles.has_any(allowed) {
+        return Err(AppError::forbidden("You are not allowed to do this"));
+    }
+    Ok(())
+}
+
+/// The authenticated user, when they hold one of `allowed`.
+fn authorized(parts: &Parts, allowed: &[Role]) -> Result<User, AppError> {
+    check_roles(parts, allowed)?;
+    parts
+        .extensions
+        .get::<User>()
+        .cloned()
+        .ok_or_else(|| AppError::forbidden("You are not allowed to do this"))
+}
+
+/// The caller holds the role but the profile it needs is missing.
+fn no_profile() -> AppError {
+    AppError::forbidden("No profile linked to this role")
+}
+
+/// A signed-in person, whatever roles they hold so far, such as someone
+/// about to set up their profile. API keys do not qualify.
+pub struct SignedInUser(pub User);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for SignedInUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if parts.extensions.get::<Roles>().is_none() {
+            return Err(AppError::unauthorized("Authentication required"));
+        }
+        parts
+            .extensions
+            .get::<User>()
+            .cloned()
+            .map(Self)
+            .ok_or_else(|| AppError::forbidden("You are not allowed to do this"))
+    }
+}
+
+/// A caller setting up a company, or editing theirs as its admin.
+pub struct CompanyEditor(pub User);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CompanyEditor {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let SignedInUser(user) = SignedInUser::from_request_parts(parts, state).await?;
+        if user.companyid.is_some() {
+            check_roles(parts, &[Role::CompanyAdmin])
+                .map_err(|_| AppError::forbidden("Only company admins can edit the company"))?;
+        }
+        Ok(Self(user))
+    }
+}
+
+/// A caller acting as an employee.
+pub struct EmployeeUser {
+    pub employee_id: i64,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for EmployeeUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = authorized(parts, &[Role::Employee])?;
+        let employee_id = user.employeeid.ok_or_else(no_profile)?;
+        Ok(Self { employee_id })
+    }
+}
+
+/// A caller acting for their company, as its admin or one of its members,
+/// or through one of its API keys.
+pub struct CompanyStaff {
+    pub company_id: i64,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CompanyStaff {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        check_roles(
+            parts,
+            &[Role::CompanyAdmin, Role::CompanyMember, Role::ApiClient],
+        )?;
+        if let Some(api_key) = parts.extensions.get::<ApiKeyPrincipal>() {
+            return Ok(Self {
+                company_id: api_key.company_id,
+            });
+        }
+        let user = authorized(parts, &[Role::CompanyAdmin, Role::CompanyMember])?;
+        let company_id = user.companyid.ok_or_else(no_profile)?;
+        Ok(Self { company_id })
+    }
+}
+
+/// A caller administering their company.
+pub struct CompanyAdmin {
+    pub company_id: i64,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CompanyAdmin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = authorized(parts, &[Role::CompanyAdmin])?;
+        let company_id = user.companyid.ok_or_else(no_profile)?;
+        Ok(Self { company_id })
+    }
+}
+
+/// A caller operating the platform itself.
+pub struct PlatformAdmin;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for PlatformAdmin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        authorized(parts, &[Role::PlatformAdmin]).map(|_| Self)
+    }
+}
@@ -1,5 +1,24 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "job_status"))]
+    pub struct JobStatus;
+}
+
+diesel::table! {
+    account_tokens (id) {
+        id -> Int8,
+        user_id -> Int8,
+        purpose -> Varchar,
+        token_hash -> Varchar,
+        email -> Nullable<Varchar>,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     companies (id) {
         id -> Int8,
@@ -7,6 +26,48 @@ diesel::table! {
         description -> Varchar,
         address -> Varchar,
         logo_url -> Varchar,
+        rating -> Float8,
+        review_count -> Int4,
+    }
+}
+
+diesel::table! {
+    company_api_keys (id) {
+        id -> Int8,
+        company_id -> Int8,
+        name -> Varchar,
+        prefix -> Varchar,
+        key_hash -> Varchar,
+        scopes -> Array<Text>,
+        created_by -> Nullable<Int8>,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    company_invitations (id) {
+        id -> Int8,
+        company_id -> Int8,
+        role -> Varchar,
+        invited_login -> Nullable<Varchar>,
+        token_hash -> Varchar,
+        invited_by -> Int8,
+        expires_at -> Timestamp,
+        accepted_by -> Nullable<Int8>,
+        accepted_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    company_members (id) {
+        id -> Int8,
+        company_id -> Int8,
+        user_id -> Int8,
+        role -> Varchar,
+        created_at -> Timestamp,
     }
 }
 
@@ -23,10 +84,63 @@ diesel::table! {
         latitude -> Float8,
         longitude -> Float8,
         rating -> Float8,
+        review_count -> Int4,
+    }
+}
+
+diesel::table! {
+    failed_logins (id) {
+        id -> Int8,
+        login -> Varchar,
+        ip -> Varchar,
+        user_id -> Nullable<Int8>,
+        reason -> Varchar,
+        created_at -> Timestamp,
     }
 }
 
 diesel::table! {
+    invoice_lines (id) {
+        id -> Int8,
+        invoice_id -> Int8,
+        ledger_transaction_id -> Int8,
+        job_id -> Nullable<Int8>,
+        employee_id -> Nullable<Int8>,
+        description -> Text,
+        hours -> Float8,
+        amount_cents -> Int8,
+        fee_cents -> Int8,
+    }
+}
+
+diesel::table! {
+    invoices (id) {
+        id -> Int8,
+        company_id -> Int8,
+        period_start -> Timestamp,
+        period_end -> Timestamp,
+        subtotal_cents -> Int8,
+        fee_cents -> Int8,
+        total_cents -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    job_applications (id) {
+        id -> Int8,
+        job_id -> Int8,
+        employee_id -> Int8,
+        status -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::JobStatus;
+
     job_opportunities (id) {
         id -> Int8,
         title -> Varchar,
@@ -38,8 +152,172 @@ diesel::table! {
         start_date_time -> Varchar,
         duration_in_hours -> Int4,
         pay_rate -> Float8,
-        status -> Varchar,
+        status -> JobStatus,
+        company_id -> Nullable<Int8>,
+        headcount -> Int4,
+        assigned_slots -> Int4,
+        remaining_slots -> Int4,
+    }
+}
+
+diesel::table! {
+    ledger_entries (id) {
+        id -> Int8,
+        transaction_id -> Int8,
+        account -> Varchar,
+        company_id -> Nullable<Int8>,
+        employee_id -> Nullable<Int8>,
+        side -> Varchar,
+        amount_cents -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    ledger_transactions (id) {
+        id -> Int8,
+        kind -> Varchar,
         company_id -> Nullable<Int8>,
+        employee_id -> Nullable<Int8>,
+        job_id -> Nullable<Int8>,
+        timesheet_id -> Nullable<Int8>,
+        description -> Text,
+        invoice_id -> Nullable<Int8>,
+        payout_id -> Nullable<Int8>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    login_throttles (key) {
+        key -> Varchar,
+        failures -> Int4,
+        last_failure_at -> Timestamp,
+        blocked_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    mfa_recovery_codes (id) {
+        id -> Int8,
+        user_id -> Int8,
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    payout_batches (id) {
+        id -> Int8,
+        status -> Varchar,
+        payout_count -> Int4,
+        total_cents -> Int8,
+        created_at -> Timestamp,
+        completed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    payouts (id) {
+        id -> Int8,
+        batch_id -> Int8,
+        employee_id -> Int8,
+        status -> Varchar,
+        amount_cents -> Int8,
+        provider -> Varchar,
+        provider_reference -> Nullable<Varchar>,
+        failure_reason -> Nullable<Text>,
+        created_at -> Timestamp,
+        paid_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (id) {
+        id -> Int8,
+        user_id -> Int8,
+        family_id -> Uuid,
+        token_hash -> Varchar,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    reviews (id) {
+        id -> Int8,
+        job_id -> Int8,
+        employee_id -> Int8,
+        company_id -> Int8,
+        direction -> Varchar,
+        author_user_id -> Nullable<Int8>,
+        rating -> Int4,
+        comment -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    shifts (id) {
+        id -> Int8,
+        job_id -> Int8,
+        employee_id -> Int8,
+        check_in_at -> Timestamp,
+        check_in_latitude -> Float8,
+        check_in_longitude -> Float8,
+        check_in_distance_km -> Float8,
+        check_out_at -> Nullable<Timestamp>,
+        check_out_latitude -> Nullable<Float8>,
+        check_out_longitude -> Nullable<Float8>,
+        check_out_distance_km -> Nullable<Float8>,
+        worked_hours -> Nullable<Float8>,
+    }
+}
+
+diesel::table! {
+    timesheet_events (id) {
+        id -> Int8,
+        timesheet_id -> Int8,
+        action -> Varchar,
+        actor_user_id -> Nullable<Int8>,
+        net_hours -> Float8,
+        note -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    timesheets (id) {
+        id -> Int8,
+        shift_id -> Int8,
+        job_id -> Int8,
+        employee_id -> Int8,
+        status -> Varchar,
+        submitted_hours -> Float8,
+        break_minutes -> Int4,
+        net_hours -> Float8,
+        pay_rate -> Float8,
+        note -> Nullable<Text>,
+        dispute_reason -> Nullable<Text>,
+        approved_hours -> Nullable<Float8>,
+        payable_amount -> Nullable<Float8>,
+        submitted_at -> Timestamp,
+        decided_at -> Nullable<Timestamp>,
+        decided_by -> Nullable<Int8>,
+        auto_approved -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_mfa (user_id) {
+        user_id -> Int8,
+        secret -> Varchar,
+        confirmed_at -> Nullable<Timestamp>,
+        last_used_step -> Nullable<Int8>,
+        created_at -> Timestamp,
     }
 }
 
@@ -50,16 +328,96 @@ diesel::table! {
         password -> Varchar,
         companyid -> Nullable<Int8>,
         employeeid -> Nullable<Int8>,
+        is_platform_admin -> Bool,
+        email -> Nullable<Varchar>,
+        email_verified_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    ws_event_recipients (user_id, event_seq) {
+        user_id -> Int8,
+        event_seq -> Int8,
+    }
+}
+
+diesel::table! {
+    ws_events (seq) {
+        seq -> Int8,
+        body -> Text,
+        created_at -> Timestamp,
     }
 }
 
+diesel::joinable!(company_invitations -> companies (company_id));
+diesel::joinable!(company_members -> companies (company_id));
+diesel::joinable!(account_tokens -> users (user_id));
+diesel::joinable!(company_api_keys -> companies (company_id));
+diesel::joinable!(company_api_keys -> users (created_by));
+diesel::joinable!(company_members -> users (user_id));
+diesel::joinable!(failed_logins -> users (user_id));
+diesel::joinable!(invoice_lines -> invoices (invoice_id));
+diesel::joinable!(invoice_lines -> ledger_transactions (ledger_transaction_id));
+diesel::joinable!(invoices -> companies (company_id));
+diesel::joinable!(job_applications -> employees (employee_id));
+diesel::joinable!(job_applications -> job_opportunities (job_id));
 diesel::joinable!(job_opportunities -> companies (company_id));
+diesel::joinable!(ledger_entries -> companies (company_id));
+diesel::joinable!(ledger_entries -> employees (employee_id));
+diesel::joinable!(ledger_entries -> ledger_transactions (transaction_id));
+diesel::joinable!(ledger_transactions -> companies (company_id));
+diesel::joinable!(ledger_transactions -> employees (employee_id));
+diesel::joinable!(ledger_transactions -> invoices (invoice_id));
+diesel::joinable!(ledger_transactions -> job_opportunities (job_id));
+diesel::joinable!(ledger_transactions -> payouts (payout_id));
+diesel::joinable!(ledger_transactions -> timesheets (timesheet_id));
+diesel::joinable!(mfa_recovery_codes -> users (user_id));
+diesel::joinable!(payouts -> employees (employee_id));
+diesel::joinable!(payouts -> payout_batches (batch_id));
+diesel::joinable!(refresh_tokens -> users (user_id));
+diesel::joinable!(reviews -> companies (company_id));
+diesel::joinable!(reviews -> employees (employee_id));
+diesel::joinable!(reviews -> job_opportunities (job_id));
+diesel::joinable!(reviews -> users (author_user_id));
+diesel::joinable!(shifts -> employees (employee_id));
+diesel::joinable!(shifts -> job_opportunities (job_id));
+diesel::joinable!(timesheet_events -> timesheets (timesheet_id));
+diesel::joinable!(timesheet_events -> users (actor_user_id));
+diesel::joinable!(timesheets -> employees (employee_id));
+diesel::joinable!(timesheets -> job_opportunities (job_id));
+diesel::joinable!(timesheets -> shifts (shift_id));
+diesel::joinable!(timesheets -> users (decided_by));
+diesel::joinable!(user_mfa -> users (user_id));
 diesel::joinable!(users -> companies (companyid));
 diesel::joinable!(users -> employees (employeeid));
+diesel::joinable!(ws_event_recipients -> users (user_id));
+diesel::joinable!(ws_event_recipients -> ws_events (event_seq));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    account_tokens,
     companies,
+    company_api_keys,
+    company_invitations,
+    company_members,
     employees,
+    failed_logins,
+    invoice_lines,
+    invoices,
+    job_applications,
     job_opportunities,
+    ledger_entries,
+    ledger_transactions,
+    login_throttles,
+    mfa_recovery_codes,
+    payout_batches,
+    payouts,
+    refresh_tokens,
+    reviews,
+    shifts,
+    timesheet_events,
+    timesheets,
+    user_mfa,
     users,
+    ws_event_recipients,
+    ws_events,
 );
@@ -1,15 +1,27 @@
 use axum::{
-    extract::{connect_info::ConnectInfo, Multipart, Path, State},
-    http::StatusCode,
+    extract::{connect_info::ConnectInfo, Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post},
     Extension, Json, Router,
 };
-use diesel_migrations::{embed_migrations, EmbeddedMigrations};
-
 use diesel_async::{pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection, AsyncConnection};
-use domain::models::{NewCompany, NewEmployee, NewJobOpportunity, NewUser, User};
-use infrastructure::auth::{self, Auth, SignInData};
+use domain::models::{
+    ForgotPasswordRequest, JobSearchQuery, MfaCodeRequest, MfaEnrollment, MfaRecoveryCodes,
+    NewChatMessage, NewCompany, NewEmployee, NewJobOpportunity, ResetPasswordRequest, User,
+    VerifyEmailQuery,
+};
+use infrastructure::auth::{
+    self, Auth, Claims, MfaVerifyRequest, RefreshRequest, RegisterData, SignInData,
+    SignInResponse, TokenPair,
+};
+use infrastructure::extract::{ValidJson, ValidQuery};
+use infrastructure::jwt::JwtConfig;
+use infrastructure::mailer::AccountMailer;
+use infrastructure::payments::Payments;
+use infrastructure::roles::{
+    CompanyAdmin, CompanyEditor, CompanyStaff, EmployeeUser, PlatformAdmin, SignedInUser,
+};
 use std::{net::SocketAddr, path::PathBuf};
 use tokio::net::TcpListener;
 use tower_http::{
@@ -20,24 +32,62 @@ use tower_http::{
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 mod infrastructure {
     pub mod auth;
+    pub mod extract;
+    pub mod jwt;
+    pub mod mailer;
+    pub mod payments;
     pub mod repositories;
+    pub mod roles;
     pub mod schema;
+    pub mod totp;
 }
 
 mod websocket {
+    pub mod broadcast;
+    pub mod config;
+    pub mod outbound;
+    pub mod protocol;
+    #[allow(clippy::module_inception)]
     pub mod websocket;
 }
 mod domain {
+    pub mod enums;
     pub mod models;
+    pub mod validation;
 }
 mod application {
+    pub mod billing;
+    pub mod dispatch;
+    pub mod error;
+    pub mod login_throttle;
+    pub mod review;
     pub mod service;
+    pub mod shift;
+    pub mod timesheet;
 }
+use self::application::billing::{self, BillingConfig};
+use self::application::dispatch::JobDispatcher;
+use self::application::error::AppError;
+use self::application::login_throttle::LoginThrottleConfig;
+use self::application::review::ReviewConfig;
 use self::application::service::Service;
-use self::domain::models::{Company, Employee, JobOpportunity};
+use self::application::shift::ShiftConfig;
+use self::application::timesheet::TimesheetConfig;
+use self::domain::enums::{ApplicationStatus, JobStatus};
+use self::domain::models::{
+    BillingRun, Company, CompanyApiKey, CompanyInvitationToken, CompanyMemberWithLogin,
+    CreatedApiKey, DisputeTimesheetRequest, Employee, InviteMemberRequest, Invoice,
+    InvoiceWithLines, JobApplication, JobApplicationWithEmployee, JobOpportunity,
+    NewApiKeyRequest, NewReviewRequest, Payout, PayoutBatch, PayoutBatchWithPayouts, Review,
+    ShiftLocation, ShiftReport, Timesheet, TimesheetSubmission,
+};
 
-use crate::websocket::websocket::WebSocketManager;
-use axum::extract::ws::Message;
+use crate::websocket::{
+    broadcast::{self, BroadcastBackend},
+    config::WsConfig,
+    protocol::ServerEvent,
+    websocket::WebSocketManager,
+};
 
 const SERVER_ADDR: &str = "0.0.0.0:9854";
 const ASSETS_DIR: &str = "assets";
@@ -55,110 +105,213 @@ fn init_tracing() {
 }
 
 pub async fn send_message_handler(
-    Path(addr): Path<String>,
+    Path(user_id): Path<i64>,
     State(ws_manager): State<WebSocketManager>,
+    SignedInUser(user): SignedInUser,
+    ValidJson(message): ValidJson<NewChatMessage>,
 ) -> impl IntoResponse {
-    if let Ok(client_addr) = addr.parse::<SocketAddr>() {
-        let msg = Message::Text("Hello, World!".to_string());
-        ws_manager.send_to_client(client_addr, msg).await;
-        Json("status: Message sent".to_string()).into_response()
-    } else {
-        Json("error: Invalid address format".to_string()).into_response()
-    }
+    let event = ServerEvent::ChatMessage {
+        from_user_id: user.id,
+        text: message.text,
+    };
+    ws_manager.send_event(user_id, &event).await;
+    Json("status: Message sent".to_string()).into_response()
+}
+
+async fn ws_metrics(
+    State(ws_manager): State<WebSocketManager>,
+    _admin: PlatformAdmin,
+) -> impl IntoResponse {
+    Json(ws_manager.metrics())
 }
 
 async fn get_employee(
     State(pool): State<Pool>,
-    Extension(user): Extension<User>,
-) -> Result<Json<Employee>, StatusCode> {
-    let mut conn = pool
-        .get()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    match user.employeeid {
-        Some(id) => match Service::find_employee(&mut conn, &id).await {
-            Ok(employee) => Ok(Json(employee)),
-            Err(_) => Err(StatusCode::NOT_FOUND),
-        },
-        None => Err(StatusCode::NOT_FOUND),
-    }
+    employee: EmployeeUser,
+) -> Result<Json<Employee>, AppError> {
+    let mut conn = pool.get().await?;
+
+    let employee = Service::find_employee(&mut conn, &employee.employee_id).await?;
+    Ok(Json(employee))
 }
 
 async fn get_company(
     State(pool): State<Pool>,
-    Extension(user): Extension<User>,
-) -> Result<Json<Company>, StatusCode> {
-    let mut conn = pool
-        .get()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    match user.companyid {
-        Some(id) => match Service::find_company(&mut conn, &id).await {
-            Ok(company) => Ok(Json(company)),
-            Err(_) => Err(StatusCode::NOT_FOUND),
-        },
-        None => Err(StatusCode::NOT_FOUND),
-    }
+    staff: CompanyStaff,
+) -> Result<Json<Company>, AppError> {
+    let mut conn = pool.get().await?;
+
+    let company = Service::find_company(&mut conn, &staff.company_id).await?;
+    Ok(Json(company))
 }
 
 async fn create_employee(
     State(pool): State<Pool>,
-    Extension(user): Extension<User>,
-    Json(employee): Json<NewEmployee>,
-) -> Result<Json<Employee>, StatusCode> {
-    let mut conn = pool
-        .get()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let res = Service::add_employee(&mut conn, employee, user)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    SignedInUser(user): SignedInUser,
+    ValidJson(employee): ValidJson<NewEmployee>,
+) -> Result<Json<Employee>, AppError> {
+    let mut conn = pool.get().await?;
+    let res = Service::add_employee(&mut conn, employee, user).await?;
     Ok(res)
 }
 
 pub async fn register_user(
     State(pool): State<Pool>,
-    Json(new_user): Json<NewUser>,
-) -> Result<String, (StatusCode, String)> {
-    let mut conn = pool.get().await.map_err(internal_error)?;
-    let token = Service::register_user(&mut conn, new_user)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Extension(mailer): Extension<AccountMailer>,
+    ValidJson(data): ValidJson<RegisterData>,
+) -> Result<Json<TokenPair>, AppError> {
+    let mut conn = pool.get().await?;
+    let user = Service::register_user(&mut conn, data.user, data.invitation_token).await?;
+    if let Err(e) = Service::send_email_verification(&mut conn, &mailer, &user).await {
+        tracing::error!("Unable to start email verification: {e}");
+    }
+    let tokens = Service::start_session(&mut conn, &user).await?;
 
-    Ok(token)
+    Ok(Json(tokens))
 }
 
 async fn login(
     State(pool): State<Pool>,
+    Extension(throttle): Extension<LoginThrottleConfig>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(credentials): Json<SignInData>,
-) -> Result<String, StatusCode> {
-    let mut conn = pool
-        .get()
+) -> Result<Json<SignInResponse>, AppError> {
+    let mut conn = pool.get().await?;
+    let client_ip = throttle.client_ip(peer, &headers);
+    let response = auth::sign_in(&mut conn, &throttle, client_ip, credentials).await?;
+
+    Ok(Json(response))
+}
+
+/// Exchanges the token `/login` gave out plus a code for a session.
+async fn verify_mfa(
+    State(pool): State<Pool>,
+    Extension(throttle): Extension<LoginThrottleConfig>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<MfaVerifyRequest>,
+) -> Result<Json<TokenPair>, AppError> {
+    let mut conn = pool.get().await?;
+    let client_ip = throttle.client_ip(peer, &headers);
+    let tokens = auth::complete_mfa_sign_in(&mut conn, &throttle, client_ip, request).await?;
+
+    Ok(Json(tokens))
+}
+
+/// Two-factor authentication is offered to company accounts, which can
+/// post paid work.
+async fn enroll_mfa(
+    State(pool): State<Pool>,
+    _staff: CompanyStaff,
+    Extension(user): Extension<User>,
+) -> Result<Json<MfaEnrollment>, AppError> {
+    let mut conn = pool.get().await?;
+    Service::enroll_mfa(&mut conn, &user)
+        .await
+        .map(Json)
+        .map_err(AppError::from)
+}
+
+async fn confirm_mfa(
+    State(pool): State<Pool>,
+    _staff: CompanyStaff,
+    Extension(user): Extension<User>,
+    Json(request): Json<MfaCodeRequest>,
+) -> Result<Json<MfaRecoveryCodes>, AppError> {
+    let mut conn = pool.get().await?;
+    Service::confirm_mfa(&mut conn, user.id, request.code)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let token = auth::sign_in(&mut conn, credentials)
+        .map(Json)
+        .map_err(AppError::from)
+}
+
+async fn disable_mfa(
+    State(pool): State<Pool>,
+    SignedInUser(user): SignedInUser,
+    Json(request): Json<MfaCodeRequest>,
+) -> Result<StatusCode, AppError> {
+    let mut conn = pool.get().await?;
+    Service::disable_mfa(&mut conn, user.id, request.code).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn refresh_session(
+    State(pool): State<Pool>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<Json<TokenPair>, AppError> {
+    let mut conn = pool.get().await?;
+    Service::refresh_session(&mut conn, &request.refresh_token)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map(Json)
+        .map_err(AppError::from)
+}
+
+/// Always accepted, so it cannot be used to find out who has an account.
+async fn forgot_password(
+    State(pool): State<Pool>,
+    Extension(mailer): Extension<AccountMailer>,
+    Json(request): Json<ForgotPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    let mut conn = pool.get().await?;
+    Service::request_password_reset(&mut conn, &mailer, &request.email).await?;
 
-    Ok(token)
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn reset_password(
+    State(pool): State<Pool>,
+    ValidJson(request): ValidJson<ResetPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    let mut conn = pool.get().await?;
+    Service::reset_password(&mut conn, request).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Target of the link mailed on registration.
+async fn verify_email(
+    State(pool): State<Pool>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let mut conn = pool.get().await?;
+    Service::verify_email(&mut conn, query.token).await?;
+
+    Ok(Json(serde_json::json!({ "verified": true })))
+}
+
+/// Ends the session the access token belongs to.
+async fn logout(
+    State(pool): State<Pool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<StatusCode, AppError> {
+    let mut conn = pool.get().await?;
+    Service::end_session(&mut conn, &claims.sid).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Ends every session of the user, including the current one.
+async fn logout_all(
+    State(pool): State<Pool>,
+    SignedInUser(user): SignedInUser,
+) -> Result<StatusCode, AppError> {
+    let mut conn = pool.get().await?;
+    Service::end_all_sessions(&mut conn, &user.id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
 async fn upload_company_logo(
     State(pool): State<Pool>,
-    Extension(user): Extension<User>,
+    CompanyAdmin { company_id }: CompanyAdmin,
     mut multipart: Multipart,
-) -> Result<impl IntoResponse, StatusCode> {
-    let company_id = match user.companyid {
-        Some(id) => id,
-        _ => return Err(StatusCode::BAD_REQUEST),
-    };
-
+) -> Result<impl IntoResponse, AppError> {
     while let Some(field) = multipart
         .next_field()
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| AppError::validation(e.body_text()))?
     {
         let field_name = field.name().unwrap_or("").to_string();
 
@@ -166,108 +319,585 @@ async fn upload_company_logo(
             let data = field
                 .bytes()
                 .await
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                .map_err(|e| AppError::validation(e.body_text()))?;
             let file_name = format!("{}.png", company_id);
             let file_path = format!("./assets/logos/{}", file_name);
 
             // Cria o diretório se não existir
             tokio::fs::create_dir_all("./assets/logos")
                 .await
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                .map_err(|e| AppError::internal(format!("Unable to create logo dir: {e}")))?;
 
             // Salva o arquivo
             tokio::fs::write(&file_path, &data)
                 .await
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                .map_err(|e| AppError::internal(format!("Unable to write {file_path}: {e}")))?;
 
             // Atualiza o banco de dados
-            let mut conn = pool
-                .get()
-                .await
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let mut conn = pool.get().await?;
 
-            let _ = Service::update_company_logo(&mut conn, company_id, file_name.clone())
-                .await
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let _ = Service::update_company_logo(&mut conn, company_id, file_name.clone()).await?;
 
             return Ok(format!("Logo uploaded successfully: {}", file_name).into_response());
         }
     }
 
-    Err(StatusCode::BAD_REQUEST)
+    Err(AppError::validation("Missing `logo` field"))
 }
 
 pub async fn list_job_opportunities(
     State(pool): State<Pool>,
     Path(company_id): Path<i64>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let mut conn = pool
-        .get()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+) -> Result<impl IntoResponse, AppError> {
+    let mut conn = pool.get().await?;
 
-    let results = Service::get_job_opportunities_with_company(&mut conn, company_id)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let results = Service::get_job_opportunities_with_company(&mut conn, company_id).await?;
+
+    Ok(Json(results))
+}
+
+pub async fn search_job_opportunities(
+    State(pool): State<Pool>,
+    ValidQuery(search): ValidQuery<JobSearchQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut conn = pool.get().await?;
+
+    let results = Service::search_job_opportunities(&mut conn, &search).await?;
 
     Ok(Json(results))
 }
 
 async fn create_company(
     State(pool): State<Pool>,
+    CompanyEditor(user): CompanyEditor,
+    ValidJson(company): ValidJson<NewCompany>,
+) -> Result<Json<Company>, AppError> {
+    let mut conn = pool.get().await?;
+    let res = Service::add_company(&mut conn, company, user).await?;
+    Ok(res)
+}
+
+/// Issues a token someone can register or log in with to join the company.
+async fn invite_company_member(
+    State(pool): State<Pool>,
+    CompanyAdmin { company_id }: CompanyAdmin,
     Extension(user): Extension<User>,
-    Json(company): Json<NewCompany>,
-) -> Result<Json<Company>, StatusCode> {
-    let mut conn = pool
-        .get()
+    Json(request): Json<InviteMemberRequest>,
+) -> Result<(StatusCode, Json<CompanyInvitationToken>), AppError> {
+    let mut conn = pool.get().await?;
+    let invitation = Service::invite_company_member(&mut conn, company_id, user.id, request).await?;
+
+    Ok((StatusCode::CREATED, Json(invitation)))
+}
+
+async fn list_company_members(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+) -> Result<Json<Vec<CompanyMemberWithLogin>>, AppError> {
+    let mut conn = pool.get().await?;
+    Service::list_company_members(&mut conn, staff.company_id)
         .await
-        .map_err(internal_error)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let res = Service::add_company(&mut conn, company, user)
+        .map(Json)
+        .map_err(AppError::from)
+}
+
+async fn remove_company_member(
+    State(pool): State<Pool>,
+    CompanyAdmin { company_id }: CompanyAdmin,
+    Path(user_id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let mut conn = pool.get().await?;
+    Service::remove_company_member(&mut conn, company_id, user_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Issues an API key for integrations; the key is only in this response.
+async fn create_api_key(
+    State(pool): State<Pool>,
+    CompanyAdmin { company_id }: CompanyAdmin,
+    Extension(user): Extension<User>,
+    ValidJson(request): ValidJson<NewApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), AppError> {
+    let mut conn = pool.get().await?;
+    let api_key = Service::create_api_key(&mut conn, company_id, user.id, request).await?;
+
+    Ok((StatusCode::CREATED, Json(api_key)))
+}
+
+async fn list_api_keys(
+    State(pool): State<Pool>,
+    CompanyAdmin { company_id }: CompanyAdmin,
+) -> Result<Json<Vec<CompanyApiKey>>, AppError> {
+    let mut conn = pool.get().await?;
+    Service::list_api_keys(&mut conn, company_id)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(res)
+        .map(Json)
+        .map_err(AppError::from)
+}
+
+async fn revoke_api_key(
+    State(pool): State<Pool>,
+    CompanyAdmin { company_id }: CompanyAdmin,
+    Path(key_id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let mut conn = pool.get().await?;
+    Service::revoke_api_key(&mut conn, company_id, key_id).await?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
 async fn create_job(
     State(pool): State<Pool>,
+    staff: CompanyStaff,
+    Extension(dispatcher): Extension<JobDispatcher>,
+    ValidJson(job): ValidJson<NewJobOpportunity>,
+) -> Result<Json<JobOpportunity>, AppError> {
+    let mut conn = pool.get().await?;
+    let res = Service::add_job_opportunity(&mut conn, job, staff.company_id).await?;
+    dispatcher.job_created(&res);
+    Ok(res)
+}
+
+async fn apply_to_job(
+    State(pool): State<Pool>,
+    employee: EmployeeUser,
+    Path(job_id): Path<i64>,
+) -> Result<Json<JobApplication>, AppError> {
+    let mut conn = pool.get().await?;
+    let res = Service::apply_to_job(&mut conn, job_id, employee.employee_id).await?;
+    Ok(res)
+}
+
+/// Pulls the caller out of a job they applied to, freeing their slot.
+async fn withdraw_from_job(
+    State(pool): State<Pool>,
+    employee: EmployeeUser,
+    Path(job_id): Path<i64>,
+) -> Result<Json<JobApplication>, AppError> {
+    let mut conn = pool.get().await?;
+    let res = Service::withdraw_from_job(&mut conn, job_id, employee.employee_id).await?;
+    Ok(res)
+}
+
+/// Starts the caller's shift; they must be accepted on the job and near it.
+async fn check_in(
+    State(pool): State<Pool>,
+    employee: EmployeeUser,
+    Extension(shifts): Extension<ShiftConfig>,
+    Path(job_id): Path<i64>,
+    ValidJson(location): ValidJson<ShiftLocation>,
+) -> Result<(StatusCode, Json<ShiftReport>), AppError> {
+    let mut conn = pool.get().await?;
+    let report =
+        Service::check_in(&mut conn, &shifts, job_id, employee.employee_id, location).await?;
+    Ok((StatusCode::CREATED, Json(report)))
+}
+
+async fn check_out(
+    State(pool): State<Pool>,
+    employee: EmployeeUser,
+    Path(job_id): Path<i64>,
+    ValidJson(location): ValidJson<ShiftLocation>,
+) -> Result<Json<ShiftReport>, AppError> {
+    let mut conn = pool.get().await?;
+    let report = Service::check_out(&mut conn, job_id, employee.employee_id, location).await?;
+    Ok(Json(report))
+}
+
+/// Claims hours for the caller's finished shift, or corrects a claim that
+/// has not been approved yet.
+async fn submit_timesheet(
+    State(pool): State<Pool>,
+    employee: EmployeeUser,
     Extension(user): Extension<User>,
-    Json(job): Json<NewJobOpportunity>,
-) -> Result<Json<JobOpportunity>, StatusCode> {
-    let mut conn = pool
-        .get()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let res = Service::add_job_opportunity(&mut conn, job, user)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Path(job_id): Path<i64>,
+    ValidJson(submission): ValidJson<TimesheetSubmission>,
+) -> Result<Json<Timesheet>, AppError> {
+    let mut conn = pool.get().await?;
+    let timesheet = Service::submit_timesheet(
+        &mut conn,
+        job_id,
+        employee.employee_id,
+        user.id,
+        submission,
+    )
+    .await?;
+    Ok(Json(timesheet))
+}
+
+async fn list_my_timesheets(
+    State(pool): State<Pool>,
+    employee: EmployeeUser,
+) -> Result<Json<Vec<Timesheet>>, AppError> {
+    let mut conn = pool.get().await?;
+    let results = Service::list_employee_timesheets(&mut conn, employee.employee_id).await?;
+    Ok(Json(results))
+}
+
+async fn list_job_timesheets(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    Path(job_id): Path<i64>,
+) -> Result<Json<Vec<Timesheet>>, AppError> {
+    let mut conn = pool.get().await?;
+    let results = Service::list_job_timesheets(&mut conn, job_id, staff.company_id).await?;
+    Ok(Json(results))
+}
+
+/// `user` is absent when the company calls with an API key.
+async fn approve_timesheet(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    user: Option<Extension<User>>,
+    Extension(billing): Extension<BillingConfig>,
+    Path(timesheet_id): Path<i64>,
+) -> Result<Json<Timesheet>, AppError> {
+    let mut conn = pool.get().await?;
+    let decided_by = user.map(|Extension(user)| user.id);
+    let timesheet = Service::approve_timesheet(
+        &mut conn,
+        &billing,
+        timesheet_id,
+        staff.company_id,
+        decided_by,
+    )
+    .await?;
+    Ok(Json(timesheet))
+}
+
+async fn dispute_timesheet(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    user: Option<Extension<User>>,
+    Path(timesheet_id): Path<i64>,
+    ValidJson(request): ValidJson<DisputeTimesheetRequest>,
+) -> Result<Json<Timesheet>, AppError> {
+    let mut conn = pool.get().await?;
+    let decided_by = user.map(|Extension(user)| user.id);
+    let timesheet = Service::dispute_timesheet(
+        &mut conn,
+        timesheet_id,
+        staff.company_id,
+        decided_by,
+        request.reason,
+    )
+    .await?;
+    Ok(Json(timesheet))
+}
+
+/// The caller's review of the company behind a job they worked.
+async fn review_company(
+    State(pool): State<Pool>,
+    employee: EmployeeUser,
+    Extension(user): Extension<User>,
+    Extension(reviews): Extension<ReviewConfig>,
+    Path(job_id): Path<i64>,
+    ValidJson(request): ValidJson<NewReviewRequest>,
+) -> Result<(StatusCode, Json<Review>), AppError> {
+    let mut conn = pool.get().await?;
+    let review = Service::review_company(
+        &mut conn,
+        &reviews,
+        job_id,
+        employee.employee_id,
+        user.id,
+        request,
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(review)))
+}
+
+async fn review_employee(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    Extension(user): Extension<User>,
+    Extension(reviews): Extension<ReviewConfig>,
+    Path((job_id, employee_id)): Path<(i64, i64)>,
+    ValidJson(request): ValidJson<NewReviewRequest>,
+) -> Result<(StatusCode, Json<Review>), AppError> {
+    let mut conn = pool.get().await?;
+    let review = Service::review_employee(
+        &mut conn,
+        &reviews,
+        job_id,
+        staff.company_id,
+        employee_id,
+        user.id,
+        request,
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(review)))
+}
+
+async fn list_employee_reviews(
+    State(pool): State<Pool>,
+    _user: SignedInUser,
+    Path(employee_id): Path<i64>,
+) -> Result<Json<Vec<Review>>, AppError> {
+    let mut conn = pool.get().await?;
+    let results = Service::list_employee_reviews(&mut conn, employee_id).await?;
+    Ok(Json(results))
+}
+
+async fn list_company_reviews(
+    State(pool): State<Pool>,
+    _user: SignedInUser,
+    Path(company_id): Path<i64>,
+) -> Result<Json<Vec<Review>>, AppError> {
+    let mut conn = pool.get().await?;
+    let results = Service::list_company_reviews(&mut conn, company_id).await?;
+    Ok(Json(results))
+}
+
+async fn list_job_applications(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    Path(job_id): Path<i64>,
+) -> Result<Json<Vec<JobApplicationWithEmployee>>, AppError> {
+    let mut conn = pool.get().await?;
+    let results = Service::list_job_applications(&mut conn, job_id, staff.company_id).await?;
+    Ok(Json(results))
+}
+
+async fn accept_application(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    Extension(dispatcher): Extension<JobDispatcher>,
+    Path(application_id): Path<i64>,
+) -> Result<Json<JobApplication>, AppError> {
+    decide_application(
+        pool,
+        staff.company_id,
+        dispatcher,
+        application_id,
+        ApplicationStatus::ACCEPTED,
+    )
+    .await
+}
+
+async fn reject_application(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    Extension(dispatcher): Extension<JobDispatcher>,
+    Path(application_id): Path<i64>,
+) -> Result<Json<JobApplication>, AppError> {
+    decide_application(
+        pool,
+        staff.company_id,
+        dispatcher,
+        application_id,
+        ApplicationStatus::REJECTED,
+    )
+    .await
+}
+
+async fn decide_application(
+    pool: Pool,
+    company_id: i64,
+    dispatcher: JobDispatcher,
+    application_id: i64,
+    decision: ApplicationStatus,
+) -> Result<Json<JobApplication>, AppError> {
+    let mut conn = pool.get().await?;
+    let res = Service::decide_job_application(&mut conn, application_id, company_id, decision)
+        .await?;
+    dispatcher.application_decided(&res);
     Ok(res)
 }
+
+async fn complete_job(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    Extension(billing): Extension<BillingConfig>,
+    Path(job_id): Path<i64>,
+) -> Result<Json<JobOpportunity>, AppError> {
+    transition_job(pool, &billing, staff.company_id, job_id, JobStatus::COMPLETED).await
+}
+
+async fn cancel_job(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    Extension(billing): Extension<BillingConfig>,
+    Path(job_id): Path<i64>,
+) -> Result<Json<JobOpportunity>, AppError> {
+    transition_job(pool, &billing, staff.company_id, job_id, JobStatus::CANCELLED).await
+}
+
+async fn transition_job(
+    pool: Pool,
+    billing: &BillingConfig,
+    company_id: i64,
+    job_id: i64,
+    next: JobStatus,
+) -> Result<Json<JobOpportunity>, AppError> {
+    let mut conn = pool.get().await?;
+    let res = Service::transition_job(&mut conn, billing, job_id, company_id, next).await?;
+    Ok(res)
+}
+
+async fn list_invoices(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+) -> Result<Json<Vec<Invoice>>, AppError> {
+    let mut conn = pool.get().await?;
+    let results = Service::list_company_invoices(&mut conn, staff.company_id).await?;
+    Ok(Json(results))
+}
+
+async fn get_invoice(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    Path(invoice_id): Path<i64>,
+) -> Result<Json<InvoiceWithLines>, AppError> {
+    let mut conn = pool.get().await?;
+    let invoice = Service::find_company_invoice(&mut conn, invoice_id, staff.company_id).await?;
+    Ok(Json(invoice))
+}
+
+/// The invoice's line items as a CSV download.
+async fn export_invoice(
+    State(pool): State<Pool>,
+    staff: CompanyStaff,
+    Path(invoice_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut conn = pool.get().await?;
+    let invoice = Service::find_company_invoice(&mut conn, invoice_id, staff.company_id).await?;
+    let body = billing::invoice_csv(&invoice)
+        .map_err(|e| AppError::internal(format!("Unable to write CSV: {e}")))?;
+    Ok(csv_attachment(format!("invoice-{invoice_id}.csv"), body))
+}
+
+async fn list_my_payouts(
+    State(pool): State<Pool>,
+    employee: EmployeeUser,
+) -> Result<Json<Vec<Payout>>, AppError> {
+    let mut conn = pool.get().await?;
+    let results = Service::list_employee_payouts(&mut conn, employee.employee_id).await?;
+    Ok(Json(results))
+}
+
+async fn list_payout_batches(
+    State(pool): State<Pool>,
+    _admin: PlatformAdmin,
+) -> Result<Json<Vec<PayoutBatch>>, AppError> {
+    let mut conn = pool.get().await?;
+    let results = Service::list_payout_batches(&mut conn).await?;
+    Ok(Json(results))
+}
+
+async fn get_payout_batch(
+    State(pool): State<Pool>,
+    _admin: PlatformAdmin,
+    Path(batch_id): Path<i64>,
+) -> Result<Json<PayoutBatchWithPayouts>, AppError> {
+    let mut conn = pool.get().await?;
+    let batch = Service::find_payout_batch(&mut conn, batch_id).await?;
+    Ok(Json(batch))
+}
+
+async fn export_payout_batch(
+    State(pool): State<Pool>,
+    _admin: PlatformAdmin,
+    Path(batch_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut conn = pool.get().await?;
+    let batch = Service::find_payout_batch(&mut conn, batch_id).await?;
+    let body = billing::payouts_csv(&batch.payouts)
+        .map_err(|e| AppError::internal(format!("Unable to write CSV: {e}")))?;
+    Ok(csv_attachment(format!("payout-batch-{batch_id}.csv"), body))
+}
+
+/// Invoices and pays out everything charged so far, without waiting for the
+/// billing period to end.
+async fn run_billing(
+    State(pool): State<Pool>,
+    _admin: PlatformAdmin,
+    Extension(payments): Extension<Payments>,
+) -> Result<Json<BillingRun>, AppError> {
+    let mut conn = pool.get().await?;
+    let now = chrono::Utc::now().naive_utc();
+    let run = Service::run_billing_cycle(&mut conn, &payments, now).await?;
+    Ok(Json(run))
+}
+
+fn csv_attachment(filename: String, body: Vec<u8>) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    )
+}
+
 type Pool = bb8::Pool<AsyncDieselConnectionManager<AsyncPgConnection>>;
-pub const MIGRATIONS: diesel_async_migrations::EmbeddedMigrations = diesel_async_migrations::embed_migrations!();
+pub static MIGRATIONS: diesel_async_migrations::EmbeddedMigrations = diesel_async_migrations::embed_migrations!();
 async fn run_migrations(url: impl AsRef<str>) -> anyhow::Result<()> {
     let mut conn = AsyncPgConnection::establish(url.as_ref()).await?;
     MIGRATIONS.run_pending_migrations(&mut conn).await?;
     Ok(())
 }
 
-async fn create_router(ws_manager: WebSocketManager) -> Router {
-    let ws_manager_clone = ws_manager.clone();
+async fn create_router() -> Router {
+    JwtConfig::from_env()
+        .and_then(JwtConfig::install)
+        .unwrap_or_else(|e| panic!("Invalid JWT configuration: {e}"));
+
+    let account_mailer = AccountMailer::from_env()
+        .unwrap_or_else(|e| panic!("Invalid mail configuration: {e}"));
+
+    let payments = Payments::from_env()
+        .unwrap_or_else(|e| panic!("Invalid payment configuration: {e}"));
+
     let db_url = std::env::var("DATABASE_URL").unwrap();
     run_migrations(db_url.clone()).await.unwrap();
-    let config = AsyncDieselConnectionManager::<diesel_async::AsyncPgConnection>::new(db_url);
+    let config = AsyncDieselConnectionManager::<diesel_async::AsyncPgConnection>::new(db_url.clone());
     let pool = bb8::Pool::builder().build(config).await.unwrap();
 
+    let ws_config = WsConfig::from_env();
+    let broadcast_backend = ws_config.broadcast_backend;
+    let ws_manager = WebSocketManager::new(pool.clone(), ws_config);
+    ws_manager.spawn_event_purge();
+    let timesheet_config = TimesheetConfig::from_env();
+    let billing_config = BillingConfig::from_env();
+    timesheet_config.spawn_auto_approval(pool.clone(), billing_config.clone());
+    billing_config.spawn_billing_cycle(pool.clone(), payments.clone());
+    if broadcast_backend == BroadcastBackend::Postgres {
+        broadcast::spawn_listener(db_url, ws_manager.clone());
+    }
+    let ws_manager_clone = ws_manager.clone();
+    let dispatcher = JobDispatcher::from_env(pool.clone(), ws_manager.clone());
+
     Router::new()
         // Rota WebSocket
         .route(
             "/ws",
-            get(move |ws, user_agent, addr: ConnectInfo<SocketAddr>| {
-                ws_manager_clone.clone().ws_handler(ws, user_agent, addr)
-            }),
+            get(
+                move |ws, user_agent, addr: ConnectInfo<SocketAddr>, authorization, params| {
+                    ws_manager_clone
+                        .clone()
+                        .ws_handler(ws, user_agent, addr, authorization, params)
+                },
+            ),
+        )
+        .route(
+            "/ws/metrics",
+            get(ws_metrics).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
         )
         // Rota para enviar mensagens
-        .route("/send/:addr", post(send_message_handler))
+        .route(
+            "/send/:user_id",
+            post(send_message_handler).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
         .with_state(ws_manager)
         .route(
             "/employees",
@@ -297,6 +927,43 @@ async fn create_router(ws_manager: WebSocketManager) -> Router {
                 Auth::authorize,
             )),
         )
+        .route(
+            "/companies/members",
+            get(list_company_members).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/companies/members/invite",
+            post(invite_company_member).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/companies/members/:user_id",
+            delete(remove_company_member).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/companies/api-keys",
+            post(create_api_key)
+                .get(list_api_keys)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    pool.clone(),
+                    Auth::authorize,
+                )),
+        )
+        .route(
+            "/companies/api-keys/:id",
+            delete(revoke_api_key).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
         .route(
             "/companies/upload-logo",
             post(upload_company_logo).route_layer(axum::middleware::from_fn_with_state(
@@ -311,10 +978,243 @@ async fn create_router(ws_manager: WebSocketManager) -> Router {
                 Auth::authorize,
             )),
         )
-        .route("/jobs/:company_id", get(list_job_opportunities))
+        .route("/jobs/search", get(search_job_opportunities))
+        .route("/companies/:id/jobs", get(list_job_opportunities))
+        // Former path of the company job listing, kept for existing clients
+        .route("/jobs/:id", get(list_job_opportunities))
+        .route(
+            "/jobs/:id/apply",
+            post(apply_to_job).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/jobs/:id/withdraw",
+            post(withdraw_from_job).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/jobs/:id/check-in",
+            post(check_in).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/jobs/:id/check-out",
+            post(check_out).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/jobs/:id/timesheet",
+            post(submit_timesheet).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/jobs/:id/timesheets",
+            get(list_job_timesheets).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/timesheets",
+            get(list_my_timesheets).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/timesheets/:id/approve",
+            post(approve_timesheet).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/timesheets/:id/dispute",
+            post(dispute_timesheet).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/jobs/:id/review",
+            post(review_company).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/jobs/:id/employees/:employee_id/review",
+            post(review_employee).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/employees/:id/reviews",
+            get(list_employee_reviews).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/companies/:id/reviews",
+            get(list_company_reviews).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/invoices",
+            get(list_invoices).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/invoices/:id",
+            get(get_invoice).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/invoices/:id/export",
+            get(export_invoice).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/payouts",
+            get(list_my_payouts).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/admin/payout-batches",
+            get(list_payout_batches).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/admin/payout-batches/:id",
+            get(get_payout_batch).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/admin/payout-batches/:id/export",
+            get(export_payout_batch).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/admin/billing/run",
+            post(run_billing).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/jobs/:id/applications",
+            get(list_job_applications).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/jobs/:id/complete",
+            post(complete_job).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/jobs/:id/cancel",
+            post(cancel_job).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/applications/:id/accept",
+            post(accept_application).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/applications/:id/reject",
+            post(reject_application).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
         .route("/login", post(login))
+        .route("/auth/refresh", post(refresh_session))
+        .route(
+            "/auth/logout",
+            post(logout).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/auth/logout-all",
+            post(logout_all).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route("/auth/mfa/verify", post(verify_mfa))
+        .route(
+            "/auth/mfa/enroll",
+            post(enroll_mfa).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/auth/mfa/confirm",
+            post(confirm_mfa).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route(
+            "/auth/mfa/disable",
+            post(disable_mfa).route_layer(axum::middleware::from_fn_with_state(
+                pool.clone(),
+                Auth::authorize,
+            )),
+        )
+        .route("/auth/forgot-password", post(forgot_password))
+        .route("/auth/reset-password", post(reset_password))
+        .route("/auth/verify-email", get(verify_email))
         .route("/register", post(register_user))
         .with_state(pool)
+        .layer(Extension(dispatcher))
+        .layer(Extension(account_mailer))
+        .layer(Extension(LoginThrottleConfig::from_env()))
+        .layer(Extension(ShiftConfig::from_env()))
+        .layer(Extension(timesheet_config))
+        .layer(Extension(ReviewConfig::from_env()))
+        .layer(Extension(billing_config))
+        .layer(Extension(payments))
         // Servir arquivos estáticos
         .fallback_service(
             ServeDir::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(ASSETS_DIR))
@@ -326,21 +1226,13 @@ async fn create_router(ws_manager: WebSocketManager) -> Router {
                 .make_span_with(DefaultMakeSpan::default().include_headers(true)),
         )
 }
-fn internal_error<E>(err: E) -> (StatusCode, String)
-where
-    E: std::error::Error,
-{
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
-}
 
 #[tokio::main]
 async fn main() {
     // Inicializa logs e tracing
     init_tracing();
 
-    let ws_manager = WebSocketManager::new();
-
-    let app = create_router(ws_manager);
+    let app = create_router();
 
     let listener = TcpListener::bind(SERVER_ADDR).await.unwrap();
     tracing::info!("🚀 Server listening on {}", SERVER_ADDR);
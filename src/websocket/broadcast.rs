@@ -0,0 +1,130 @@
+use diesel::sql_types::Text;
+use diesel_async::RunQueryDsl;
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio_postgres::{AsyncMessage, NoTls};
+
+use super::{protocol::Topic, websocket::WebSocketManager};
+use crate::Pool;
+
+/// Postgres channel every instance listens on.
+const CHANNEL: &str = "ws_events";
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// How events reach sockets that may be connected to other instances.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BroadcastBackend {
+    /// Deliver straight to this process's sockets; enough for a single node.
+    InMemory,
+    /// Publish through Postgres `NOTIFY`; every instance `LISTEN`s and
+    /// delivers to its own sockets, including the one that published.
+    Postgres,
+}
+
+/// An encoded frame and who should receive it.
+pub struct Broadcast {
+    pub user_ids: Vec<i64>,
+    pub topic: Option<Topic>,
+    pub frame: String,
+}
+
+/// What goes through `NOTIFY`: just enough to find the stored event. Postgres
+/// rejects payloads of 8000 bytes or more, which a frame with its recipients
+/// easily reaches, so listeners load the event and recipients themselves.
+#[derive(Serialize, Deserialize)]
+pub struct Notice {
+    pub seq: i64,
+    pub topic: Option<Topic>,
+}
+
+#[derive(Debug)]
+pub enum PublishError {
+    Encode(serde_json::Error),
+    Pool(String),
+    Database(diesel::result::Error),
+}
+
+impl std::fmt::Display for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PublishError::Encode(e) => write!(f, "unable to encode broadcast: {e}"),
+            PublishError::Pool(e) => write!(f, "unable to get a connection: {e}"),
+            PublishError::Database(e) => write!(f, "unable to notify: {e}"),
+        }
+    }
+}
+
+/// Tell every instance about a stored event through `pg_notify`.
+pub async fn publish(pool: &Pool, notice: &Notice) -> Result<(), PublishError> {
+    let payload = serde_json::to_string(notice).map_err(PublishError::Encode)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| PublishError::Pool(e.to_string()))?;
+    diesel::sql_query("SELECT pg_notify($1, $2)")
+        .bind::<Text, _>(CHANNEL)
+        .bind::<Text, _>(payload)
+        .execute(&mut conn)
+        .await
+        .map_err(PublishError::Database)?;
+    Ok(())
+}
+
+/// Keep a dedicated connection listening on the channel and hand every
+/// notified event to the local sockets, reconnecting with backoff when it drops.
+pub fn spawn_listener(db_url: String, ws_manager: WebSocketManager) {
+    tokio::spawn(async move {
+        let mut delay = MIN_RECONNECT_DELAY;
+        loop {
+            let started = Instant::now();
+            match listen(&db_url, &ws_manager).await {
+                Ok(()) => tracing::warn!("Broadcast listener connection closed"),
+                Err(e) => tracing::error!("Broadcast listener failed: {e}"),
+            }
+            // A connection that stayed up for a while was healthy; start over.
+            if started.elapsed() > MAX_RECONNECT_DELAY {
+                delay = MIN_RECONNECT_DELAY;
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    });
+}
+
+async fn listen(db_url: &str, ws_manager: &WebSocketManager) -> Result<(), tokio_postgres::Error> {
+    let (client, mut connection) = tokio_postgres::connect(db_url, NoTls).await?;
+    let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+
+    let statement = format!("LISTEN {CHANNEL}");
+    let subscribe = client.batch_execute(&statement);
+    tokio::pin!(subscribe);
+    // The connection has to be polled for the LISTEN to complete.
+    loop {
+        tokio::select! {
+            result = &mut subscribe => {
+                result?;
+                break;
+            }
+            message = messages.next() => match message {
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => return Ok(()),
+            },
+        }
+    }
+    tracing::info!("Listening for broadcasts on `{CHANNEL}`");
+
+    while let Some(message) = messages.next().await {
+        if let AsyncMessage::Notification(notification) = message? {
+            match serde_json::from_str::<Notice>(notification.payload()) {
+                Ok(notice) => ws_manager.deliver_stored(&notice).await,
+                Err(e) => tracing::warn!("Ignoring malformed broadcast: {e}"),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use super::broadcast::BroadcastBackend;
+
+const DEFAULT_QUEUE_CAPACITY: usize = 256;
+const DEFAULT_PING_INTERVAL_SECS: u64 = 30;
+const DEFAULT_MAX_MISSED_PONGS: u32 = 2;
+const DEFAULT_REPLAY_BATCH_SIZE: usize = 200;
+const DEFAULT_EVENT_RETENTION_HOURS: u64 = 72;
+
+/// What to do when a client's outbound queue is full.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued message to make room for the new one.
+    DropOldest,
+    /// Close the connection; the client is expected to reconnect.
+    Disconnect,
+}
+
+/// Tunables for WebSocket connections.
+#[derive(Clone, Debug)]
+pub struct WsConfig {
+    pub queue_capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+    /// How often the server pings each client.
+    pub ping_interval: Duration,
+    /// Unanswered pings tolerated before the client is considered gone.
+    pub max_missed_pongs: u32,
+    pub broadcast_backend: BroadcastBackend,
+    /// Most events replayed at once; capped below the queue capacity so a
+    /// replay cannot overflow the connection it is meant for.
+    pub replay_batch_size: usize,
+    /// How long events are kept for replay.
+    pub event_retention: Duration,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            overflow_policy: OverflowPolicy::DropOldest,
+            ping_interval: Duration::from_secs(DEFAULT_PING_INTERVAL_SECS),
+            max_missed_pongs: DEFAULT_MAX_MISSED_PONGS,
+            broadcast_backend: BroadcastBackend::InMemory,
+            replay_batch_size: DEFAULT_REPLAY_BATCH_SIZE,
+            event_retention: Duration::from_secs(DEFAULT_EVENT_RETENTION_HOURS * 3600),
+        }
+    }
+}
+
+impl WsConfig {
+    /// Reads `WS_QUEUE_CAPACITY`, `WS_OVERFLOW_POLICY` (`drop_oldest` or
+    /// `disconnect`), `WS_PING_INTERVAL_SECS`, `WS_MAX_MISSED_PONGS`,
+    /// `WS_BROADCAST_BACKEND` (`memory` or `postgres`), `WS_REPLAY_BATCH_SIZE`
+    /// and `WS_EVENT_RETENTION_HOURS`, keeping the defaults for anything unset
+    /// or invalid.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+
+        let queue_capacity = std::env::var("WS_QUEUE_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|capacity| *capacity > 0)
+            .unwrap_or(defaults.queue_capacity);
+
+        let overflow_policy = match std::env::var("WS_OVERFLOW_POLICY").as_deref() {
+            Ok("disconnect") => OverflowPolicy::Disconnect,
+            Ok("drop_oldest") => OverflowPolicy::DropOldest,
+            _ => defaults.overflow_policy,
+        };
+
+        let ping_interval = std::env::var("WS_PING_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.ping_interval);
+
+        let max_missed_pongs = std::env::var("WS_MAX_MISSED_PONGS")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .filter(|missed| *missed > 0)
+            .unwrap_or(defaults.max_missed_pongs);
+
+        let broadcast_backend = match std::env::var("WS_BROADCAST_BACKEND").as_deref() {
+            Ok("postgres") => BroadcastBackend::Postgres,
+            Ok("memory") => BroadcastBackend::InMemory,
+            _ => defaults.broadcast_backend,
+        };
+
+        let replay_batch_size = std::env::var("WS_REPLAY_BATCH_SIZE")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|size| *size > 0)
+            .unwrap_or(defaults.replay_batch_size)
+            // Leave room for the `replay_complete` marker.
+            .min(queue_capacity.saturating_sub(1).max(1));
+
+        let event_retention = std::env::var("WS_EVENT_RETENTION_HOURS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|hours| *hours > 0)
+            .map(|hours| Duration::from_secs(hours * 3600))
+            .unwrap_or(defaults.event_retention);
+
+        Self {
+            queue_capacity,
+            overflow_policy,
+            ping_interval,
+            max_missed_pongs,
+            broadcast_backend,
+            replay_batch_size,
+            event_retention,
+        }
+    }
+}
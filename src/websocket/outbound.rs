@@ -0,0 +1,279 @@
+use axum::extract::ws::{CloseFrame, Message};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use tokio::sync::Notify;
+
+use super::config::OverflowPolicy;
+
+/// Close code sent when a client is dropped for not keeping up (RFC 6455 "Try Again Later").
+const CLOSE_TRY_AGAIN_LATER: u16 = 1013;
+
+/// What a connection's writer task should do next.
+pub enum Outbound {
+    Send(Message),
+    Ping,
+    /// Stop writing; send the frame first when there is one.
+    Close(Option<CloseFrame<'static>>),
+}
+
+/// Result of queueing a message for a connection.
+#[derive(PartialEq, Eq, Debug)]
+pub enum PushOutcome {
+    Queued,
+    DroppedOldest,
+    Overflowed,
+    Closed,
+}
+
+struct QueueState {
+    messages: VecDeque<Message>,
+    ping_pending: bool,
+    closing: Option<Option<CloseFrame<'static>>>,
+}
+
+/// Bounded outbound buffer for a single socket, drained by that socket's
+/// writer task. A `tokio::sync::mpsc` channel cannot evict from the sending
+/// side, which the drop-oldest policy needs, hence the hand-rolled queue.
+pub struct OutboundQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl OutboundQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                messages: VecDeque::with_capacity(capacity),
+                ping_pending: false,
+                closing: None,
+            }),
+            notify: Notify::new(),
+            capacity,
+            policy,
+        }
+    }
+
+    /// Queue a message without waiting, applying the overflow policy when full.
+    pub fn push(&self, msg: Message) -> PushOutcome {
+        let outcome = {
+            let mut state = self.state.lock().unwrap();
+            if state.closing.is_some() {
+                return PushOutcome::Closed;
+            }
+
+            if state.messages.len() < self.capacity {
+                state.messages.push_back(msg);
+                PushOutcome::Queued
+            } else {
+                match self.policy {
+                    OverflowPolicy::DropOldest => {
+                        state.messages.pop_front();
+                        state.messages.push_back(msg);
+                        PushOutcome::DroppedOldest
+                    }
+                    OverflowPolicy::Disconnect => {
+                        state.messages.clear();
+                        state.closing = Some(Some(CloseFrame {
+                            code: CLOSE_TRY_AGAIN_LATER,
+                            reason: "outbound queue overflow".into(),
+                        }));
+                        PushOutcome::Overflowed
+                    }
+                }
+            }
+        };
+        self.notify.notify_one();
+        outcome
+    }
+
+    /// Ask the writer for a ping ahead of any queued messages. Pings sit
+    /// outside the queue, so they never count towards its capacity or evict
+    /// a message, and a ping still waiting is not doubled.
+    pub fn ping(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.closing.is_some() {
+                return;
+            }
+            state.ping_pending = true;
+        }
+        self.notify.notify_one();
+    }
+
+    /// Stop accepting messages and let the writer finish, optionally sending
+    /// `frame` once whatever is already queued has gone out.
+    pub fn close(&self, frame: Option<CloseFrame<'static>>) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.closing.is_none() {
+                state.closing = Some(frame);
+            }
+        }
+        self.notify.notify_one();
+    }
+
+    /// Wait for the next thing the writer should do.
+    pub async fn pop(&self) -> Outbound {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if std::mem::take(&mut state.ping_pending) {
+                    return Outbound::Ping;
+                }
+                if let Some(msg) = state.messages.pop_front() {
+                    return Outbound::Send(msg);
+                }
+                if let Some(frame) = state.closing.take() {
+                    state.closing = Some(None);
+                    return Outbound::Close(frame);
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Counters describing outbound delivery across every connection.
+#[derive(Default)]
+pub struct WsMetrics {
+    messages_queued: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_dropped: AtomicU64,
+    overflow_disconnects: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct WsMetricsSnapshot {
+    pub messages_queued: u64,
+    pub messages_sent: u64,
+    pub messages_dropped: u64,
+    pub overflow_disconnects: u64,
+}
+
+impl WsMetrics {
+    pub fn record_push(&self, outcome: &PushOutcome) {
+        match outcome {
+            PushOutcome::Queued => {
+                self.messages_queued.fetch_add(1, Ordering::Relaxed);
+            }
+            PushOutcome::DroppedOldest => {
+                self.messages_queued.fetch_add(1, Ordering::Relaxed);
+                self.messages_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            PushOutcome::Overflowed => {
+                self.messages_dropped.fetch_add(1, Ordering::Relaxed);
+                self.overflow_disconnects.fetch_add(1, Ordering::Relaxed);
+            }
+            PushOutcome::Closed => {
+                self.messages_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn record_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> WsMetricsSnapshot {
+        WsMetricsSnapshot {
+            messages_queued: self.messages_queued.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_dropped: self.messages_dropped.load(Ordering::Relaxed),
+            overflow_disconnects: self.overflow_disconnects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> Message {
+        Message::Text(value.to_string())
+    }
+
+    async fn next_text(queue: &OutboundQueue) -> Option<String> {
+        match queue.pop().await {
+            Outbound::Send(Message::Text(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_the_newest_messages() {
+        let queue = OutboundQueue::new(2, OverflowPolicy::DropOldest);
+        assert_eq!(queue.push(text("a")), PushOutcome::Queued);
+        assert_eq!(queue.push(text("b")), PushOutcome::Queued);
+        assert_eq!(queue.push(text("c")), PushOutcome::DroppedOldest);
+
+        assert_eq!(next_text(&queue).await.as_deref(), Some("b"));
+        assert_eq!(next_text(&queue).await.as_deref(), Some("c"));
+        assert_eq!(queue.push(text("d")), PushOutcome::Queued);
+    }
+
+    #[tokio::test]
+    async fn disconnect_discards_the_backlog_and_closes() {
+        let queue = OutboundQueue::new(2, OverflowPolicy::Disconnect);
+        queue.push(text("a"));
+        queue.push(text("b"));
+        assert_eq!(queue.push(text("c")), PushOutcome::Overflowed);
+        assert_eq!(queue.push(text("d")), PushOutcome::Closed);
+
+        match queue.pop().await {
+            Outbound::Close(Some(frame)) => assert_eq!(frame.code, CLOSE_TRY_AGAIN_LATER),
+            _ => panic!("expected a close frame"),
+        }
+        assert!(matches!(queue.pop().await, Outbound::Close(None)));
+    }
+
+    #[tokio::test]
+    async fn close_sends_what_is_already_queued_first() {
+        let queue = OutboundQueue::new(2, OverflowPolicy::Disconnect);
+        queue.push(text("a"));
+        queue.close(None);
+
+        assert_eq!(queue.push(text("b")), PushOutcome::Closed);
+        assert_eq!(next_text(&queue).await.as_deref(), Some("a"));
+        assert!(matches!(queue.pop().await, Outbound::Close(None)));
+    }
+
+    #[tokio::test]
+    async fn pings_skip_the_queue_without_taking_a_slot() {
+        let queue = OutboundQueue::new(1, OverflowPolicy::DropOldest);
+        assert_eq!(queue.push(text("a")), PushOutcome::Queued);
+        queue.ping();
+        queue.ping();
+
+        assert!(matches!(queue.pop().await, Outbound::Ping));
+        assert_eq!(next_text(&queue).await.as_deref(), Some("a"));
+        queue.close(None);
+        queue.ping();
+        assert!(matches!(queue.pop().await, Outbound::Close(None)));
+    }
+
+    #[test]
+    fn metrics_count_drops_and_disconnects() {
+        let metrics = WsMetrics::default();
+        for outcome in [
+            PushOutcome::Queued,
+            PushOutcome::DroppedOldest,
+            PushOutcome::Overflowed,
+            PushOutcome::Closed,
+        ] {
+            metrics.record_push(&outcome);
+        }
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.messages_queued, 2);
+        assert_eq!(snapshot.messages_dropped, 3);
+        assert_eq!(snapshot.overflow_disconnects, 1);
+    }
+}
@@ -0,0 +1,267 @@
+use axum::extract::ws::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::domain::models::{JobApplication, JobOpportunity};
+
+/// Version stamped on every frame; clients sending another one get an error.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Common frame shape in both directions: `{"v", "id", "type", "payload"}`.
+/// Events persisted for replay also carry their `seq`.
+#[derive(Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub v: u8,
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+/// Groups of events a connection can choose to receive.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    Jobs,
+    Applications,
+    Chat,
+}
+
+impl Topic {
+    pub const ALL: [Topic; 3] = [Topic::Jobs, Topic::Applications, Topic::Chat];
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidJson,
+    UnsupportedVersion,
+    UnknownType,
+    InvalidPayload,
+    UnsupportedFrame,
+    NotAnEmployee,
+    Internal,
+}
+
+/// Events pushed from the server to clients.
+#[derive(Serialize)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum ServerEvent {
+    JobCreated(JobOpportunity),
+    ApplicationStatusChanged {
+        application_id: i64,
+        job_id: i64,
+        status: String,
+    },
+    ChatMessage {
+        from_user_id: i64,
+        text: String,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        in_reply_to: Option<String>,
+    },
+    /// Sent after replayed events. When `truncated`, more are waiting and the
+    /// client should send `replay` with `after_seq` set to `last_seq`.
+    ReplayComplete {
+        last_seq: i64,
+        truncated: bool,
+    },
+}
+
+impl ServerEvent {
+    pub fn application_status_changed(application: &JobApplication) -> Self {
+        ServerEvent::ApplicationStatusChanged {
+            application_id: application.id,
+            job_id: application.job_id,
+            status: application.status.clone(),
+        }
+    }
+
+    pub fn error(code: ErrorCode, message: impl Into<String>, in_reply_to: Option<String>) -> Self {
+        ServerEvent::Error {
+            code,
+            message: message.into(),
+            in_reply_to,
+        }
+    }
+
+    /// The topic a connection must be subscribed to in order to receive this
+    /// event. Errors and replay markers are replies to the connection itself
+    /// and always go out.
+    pub fn topic(&self) -> Option<Topic> {
+        match self {
+            ServerEvent::JobCreated(_) => Some(Topic::Jobs),
+            ServerEvent::ApplicationStatusChanged { .. } => Some(Topic::Applications),
+            ServerEvent::ChatMessage { .. } => Some(Topic::Chat),
+            ServerEvent::Error { .. } | ServerEvent::ReplayComplete { .. } => None,
+        }
+    }
+
+    /// Wraps the event in a fresh envelope and encodes it as JSON.
+    pub fn to_frame(&self) -> String {
+        encode_frame(self, None)
+    }
+
+    /// Encodes the event as a text frame.
+    pub fn to_message(&self) -> Message {
+        Message::Text(self.to_frame())
+    }
+}
+
+/// Wraps an event body in a fresh envelope and encodes it as JSON. `body` is
+/// anything serializing to `{"type", "payload"}`, such as a stored event.
+pub fn encode_frame<T: Serialize>(body: T, seq: Option<i64>) -> String {
+    let envelope = Envelope {
+        v: PROTOCOL_VERSION,
+        id: Uuid::new_v4().to_string(),
+        seq,
+        body,
+    };
+    serde_json::to_string(&envelope).unwrap_or_default()
+}
+
+/// Commands clients may send to the server.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum ClientCommand {
+    /// Replaces the connection's topics; new connections receive every topic.
+    Subscribe {
+        topics: Vec<Topic>,
+    },
+    Ack {
+        event_id: String,
+    },
+    LocationUpdate {
+        latitude: f64,
+        longitude: f64,
+    },
+    /// Asks for the stored events after `after_seq`.
+    Replay {
+        after_seq: i64,
+    },
+}
+
+impl ClientCommand {
+    const TYPES: [&'static str; 4] = ["subscribe", "ack", "location_update", "replay"];
+}
+
+/// Why a client frame could not be turned into a command. Carries the frame id
+/// when one could be read so the error frame can point back at it.
+pub struct ProtocolError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub in_reply_to: Option<String>,
+}
+
+impl ProtocolError {
+    fn new(code: ErrorCode, message: impl Into<String>, in_reply_to: Option<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            in_reply_to,
+        }
+    }
+
+    pub fn into_event(self) -> ServerEvent {
+        ServerEvent::error(self.code, self.message, self.in_reply_to)
+    }
+}
+
+/// Parses a text frame into a command envelope, classifying what went wrong.
+pub fn parse_command(text: &str) -> Result<Envelope<ClientCommand>, ProtocolError> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|e| ProtocolError::new(ErrorCode::InvalidJson, e.to_string(), None))?;
+
+    let frame_id = value.get("id").and_then(Value::as_str).map(str::to_string);
+
+    match value.get("v").and_then(Value::as_u64) {
+        Some(v) if v == u64::from(PROTOCOL_VERSION) => {}
+        _ => {
+            return Err(ProtocolError::new(
+                ErrorCode::UnsupportedVersion,
+                format!("expected protocol version {PROTOCOL_VERSION}"),
+                frame_id,
+            ))
+        }
+    }
+
+    match value.get("type").and_then(Value::as_str) {
+        Some(kind) if ClientCommand::TYPES.contains(&kind) => {}
+        Some(kind) => {
+            return Err(ProtocolError::new(
+                ErrorCode::UnknownType,
+                format!("unknown command type `{kind}`"),
+                frame_id,
+            ))
+        }
+        None => {
+            return Err(ProtocolError::new(
+                ErrorCode::UnknownType,
+                "missing command type",
+                frame_id,
+            ))
+        }
+    }
+
+    serde_json::from_value(value)
+        .map_err(|e| ProtocolError::new(ErrorCode::InvalidPayload, e.to_string(), frame_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> ProtocolError {
+        match parse_command(text) {
+            Ok(envelope) => panic!("parsed {:?}", envelope.body),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn parses_a_command() {
+        let envelope = parse_command(r#"{"v":1,"id":"a","type":"ack","payload":{"event_id":"e"}}"#)
+            .unwrap_or_else(|err| panic!("{}", err.message));
+        assert_eq!(envelope.id, "a");
+        assert!(matches!(envelope.body, ClientCommand::Ack { event_id } if event_id == "e"));
+    }
+
+    #[test]
+    fn rejects_malformed_json() {
+        let err = parse_error(r#"{"v":1,"id":"a","#);
+        assert!(matches!(err.code, ErrorCode::InvalidJson));
+        assert_eq!(err.in_reply_to, None);
+    }
+
+    #[test]
+    fn rejects_another_version() {
+        let err = parse_error(r#"{"v":2,"id":"a","type":"ack","payload":{"event_id":"e"}}"#);
+        assert!(matches!(err.code, ErrorCode::UnsupportedVersion));
+        assert_eq!(err.in_reply_to.as_deref(), Some("a"));
+
+        let err = parse_error(r#"{"id":"a","type":"ack","payload":{"event_id":"e"}}"#);
+        assert!(matches!(err.code, ErrorCode::UnsupportedVersion));
+    }
+
+    #[test]
+    fn rejects_an_unknown_type() {
+        let err = parse_error(r#"{"v":1,"id":"a","type":"dance","payload":{}}"#);
+        assert!(matches!(err.code, ErrorCode::UnknownType));
+        assert_eq!(err.in_reply_to.as_deref(), Some("a"));
+
+        let err = parse_error(r#"{"v":1,"id":"a","payload":{}}"#);
+        assert!(matches!(err.code, ErrorCode::UnknownType));
+    }
+
+    #[test]
+    fn rejects_a_bad_payload() {
+        let err = parse_error(r#"{"v":1,"id":"a","type":"replay","payload":{"after_seq":"x"}}"#);
+        assert!(matches!(err.code, ErrorCode::InvalidPayload));
+        assert_eq!(err.in_reply_to.as_deref(), Some("a"));
+    }
+}
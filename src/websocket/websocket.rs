@@ -1,98 +1,579 @@
 use axum::{
-    extract::ws::{Message, WebSocket, WebSocketUpgrade},
-    extract::connect_info::ConnectInfo,
-    response::IntoResponse,
+    extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+    extract::{connect_info::ConnectInfo, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
 use axum_extra::TypedHeader;
-use futures::{stream::SplitSink, StreamExt, SinkExt};
-use headers::UserAgent;
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use headers::{authorization::Bearer, Authorization, UserAgent};
+use serde::Deserialize;
+use serde_json::Value;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::SocketAddr,
     ops::ControlFlow,
     sync::Arc,
+    time::Duration,
 };
-use tokio::sync::RwLock;
+use tokio::{sync::RwLock, time::MissedTickBehavior};
+use uuid::Uuid;
 
-// Clients type definition
-type Clients = Arc<RwLock<HashMap<SocketAddr, SplitSink<WebSocket, Message>>>>;
+use super::{
+    broadcast::{self, Broadcast, BroadcastBackend, Notice},
+    config::WsConfig,
+    outbound::{Outbound, OutboundQueue, PushOutcome, WsMetrics, WsMetricsSnapshot},
+    protocol::{self, ClientCommand, ErrorCode, ServerEvent, Topic},
+};
+use crate::{application::service::Service, infrastructure::auth::Auth, Pool};
+
+/// Close code for clients that stopped answering pings (private-use range).
+const CLOSE_HEARTBEAT_TIMEOUT: u16 = 4000;
+
+/// How long the writer gets to flush and send the close frame once the
+/// connection is over, before it is aborted.
+const WRITER_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// How often events older than the retention window are deleted.
+const EVENT_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Identifies one socket among the ones a user has open.
+type ConnectionId = Uuid;
+
+/// One open socket: the queue its writer task drains and the topics it wants
+/// to hear about.
+struct Connection {
+    queue: Arc<OutboundQueue>,
+    topics: HashSet<Topic>,
+}
+
+// Clients type definition: every open socket, grouped by the user it belongs to
+type Clients = Arc<RwLock<HashMap<i64, HashMap<ConnectionId, Connection>>>>;
+
+#[derive(Deserialize)]
+pub struct ConnectParams {
+    pub token: Option<String>,
+    /// Sequence number of the last event the client received; everything
+    /// stored after it is replayed once the socket is open.
+    pub last_seen_seq: Option<i64>,
+}
 
 /// A WebSocket Manager to handle connected clients and messages.
-#[derive(Clone, Default)]
+///
+/// Sending only pushes onto per-connection queues, so the clients lock is
+/// never held across a socket write and a slow client cannot stall others.
+#[derive(Clone)]
 pub struct WebSocketManager {
     clients: Clients,
+    pool: Pool,
+    config: WsConfig,
+    metrics: Arc<WsMetrics>,
 }
 
 impl WebSocketManager {
     /// Create a new WebSocketManager instance.
-    pub fn new() -> Self {
+    pub fn new(pool: Pool, config: WsConfig) -> Self {
         Self {
             clients: Arc::new(RwLock::new(HashMap::new())),
+            pool,
+            config,
+            metrics: Arc::new(WsMetrics::default()),
         }
     }
 
+    /// Delivery counters since startup.
+    pub fn metrics(&self) -> WsMetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
     /// Add a new client to the manager.
-    async fn add_client(&self, addr: SocketAddr, sender: SplitSink<WebSocket, Message>) {
-        self.clients.write().await.insert(addr, sender);
-        println!("Client {addr} added");
+    async fn add_client(
+        &self,
+        user_id: i64,
+        connection_id: ConnectionId,
+        queue: Arc<OutboundQueue>,
+    ) {
+        self.clients
+            .write()
+            .await
+            .entry(user_id)
+            .or_default()
+            .insert(
+                connection_id,
+                Connection {
+                    queue,
+                    topics: Topic::ALL.into_iter().collect(),
+                },
+            );
+        tracing::debug!("Client {connection_id} of user {user_id} added");
     }
 
     /// Remove a client from the manager.
-    async fn remove_client(&self, addr: SocketAddr) {
-        self.clients.write().await.remove(&addr);
-        println!("Client {addr} removed");
+    async fn remove_client(&self, user_id: i64, connection_id: ConnectionId) {
+        let mut clients_lock = self.clients.write().await;
+        if let Some(connections) = clients_lock.get_mut(&user_id) {
+            connections.remove(&connection_id);
+            if connections.is_empty() {
+                clients_lock.remove(&user_id);
+            }
+        }
+        tracing::debug!("Client {connection_id} of user {user_id} removed");
     }
 
-    /// Send a message to a specific client.
-    pub async fn send_to_client(&self, addr: SocketAddr, msg: Message) {
-        let mut clients_lock = self.clients.write().await;
-        if let Some(sender) = clients_lock.get_mut(&addr) {
-            if let Err(e) = sender.send(msg).await {
-                println!("Error sending message to {addr}: {e}");
+    /// Queue a message on one connection, recording drops.
+    fn enqueue(
+        &self,
+        user_id: i64,
+        connection_id: ConnectionId,
+        queue: &OutboundQueue,
+        msg: Message,
+    ) {
+        let outcome = queue.push(msg);
+        self.metrics.record_push(&outcome);
+        match outcome {
+            PushOutcome::Queued | PushOutcome::Closed => {}
+            PushOutcome::DroppedOldest => tracing::warn!(
+                "Outbound queue of {connection_id} (user {user_id}) is full, dropped oldest message"
+            ),
+            PushOutcome::Overflowed => tracing::warn!(
+                "Outbound queue of {connection_id} (user {user_id}) overflowed, disconnecting"
+            ),
+        }
+    }
+
+    /// Send a message to every socket a user has open on this instance.
+    pub async fn send_to_user(&self, user_id: i64, msg: Message) {
+        let clients_lock = self.clients.read().await;
+        if let Some(connections) = clients_lock.get(&user_id) {
+            for (connection_id, connection) in connections.iter() {
+                self.enqueue(user_id, *connection_id, &connection.queue, msg.clone());
             }
         } else {
-            println!("Client {addr} not found");
+            tracing::debug!("User {user_id} has no open connections");
+        }
+    }
+
+    /// Send an event to the sockets of each of `user_ids` subscribed to its
+    /// topic, on whichever instance they are connected to. The event is stored
+    /// first so users who are offline can have it replayed later.
+    pub async fn send_event_to_users(&self, user_ids: &[i64], event: &ServerEvent) {
+        if user_ids.is_empty() {
+            return;
+        }
+
+        let seq = self.record_event(user_ids, event).await;
+        let broadcast = Broadcast {
+            user_ids: user_ids.to_vec(),
+            topic: event.topic(),
+            frame: protocol::encode_frame(event, seq),
+        };
+
+        match (self.config.broadcast_backend, seq) {
+            (BroadcastBackend::InMemory, _) => self.deliver(&broadcast).await,
+            (BroadcastBackend::Postgres, Some(seq)) => {
+                let notice = Notice {
+                    seq,
+                    topic: broadcast.topic,
+                };
+                if let Err(e) = broadcast::publish(&self.pool, &notice).await {
+                    tracing::warn!("Unable to publish broadcast, delivering locally: {e}");
+                    self.deliver(&broadcast).await;
+                }
+            }
+            // Other instances can only pick up stored events.
+            (BroadcastBackend::Postgres, None) => {
+                tracing::warn!("Event was not stored, delivering locally");
+                self.deliver(&broadcast).await;
+            }
+        }
+    }
+
+    /// Deliver a stored event another instance (or this one) notified about
+    /// to whichever of its recipients are connected here.
+    pub async fn deliver_stored(&self, notice: &Notice) {
+        let local_user_ids: Vec<i64> = self.clients.read().await.keys().copied().collect();
+        if local_user_ids.is_empty() {
+            return;
+        }
+
+        let found = match self.pool.get().await {
+            Ok(mut conn) => {
+                Service::find_ws_event_for_users(&mut conn, notice.seq, &local_user_ids)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        let (event, user_ids) = match found {
+            Ok(Some(found)) => found,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::error!("Unable to load broadcast event {}: {e}", notice.seq);
+                return;
+            }
+        };
+        let body = match serde_json::from_str::<Value>(&event.body) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!("Skipping unreadable stored event {}: {e}", event.seq);
+                return;
+            }
+        };
+
+        let broadcast = Broadcast {
+            user_ids,
+            topic: notice.topic,
+            frame: protocol::encode_frame(body, Some(event.seq)),
+        };
+        self.deliver(&broadcast).await;
+    }
+
+    /// Store an event for replay, returning its sequence number. Failing to
+    /// store it does not stop live delivery, the event just goes out without
+    /// a `seq`.
+    async fn record_event(&self, user_ids: &[i64], event: &ServerEvent) -> Option<i64> {
+        let body = match serde_json::to_string(event) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("Unable to encode event for replay: {e}");
+                return None;
+            }
+        };
+        let mut conn = match self.pool.get().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::error!("Unable to store event for replay: {e}");
+                return None;
+            }
+        };
+        match Service::record_ws_event(&mut conn, body, user_ids).await {
+            Ok(seq) => Some(seq),
+            Err(e) => {
+                tracing::error!("Unable to store event for replay: {e}");
+                None
+            }
         }
     }
 
+    /// Send one connection the stored events after `after_seq`, oldest first,
+    /// followed by a `replay_complete` marker. At most one batch goes out; the
+    /// marker tells the client whether to ask for more.
+    async fn replay(
+        &self,
+        user_id: i64,
+        connection_id: ConnectionId,
+        after_seq: i64,
+        frame_id: Option<String>,
+    ) {
+        let batch_size = self.config.replay_batch_size;
+        let events = match self.pool.get().await {
+            // One extra row tells whether the batch is the last one.
+            Ok(mut conn) => {
+                Service::find_missed_ws_events(&mut conn, user_id, after_seq, batch_size as i64 + 1)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        let mut events = match events {
+            Ok(events) => events,
+            Err(e) => {
+                tracing::error!("Unable to replay events for user {user_id}: {e}");
+                let reply =
+                    ServerEvent::error(ErrorCode::Internal, "unable to replay events", frame_id);
+                self.reply(user_id, connection_id, &reply).await;
+                return;
+            }
+        };
+
+        let truncated = events.len() > batch_size;
+        events.truncate(batch_size);
+        let last_seq = events.last().map_or(after_seq, |event| event.seq);
+
+        for event in events {
+            match serde_json::from_str::<Value>(&event.body) {
+                Ok(body) => {
+                    let frame = protocol::encode_frame(body, Some(event.seq));
+                    self.send_to_connection(user_id, connection_id, Message::Text(frame))
+                        .await;
+                }
+                Err(e) => tracing::warn!("Skipping unreadable stored event {}: {e}", event.seq),
+            }
+        }
+        let complete = ServerEvent::ReplayComplete {
+            last_seq,
+            truncated,
+        };
+        self.reply(user_id, connection_id, &complete).await;
+    }
+
+    /// Periodically delete events that are past the retention window.
+    pub fn spawn_event_purge(&self) {
+        let pool = self.pool.clone();
+        let retention = self.config.event_retention;
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EVENT_PURGE_INTERVAL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Ok(retention) = chrono::Duration::from_std(retention) else {
+                    return;
+                };
+                let cutoff = chrono::Utc::now().naive_utc() - retention;
+                let mut conn = match pool.get().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        tracing::error!("Unable to purge old events: {e}");
+                        continue;
+                    }
+                };
+                match Service::purge_ws_events(&mut conn, cutoff).await {
+                    Ok(0) => {}
+                    Ok(deleted) => tracing::info!("Purged {deleted} events older than {cutoff}"),
+                    Err(e) => tracing::error!("Unable to purge old events: {e}"),
+                }
+            }
+        });
+    }
+
+    /// Hand a broadcast to this instance's matching sockets.
+    pub async fn deliver(&self, broadcast: &Broadcast) {
+        let msg = Message::Text(broadcast.frame.clone());
+        let clients_lock = self.clients.read().await;
+        for user_id in &broadcast.user_ids {
+            let Some(connections) = clients_lock.get(user_id) else {
+                continue;
+            };
+            for (connection_id, connection) in connections.iter() {
+                if broadcast
+                    .topic
+                    .is_some_and(|topic| !connection.topics.contains(&topic))
+                {
+                    continue;
+                }
+                self.enqueue(*user_id, *connection_id, &connection.queue, msg.clone());
+            }
+        }
+    }
+
+    /// Send an event to the sockets of a user subscribed to its topic.
+    pub async fn send_event(&self, user_id: i64, event: &ServerEvent) {
+        self.send_event_to_users(&[user_id], event).await;
+    }
+
+    /// Reply to the one socket a command came from.
+    async fn reply(&self, user_id: i64, connection_id: ConnectionId, event: &ServerEvent) {
+        self.send_to_connection(user_id, connection_id, event.to_message())
+            .await;
+    }
+
+    /// Queue a message on one socket, ignoring its topics.
+    async fn send_to_connection(&self, user_id: i64, connection_id: ConnectionId, msg: Message) {
+        let clients_lock = self.clients.read().await;
+        if let Some(connection) = clients_lock
+            .get(&user_id)
+            .and_then(|connections| connections.get(&connection_id))
+        {
+            self.enqueue(user_id, connection_id, &connection.queue, msg);
+        }
+    }
+
+    /// Resolve the user behind a JWT taken from the `Authorization` header or
+    /// the `token` query parameter, since browsers cannot set headers on the
+    /// upgrade request.
+    async fn authenticate(&self, token: Option<String>) -> Result<i64, StatusCode> {
+        let token = token.ok_or(StatusCode::UNAUTHORIZED)?;
+
+        let token_data = Auth::decode_jwt(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let user = Service::find_session_user(&mut conn, &token_data.claims)
+            .await
+            .map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+        Ok(user.id)
+    }
+
     /// WebSocket handler for incoming HTTP connections.
     pub async fn ws_handler(
         self,
         ws: WebSocketUpgrade,
         user_agent: Option<TypedHeader<UserAgent>>,
         ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    ) -> impl IntoResponse {
+        authorization: Option<TypedHeader<Authorization<Bearer>>>,
+        Query(params): Query<ConnectParams>,
+    ) -> Response {
         let user_agent = user_agent
             .map(|TypedHeader(ua)| ua.to_string())
             .unwrap_or_else(|| "Unknown browser".to_string());
 
-        println!("`{user_agent}` at {addr} connected.");
-        ws.on_upgrade(move |socket| self.clone().handle_socket(socket, addr))
+        let token = authorization
+            .map(|TypedHeader(auth)| auth.token().to_string())
+            .or(params.token);
+        let user_id = match self.authenticate(token).await {
+            Ok(user_id) => user_id,
+            Err(status) => return status.into_response(),
+        };
+
+        tracing::info!("`{user_agent}` at {addr} connected as user {user_id}.");
+        ws.on_upgrade(move |socket| {
+            self.clone()
+                .handle_socket(socket, addr, user_id, params.last_seen_seq)
+        })
     }
 
     /// Handle an upgraded WebSocket connection.
-    async fn handle_socket(self, socket: WebSocket, addr: SocketAddr) {
+    async fn handle_socket(
+        self,
+        socket: WebSocket,
+        addr: SocketAddr,
+        user_id: i64,
+        last_seen_seq: Option<i64>,
+    ) {
         let (sender, mut receiver) = socket.split();
+        let connection_id = Uuid::new_v4();
+        let queue = Arc::new(OutboundQueue::new(
+            self.config.queue_capacity,
+            self.config.overflow_policy,
+        ));
+
+        self.add_client(user_id, connection_id, queue.clone()).await;
+        let mut writer = tokio::spawn(Self::write_loop(
+            sender,
+            queue.clone(),
+            self.metrics.clone(),
+        ));
 
-        self.add_client(addr, sender).await;
+        // Registered before replaying, so an event sent meanwhile is not lost;
+        // it may arrive twice, clients drop any `seq` they have already seen.
+        if let Some(after_seq) = last_seen_seq {
+            self.replay(user_id, connection_id, after_seq, None).await;
+        }
+
+        let mut heartbeat = tokio::time::interval(self.config.ping_interval);
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately; skip it so the first ping
+        // goes out one interval after connecting.
+        heartbeat.tick().await;
+        let mut missed_pongs = 0;
+        let mut close_frame = None;
+        let mut writer_done = false;
 
-        // Handle incoming messages
-        while let Some(Ok(msg)) = receiver.next().await {
-            if Self::process_message(&msg, addr).is_break() {
-                break;
+        // Handle incoming messages until the client leaves, stops answering
+        // pings or the writer gives up
+        loop {
+            tokio::select! {
+                msg = receiver.next() => {
+                    let Some(Ok(msg)) = msg else { break };
+                    if matches!(msg, Message::Pong(_)) {
+                        missed_pongs = 0;
+                    }
+                    if self
+                        .process_message(&msg, addr, user_id, connection_id)
+                        .await
+                        .is_break()
+                    {
+                        break;
+                    }
+                }
+                _ = heartbeat.tick() => {
+                    if missed_pongs >= self.config.max_missed_pongs {
+                        tracing::info!("Client {addr} missed {missed_pongs} pongs, closing");
+                        close_frame = Some(CloseFrame {
+                            code: CLOSE_HEARTBEAT_TIMEOUT,
+                            reason: "heartbeat timeout".into(),
+                        });
+                        break;
+                    }
+                    missed_pongs += 1;
+                    queue.ping();
+                }
+                _ = &mut writer => {
+                    writer_done = true;
+                    break;
+                }
             }
         }
 
         // Remove the client when disconnected
-        self.remove_client(addr).await;
+        self.remove_client(user_id, connection_id).await;
+        queue.close(close_frame);
+        if !writer_done
+            && tokio::time::timeout(WRITER_GRACE_PERIOD, &mut writer)
+                .await
+                .is_err()
+        {
+            writer.abort();
+        }
         println!("Client {addr} disconnected");
     }
 
+    /// Drain a connection's queue into its socket until the queue is closed or
+    /// the socket stops accepting writes.
+    async fn write_loop(
+        mut sender: SplitSink<WebSocket, Message>,
+        queue: Arc<OutboundQueue>,
+        metrics: Arc<WsMetrics>,
+    ) {
+        loop {
+            match queue.pop().await {
+                Outbound::Send(msg) => {
+                    if let Err(e) = sender.send(msg).await {
+                        tracing::debug!("Error sending message: {e}");
+                        return;
+                    }
+                    metrics.record_sent();
+                }
+                Outbound::Ping => {
+                    if let Err(e) = sender.send(Message::Ping(Vec::new())).await {
+                        tracing::debug!("Error sending ping: {e}");
+                        return;
+                    }
+                }
+                Outbound::Close(frame) => {
+                    if let Some(frame) = frame {
+                        let _ = sender.send(Message::Close(Some(frame))).await;
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
     /// Process an incoming WebSocket message.
-    fn process_message(msg: &Message, addr: SocketAddr) -> ControlFlow<(), ()> {
+    async fn process_message(
+        &self,
+        msg: &Message,
+        addr: SocketAddr,
+        user_id: i64,
+        connection_id: ConnectionId,
+    ) -> ControlFlow<(), ()> {
         match msg {
-            Message::Text(t) => println!(">>> {addr} sent text: {t:?}"),
-            Message::Binary(d) => println!(">>> {addr} sent binary ({} bytes)", d.len()),
+            Message::Text(t) => {
+                let reply = match protocol::parse_command(t) {
+                    Ok(envelope) => {
+                        self.handle_command(envelope.body, envelope.id, user_id, connection_id)
+                            .await
+                    }
+                    Err(err) => Some(err.into_event()),
+                };
+                if let Some(reply) = reply {
+                    self.reply(user_id, connection_id, &reply).await;
+                }
+            }
+            Message::Binary(d) => {
+                tracing::debug!("{addr} sent binary ({} bytes)", d.len());
+                let reply = ServerEvent::error(
+                    ErrorCode::UnsupportedFrame,
+                    "binary frames are not supported",
+                    None,
+                );
+                self.reply(user_id, connection_id, &reply).await;
+            }
             Message::Close(c) => {
                 if let Some(cf) = c {
                     println!(
@@ -104,9 +585,78 @@ impl WebSocketManager {
                 }
                 return ControlFlow::Break(());
             }
-            Message::Ping(v) => println!(">>> {addr} sent ping: {v:?}"),
-            Message::Pong(v) => println!(">>> {addr} sent pong: {v:?}"),
+            Message::Ping(v) => tracing::debug!("{addr} sent ping: {v:?}"),
+            Message::Pong(v) => tracing::debug!("{addr} sent pong: {v:?}"),
         }
         ControlFlow::Continue(())
     }
-}
\ No newline at end of file
+
+    /// Apply a client command, returning the error frame to answer with if it fails.
+    async fn handle_command(
+        &self,
+        command: ClientCommand,
+        frame_id: String,
+        user_id: i64,
+        connection_id: ConnectionId,
+    ) -> Option<ServerEvent> {
+        match command {
+            ClientCommand::Subscribe { topics } => {
+                let mut clients_lock = self.clients.write().await;
+                if let Some(connection) = clients_lock
+                    .get_mut(&user_id)
+                    .and_then(|connections| connections.get_mut(&connection_id))
+                {
+                    connection.topics = topics.into_iter().collect();
+                }
+                None
+            }
+            ClientCommand::Ack { event_id } => {
+                tracing::debug!("{connection_id} of user {user_id} acknowledged {event_id}");
+                None
+            }
+            ClientCommand::Replay { after_seq } => {
+                self.replay(user_id, connection_id, after_seq, Some(frame_id))
+                    .await;
+                None
+            }
+            ClientCommand::LocationUpdate {
+                latitude,
+                longitude,
+            } => {
+                if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                    return Some(ServerEvent::error(
+                        ErrorCode::InvalidPayload,
+                        "latitude or longitude out of range",
+                        Some(frame_id),
+                    ));
+                }
+
+                let mut conn = match self.pool.get().await {
+                    Ok(conn) => conn,
+                    Err(_) => {
+                        return Some(ServerEvent::error(
+                            ErrorCode::Internal,
+                            "unable to connect to database",
+                            Some(frame_id),
+                        ))
+                    }
+                };
+                match Service::update_employee_location(&mut conn, user_id, latitude, longitude)
+                    .await
+                {
+                    Ok(_) => None,
+                    Err(diesel::result::Error::NotFound) => Some(ServerEvent::error(
+                        ErrorCode::NotAnEmployee,
+                        "only employees can report their location",
+                        Some(frame_id),
+                    )),
+                    Err(_) => Some(ServerEvent::error(
+                        ErrorCode::Internal,
+                        "unable to update location",
+                        Some(frame_id),
+                    )),
+                }
+            }
+        }
+    }
+}